@@ -20,7 +20,7 @@ pub fn _calc_interest(debt: Amount, interest_rate: u32, start_time: u64) -> Amou
 
     let current_time = time();
 
-    while start_time + one_hour < current_time {
+    while _starting_time + one_hour < current_time {
         fee += ((interest_rate as u128) * debt) / u128::from(100 * _ONE_PERCENT);
 
         _starting_time += one_hour;
@@ -66,3 +66,64 @@ pub fn _percentage128(x: u64, value: Amount) -> Amount {
 pub fn _percentage64(x: u64, value: u64) -> u64 {
     return (x * value) / (100 * _ONE_PERCENT);
 }
+
+/// Calculate Liquidation Fee
+///
+/// Calculates the liquidation fee on the part of a position unwound by a liquidation
+///
+/// Params
+///  - Liquidation Fee :The liquidation fee in percentage
+///  - Position Value :The collateral and debt value of the position before the liquidation
+///  - Init Volume Share :The volume share of the position before the liquidation
+///  - Volume Share :The volume share left in the position after the liquidation
+///  - Collateral Remaining :The collateral left from the unwound part after repaying the debt
+///
+/// Returns
+///  - Liquidation Fee Value :The fee on the unwound share of the position value ,capped at the collateral remaining
+pub fn _calc_liquidation_fee(
+    liquidation_fee: u64,
+    position_value: Amount,
+    init_volume_share: Amount,
+    volume_share: Amount,
+    collateral_remaining: Amount,
+) -> Amount {
+    if init_volume_share == 0 {
+        return 0;
+    }
+
+    let unwound_value =
+        (position_value * init_volume_share.saturating_sub(volume_share)) / init_volume_share;
+
+    _percentage128(liquidation_fee, unwound_value).min(collateral_remaining)
+}
+
+#[cfg(test)]
+mod unit_test {
+
+    use super::*;
+
+    #[test]
+    fn test_liquidation_fee_on_full_liquidation() {
+        let liquidation_fee = _calc_liquidation_fee(_ONE_PERCENT, 1_000_000, 500, 0, 50_000);
+
+        assert_eq!(liquidation_fee, 10_000);
+
+        // the fee can not exceed the collateral remaining
+        let liquidation_fee = _calc_liquidation_fee(_ONE_PERCENT, 1_000_000, 500, 0, 4_000);
+
+        assert_eq!(liquidation_fee, 4_000);
+    }
+
+    #[test]
+    fn test_liquidation_fee_on_partial_liquidation() {
+        // a quarter of the position is unwound
+        let liquidation_fee = _calc_liquidation_fee(_ONE_PERCENT, 1_000_000, 400, 300, 50_000);
+
+        assert_eq!(liquidation_fee, 2_500);
+
+        // nothing is unwound
+        let liquidation_fee = _calc_liquidation_fee(_ONE_PERCENT, 1_000_000, 400, 400, 50_000);
+
+        assert_eq!(liquidation_fee, 0);
+    }
+}
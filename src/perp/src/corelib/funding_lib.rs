@@ -0,0 +1,171 @@
+use super::constants::_ONE_PERCENT;
+
+/// the seconds in a funding rate interval ,funding indexes grow by the hourly funding rate multiplied by seconds
+const _ONE_HOUR_IN_SECONDS: i128 = 3_600;
+
+/// Calculate Premium
+///
+/// Calculates the premium of the perp price over the spot price in percentage
+///
+/// Note:The premium is negative if the perp price is below the spot price
+pub fn _calc_premium(perp_price: u128, spot_price: u128) -> i64 {
+    let premium = ((perp_price as i128 - spot_price as i128) * 100 * _ONE_PERCENT as i128)
+        / spot_price as i128;
+    premium as i64
+}
+
+/// Calculate Funding Rate
+///
+/// Calculates the funding rate for a funding interval from the premium
+///
+/// Params
+///  - Premium :The premium of the perp price over the spot price in percentage (see Calculate Premium)
+///  - Max Funding Rate :The max absolute funding rate for an interval in percentage
+///  - Dead Band :The absolute premium in percentage below which the premium is ignored
+///  - Interest Rate :The baseline interest rate for an interval in percentage ,positive if longs pay shorts
+///
+/// Returns
+///  - Funding Rate :The funding rate in percentage ,positive if longs pay shorts and negative otherwise
+///
+/// Note
+///  - The dead band is taken off the premium so the premium grows from zero once it leaves the band
+///  - The resulting funding rate is clamped between the negative and positive max funding rate
+pub fn _calc_funding_rate(
+    premium: i64,
+    max_funding_rate: u64,
+    dead_band: u64,
+    interest_rate: i64,
+) -> i64 {
+    let dead_band = dead_band as i64;
+
+    let dampened_premium = if premium.abs() <= dead_band {
+        0
+    } else {
+        premium - premium.signum() * dead_band
+    };
+
+    let max_funding_rate = max_funding_rate as i64;
+
+    (dampened_premium + interest_rate).clamp(-max_funding_rate, max_funding_rate)
+}
+
+/// Apply Funding
+///
+/// Deducts the funding paid from a value or adds the funding received to it
+///
+/// Params
+///  - Value :The realised value of a position
+///  - Funding Index Delta :The change in the funding index of the position's side since the position was opened (see FundingRateTracker)
+///
+/// Returns
+///  - Value :The value after the funding ,zero if the funding paid exceeds the value
+///
+/// Note
+///  - The funding received is not bounded by the funding paid ,so the funding a position can not pay is borne by the vault like any other trader profit
+///  - The liquidation tick accounts for the funding ,so this shortfall only arises for positions not liquidated in time
+pub fn _apply_funding(value: u128, funding_index_delta: i128) -> u128 {
+    let funding_value =
+        (value as i128 * funding_index_delta) / (_ONE_HOUR_IN_SECONDS * 100 * _ONE_PERCENT as i128);
+
+    if funding_value > 0 {
+        return value.saturating_sub(funding_value as u128);
+    }
+    value + funding_value.unsigned_abs()
+}
+
+#[cfg(test)]
+mod unit_test {
+
+    use super::*;
+    use crate::corelib::constants::_ONE_BASIS_POINT;
+
+    #[test]
+    fn test_calc_premium() {
+        assert_eq!(_calc_premium(102, 100), 2 * _ONE_PERCENT as i64);
+
+        assert_eq!(_calc_premium(98, 100), -2 * (_ONE_PERCENT as i64));
+
+        assert_eq!(_calc_premium(100, 100), 0);
+    }
+
+    #[test]
+    fn test_funding_rate_within_dead_band() {
+        let dead_band = 5 * _ONE_BASIS_POINT;
+
+        let funding_rate =
+            _calc_funding_rate(4 * _ONE_BASIS_POINT as i64, _ONE_PERCENT, dead_band, 0);
+
+        assert_eq!(funding_rate, 0);
+
+        // only the interest rate is paid within the dead band
+        let funding_rate = _calc_funding_rate(
+            -4 * (_ONE_BASIS_POINT as i64),
+            _ONE_PERCENT,
+            dead_band,
+            _ONE_BASIS_POINT as i64,
+        );
+
+        assert_eq!(funding_rate, _ONE_BASIS_POINT as i64);
+    }
+
+    #[test]
+    fn test_funding_rate_outside_dead_band() {
+        let dead_band = 5 * _ONE_BASIS_POINT;
+
+        let funding_rate =
+            _calc_funding_rate(15 * _ONE_BASIS_POINT as i64, _ONE_PERCENT, dead_band, 0);
+
+        assert_eq!(funding_rate, 10 * _ONE_BASIS_POINT as i64);
+
+        let funding_rate = _calc_funding_rate(
+            -15 * (_ONE_BASIS_POINT as i64),
+            _ONE_PERCENT,
+            dead_band,
+            _ONE_BASIS_POINT as i64,
+        );
+
+        assert_eq!(funding_rate, -9 * (_ONE_BASIS_POINT as i64));
+    }
+
+    #[test]
+    fn test_funding_rate_clamped() {
+        let max_funding_rate = _ONE_PERCENT;
+
+        // a 20% premium only moves the max funding rate
+        let funding_rate = _calc_funding_rate(20 * _ONE_PERCENT as i64, max_funding_rate, 0, 0);
+
+        assert_eq!(funding_rate, max_funding_rate as i64);
+
+        let funding_rate = _calc_funding_rate(-20 * (_ONE_PERCENT as i64), max_funding_rate, 0, 0);
+
+        assert_eq!(funding_rate, -(max_funding_rate as i64));
+    }
+
+    #[test]
+    fn test_apply_funding() {
+        let value = 1_000_000;
+
+        // 1% hourly funding for one hour
+        let funding_index_delta = _ONE_PERCENT as i128 * _ONE_HOUR_IN_SECONDS;
+
+        assert_eq!(_apply_funding(value, funding_index_delta), 990_000);
+
+        assert_eq!(_apply_funding(value, -funding_index_delta), 1_010_000);
+
+        assert_eq!(_apply_funding(value, 0), value);
+    }
+
+    #[test]
+    fn test_apply_funding_shortfall() {
+        let value = 1_000_000;
+
+        // 150% of the value is owed
+        let funding_index_delta = 150 * _ONE_PERCENT as i128 * _ONE_HOUR_IN_SECONDS;
+
+        // the payer only pays up to it's value
+        assert_eq!(_apply_funding(value, funding_index_delta), 0);
+
+        // the receiver is paid in full ,the unpaid 500_000 is borne by the vault
+        assert_eq!(_apply_funding(value, -funding_index_delta), 2_500_000);
+    }
+}
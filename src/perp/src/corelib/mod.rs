@@ -9,6 +9,8 @@ pub mod calc_lib;
 
 pub mod constants;
 
+pub mod funding_lib;
+
 pub mod price_lib;
 
 pub mod tick_lib;
@@ -50,7 +52,7 @@ mod unit_test {
 
         let amount_to_swap = 1_000_000_000;
         // swap_at_tick
-        let (amount_out, _amount_remaining, _resulting_tick, _crossed_ticks) = _swap(
+        let (amount_out, _amount_remaining, _resulting_tick, _crossed_ticks, _swap_fees) = _swap(
             amount_to_swap,
             false,
             230 * constants::_ONE_PERCENT,
@@ -84,7 +86,8 @@ mod unit_test {
         let amount_to_swap = 1_000_000_000;
         // swap_at_tick
         {
-            let (amount_out, _, _, _) = _swap(amount_to_swap, true, reference_tick, reference_tick);
+            let (amount_out, _, _, _, _) =
+                _swap(amount_to_swap, true, reference_tick, reference_tick);
 
             // remove order
 
@@ -115,7 +118,7 @@ mod unit_test {
         }
         let amount_to_swap = 1_000_000_000;
         //swap
-        let (amount_out, _, _, _) = _swap(amount_to_swap, true, reference_tick, reference_tick);
+        let (amount_out, _, _, _, _) = _swap(amount_to_swap, true, reference_tick, reference_tick);
 
         //close order2
         {
@@ -152,6 +155,7 @@ mod unit_test {
                     order,
                     integrals_bitmaps: multipliers_bitmaps,
                     ticks_details,
+                    maker_fee: 0,
                 };
                 open_order_params.open_order();
             })
@@ -180,7 +184,7 @@ mod unit_test {
         buy: bool,
         init_tick: Tick,
         stopping_tick: Tick,
-    ) -> (Amount, Amount, Tick, Vec<Tick>) {
+    ) -> (Amount, Amount, Tick, Vec<Tick>, swap_lib::SwapFees) {
         TICKS_DETAILS.with(|ref_ticks_details| {
             let ticks_details = &mut ref_ticks_details.borrow_mut();
             INTEGRALS_BITMAPS.with(|ref_multiplier_bitmaps| {
@@ -192,6 +196,8 @@ mod unit_test {
                     order_size,
                     integrals_bitmaps: multipliers_bitmaps,
                     ticks_details,
+                    taker_fee: 0,
+                    dry_run: false,
                 };
                 swap_params._swap()
             })
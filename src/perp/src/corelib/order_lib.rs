@@ -39,6 +39,10 @@ pub struct OpenOrderParams<'a> {
     ///
     /// A mutable refrence to any generic type that implements the Order trait  determing which order type is being opened
     pub order: &'a mut LimitOrder,
+    /// Maker Fee
+    ///
+    /// the maker fee recorded on the reference tick if the order creates it (see TickDetails)
+    pub maker_fee: i64,
 }
 
 impl<'a> OpenOrderParams<'a> {
@@ -59,7 +63,7 @@ impl<'a> OpenOrderParams<'a> {
 
                 self.integrals_bitmaps.insert(integral, flipped_bitmap);
 
-                TickDetails::new()
+                TickDetails::new(self.maker_fee)
             }
         };
 
@@ -160,6 +164,10 @@ pub struct LimitOrder {
 
     // corresponds to the last timesamp when that tick was created
     pub init_tick_timestamp: Time,
+    /// Maker Fee
+    ///
+    /// the maker fee of the reference tick when the order was placed ,charged or paid as a rebate when the order is filled
+    pub maker_fee: i64,
 }
 
 impl LimitOrder {
@@ -171,6 +179,7 @@ impl LimitOrder {
             init_lower_bound: 0,
             init_removed_liquidity: 0,
             init_tick_timestamp: 0,
+            maker_fee: 0,
         };
     }
 }
@@ -190,6 +199,8 @@ impl Order for LimitOrder {
 
         self.init_tick_timestamp = tick_details.created_timestamp;
 
+        self.maker_fee = tick_details.maker_fee;
+
         self.init_lower_bound = init_liq_bounds.upper_bound;
 
         self.init_removed_liquidity = init_liq_bounds.lifetime_removed_liquidity;
@@ -1,4 +1,5 @@
 use super::bitmap_lib::{_flip_bit, _next_initialised_tick};
+use super::calc_lib::_percentage128;
 use super::price_lib::_equivalent;
 use super::tick_lib::*;
 use crate::types::TickDetails;
@@ -71,6 +72,77 @@ pub fn _get_best_offer<'a>(
     }
 }
 
+/// Get Offers
+///
+/// Gets the initialised ticks with liquidity for either selling or buying ,walking from the start tick towards the stopping tick
+///
+/// Params
+///  - Buy :true to get the offers a buy order is filled against (asks) ,false for the offers a sell order is filled against (bids)
+///  - Start Tick :The tick to start walking from
+///  - Stopping Tick :The tick to stop walking at
+///  - Levels :The max number of ticks to return
+///
+/// Returns
+///  - Offers :The ticks and the liquidity within the tick's boundary ,ordered from the best offer
+///  - Next Tick :The next tick with liquidity after the returned ticks ,none if there are no more offers before the stopping tick
+pub fn _get_offers(
+    buy: bool,
+    start_tick: Tick,
+    stopping_tick: Tick,
+    levels: usize,
+    integrals_bitmaps: &MB,
+    ticks_details: &TD,
+) -> (Vec<(Tick, Amount)>, Option<Tick>) {
+    let mut offers = Vec::new();
+    let mut loop_current_tick = start_tick;
+    while !(_exceeded_stopping_tick(loop_current_tick, stopping_tick, buy)) {
+        let (integral, bit_position) = _int_and_dec(loop_current_tick);
+        let bitmap = match integrals_bitmaps.get(&integral) {
+            Some(res) => res,
+            None => {
+                loop_current_tick = _next_default_tick(integral, buy);
+                continue;
+            }
+        };
+
+        if let Some(tick_details) = ticks_details.get(&loop_current_tick) {
+            let liquidity_boundary = if buy {
+                tick_details.liq_bounds_token0
+            } else {
+                tick_details.liq_bounds_token1
+            };
+
+            let liquidity = liquidity_boundary._liquidity_within();
+
+            if liquidity > 0 {
+                if offers.len() == levels {
+                    return (offers, Some(loop_current_tick));
+                }
+                offers.push((loop_current_tick, liquidity));
+            }
+        }
+
+        loop_current_tick = _next_initialised_tick(bitmap, integral, bit_position, buy);
+    }
+
+    (offers, None)
+}
+
+/// Swap Fees
+///
+/// The trading fees charged on the value traded within a swap
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct SwapFees {
+    /// Taker Fee
+    ///
+    /// the fee charged on the swap initiator
+    pub taker_fee: Amount,
+    /// Maker Fee
+    ///
+    /// the fee charged on the limit orders filled by the swap ,negative if the makers are rebated
+    pub maker_fee: i128,
+}
+
 struct SwapTickConstants {
     tick: Tick,
     order_size: Amount,
@@ -108,6 +180,14 @@ pub struct SwapParams<'a> {
     ///
     /// HashMasp  of ticks to their  respective tick_details
     pub ticks_details: &'a mut TD,
+    /// Taker Fee
+    ///
+    /// the fee charged on the value traded by the swap initiator ,given in percentage i.e 1% is 100_000
+    pub taker_fee: u64,
+    /// Dry Run
+    ///
+    /// if true the swap is only simulated and the ticks details and integrals bitmaps are left unchanged
+    pub dry_run: bool,
 }
 
 impl<'a> SwapParams<'a> {
@@ -122,9 +202,16 @@ impl<'a> SwapParams<'a> {
     /// or tick before stopping tick was exceeded
     ///
     ///  - Crossed Ticks :The Total ticks that were crossed
-    pub fn _swap(&mut self) -> (Amount, Amount, Tick, Vec<Tick>) {
+    ///  - Swap Fees :The taker and maker fees charged on the value traded (see SwapFees)
+    ///
+    /// Note
+    ///  - Fees are charged on the quote value traded ,the amount in for a buy and the amount out for a sell
+    ///  - For a dry run the amounts ,resulting tick and crossed ticks are computed without mutating the ticks details or the integrals bitmaps
+    pub fn _swap(&mut self) -> (Amount, Amount, Tick, Vec<Tick>, SwapFees) {
         let mut amount_out = 0;
 
+        let mut swap_fees = SwapFees::default();
+
         let mut amount_remaining = self.order_size;
 
         let mut resulting_tick = self.init_tick;
@@ -163,12 +250,16 @@ impl<'a> SwapParams<'a> {
                 tick: loop_current_tick,
             };
 
-            let (value_out, boundary_closed);
+            let tick_order_size = amount_remaining;
+
+            let (value_out, boundary_closed, maker_fee);
 
             if self.buy {
-                (value_out, amount_remaining, boundary_closed) = self._buy_at_tick(tick_params);
+                (value_out, amount_remaining, boundary_closed, maker_fee) =
+                    self._buy_at_tick(tick_params);
             } else {
-                (value_out, amount_remaining, boundary_closed) = self._sell_at_tick(tick_params);
+                (value_out, amount_remaining, boundary_closed, maker_fee) =
+                    self._sell_at_tick(tick_params);
             }
 
             // if static liquidity was exhausted at that tick and val out is greater to zero
@@ -176,14 +267,26 @@ impl<'a> SwapParams<'a> {
             if value_out > 0 {
                 amount_out += value_out;
 
+                let traded_value = if self.buy {
+                    tick_order_size - amount_remaining
+                } else {
+                    value_out
+                };
+
+                self._charge_fees(&mut swap_fees, traded_value, maker_fee);
+
                 resulting_tick = loop_current_tick;
 
                 // if static liquidity was exhausted at that tick and val out is not equal to zero
 
                 if boundary_closed {
-                    self.ticks_details.remove(&loop_current_tick);
                     //add ticks to list of crossed ticks
                     crossed_ticks.push(loop_current_tick);
+                }
+
+                // a dry run leaves the cleared tick and its bitmap unchanged
+                if boundary_closed && !self.dry_run {
+                    self.ticks_details.remove(&loop_current_tick);
 
                     let flipped_bitmap = _flip_bit(bitmap, bit_position);
 
@@ -211,7 +314,34 @@ impl<'a> SwapParams<'a> {
         }
         // if swap could not happen ,current tick remains unchanged and can only be changed manually
 
-        return (amount_out, amount_remaining, resulting_tick, crossed_ticks);
+        (
+            amount_out,
+            amount_remaining,
+            resulting_tick,
+            crossed_ticks,
+            swap_fees,
+        )
+    }
+
+    /// Charge Fees Function
+    ///
+    /// Adds the taker and maker fees on the value traded at a tick to the swap fees
+    ///
+    /// Note
+    ///  - The maker fee is the fee recorded on the tick (see TickDetails) ,a negative fee is a rebate
+    ///  - The taker fee at a tick is at least the tick's rebate ,so the rebate is always backed if the taker fee was lowered after the tick was created
+    fn _charge_fees(&self, swap_fees: &mut SwapFees, traded_value: Amount, tick_maker_fee: i64) {
+        let maker_fee = _percentage128(tick_maker_fee.unsigned_abs(), traded_value);
+
+        let taker_fee = _percentage128(self.taker_fee, traded_value);
+
+        if tick_maker_fee < 0 {
+            swap_fees.taker_fee += taker_fee.max(maker_fee);
+            swap_fees.maker_fee -= maker_fee as i128;
+        } else {
+            swap_fees.taker_fee += taker_fee;
+            swap_fees.maker_fee += maker_fee as i128;
+        }
     }
 
     /// buy at tick function
@@ -224,7 +354,8 @@ impl<'a> SwapParams<'a> {
     ///  this is  zero if the swap is completedly fully at tick
     /// - Cleared : true if all liquidity at tick  was cleared
     /// - Boundary Closed : true if all static liquidity at tick (see TickDetails and LiquidityBoundary) is cleared
-    fn _buy_at_tick(&mut self, params: SwapTickConstants) -> (Amount, Amount, bool) {
+    /// - Maker Fee :The maker fee recorded on the tick
+    fn _buy_at_tick(&mut self, params: SwapTickConstants) -> (Amount, Amount, bool, i64) {
         let mut amount_out = 0;
 
         let mut amount_remaining = params.order_size;
@@ -238,7 +369,7 @@ impl<'a> SwapParams<'a> {
 
         let mut tick_details = match self.ticks_details.get(&params.tick) {
             Some(res) => res,
-            None => return (amount_out, amount_remaining, false),
+            None => return (amount_out, amount_remaining, false, 0),
         };
 
         let init_tick_liq = tick_details.liq_bounds_token0._liquidity_within();
@@ -246,14 +377,14 @@ impl<'a> SwapParams<'a> {
         //value of all_liquidity in token1
         let init_liq_equivalent = equivalent(init_tick_liq, false);
 
-        if init_liq_equivalent <= self.order_size {
+        if init_liq_equivalent <= params.order_size {
             // all liquidity has been exhausted
             amount_out = init_tick_liq;
 
             amount_remaining -= init_liq_equivalent;
         } else {
             //liquidity remains
-            amount_out = equivalent(self.order_size, true);
+            amount_out = equivalent(params.order_size, true);
 
             amount_remaining = 0;
         }
@@ -264,9 +395,16 @@ impl<'a> SwapParams<'a> {
         boundary_closed = tick_details.liq_bounds_token0._liquidity_within() == 0
             && tick_details.liq_bounds_token1._liquidity_within() == 0;
 
-        self.ticks_details.insert(params.tick, tick_details);
+        if !self.dry_run {
+            self.ticks_details.insert(params.tick, tick_details);
+        }
 
-        return (amount_out, amount_remaining, boundary_closed);
+        (
+            amount_out,
+            amount_remaining,
+            boundary_closed,
+            tick_details.maker_fee,
+        )
     }
 
     /// Sell at tick function
@@ -279,8 +417,8 @@ impl<'a> SwapParams<'a> {
     ///  this is  zero if the swap is completedly fully at tick
     /// - Cleared : true if all liquidity at tick  was cleared
     /// - Boundary Closed : true if all static liquidity at tick (see TickDetails and LiquidityBoundary) is cleared
-
-    fn _sell_at_tick(&mut self, params: SwapTickConstants) -> (Amount, Amount, bool) {
+    /// - Maker Fee :The maker fee recorded on the tick
+    fn _sell_at_tick(&mut self, params: SwapTickConstants) -> (Amount, Amount, bool, i64) {
         let mut amount_out = 0;
 
         let mut amount_remaining = params.order_size;
@@ -295,20 +433,20 @@ impl<'a> SwapParams<'a> {
         // tick details
         let mut tick_details = match self.ticks_details.get(&params.tick) {
             Some(res) => res,
-            None => return (amount_out, amount_remaining, false),
+            None => return (amount_out, amount_remaining, false, 0),
         };
 
         let init_tick_liq = tick_details.liq_bounds_token1._liquidity_within();
 
         let init_liq_equivalent = equivalent(init_tick_liq, true);
 
-        if init_liq_equivalent <= self.order_size {
+        if init_liq_equivalent <= params.order_size {
             amount_out = init_tick_liq;
 
             amount_remaining -= init_liq_equivalent;
         } else {
             //liquidity remains
-            amount_out = equivalent(self.order_size, false);
+            amount_out = equivalent(params.order_size, false);
 
             amount_remaining = 0;
         }
@@ -318,9 +456,16 @@ impl<'a> SwapParams<'a> {
         boundary_closed = tick_details.liq_bounds_token1._liquidity_within() == 0
             && tick_details.liq_bounds_token0._liquidity_within() == 0;
 
-        self.ticks_details.insert(params.tick, tick_details);
+        if !self.dry_run {
+            self.ticks_details.insert(params.tick, tick_details);
+        }
 
-        return (amount_out, amount_remaining, boundary_closed);
+        (
+            amount_out,
+            amount_remaining,
+            boundary_closed,
+            tick_details.maker_fee,
+        )
     }
 }
 
@@ -33,6 +33,50 @@ pub fn _tick_zero(integral: u64) -> u64 {
     integral * _ONE_PERCENT
 }
 
+/// Liquidation Tick
+///
+/// Calculates the tick at which a market position becomes liquidatable ,that is when the position's remaining collateral
+/// is at or below the maintenance margin of the position's current value
+///
+/// Params
+///  - Long :The position direction
+///  - Entry Tick :The entry tick of the position
+///  - Realised Value :The current value of the position volume share
+///  - Net Debt :The position debt with accrued interest
+///  - Maintenance Margin :The maintenance margin in percentage
+///
+/// Returns
+///  - Liquidation Tick :A long position is liquidatable at or below this tick and a short position at or above it ,none if position has no debt
+pub fn _liquidation_tick(
+    long: bool,
+    entry_tick: u64,
+    realised_value: u128,
+    net_debt: u128,
+    maintenance_margin: u64,
+) -> Option<u64> {
+    if net_debt == 0 || realised_value == 0 {
+        return None;
+    }
+
+    let one_hundred_percent = 100 * _ONE_PERCENT as u128;
+
+    let margin_remainder = one_hundred_percent - maintenance_margin as u128;
+
+    if long {
+        // current value is realised value * tick / entry tick
+        let tick = (net_debt * entry_tick as u128 * one_hundred_percent)
+            / (realised_value * margin_remainder);
+
+        Some(tick as u64)
+    } else {
+        // current value is realised value * entry tick / tick
+        let numerator = realised_value * entry_tick as u128 * margin_remainder;
+        let denominator = net_debt * one_hundred_percent;
+
+        Some(numerator.div_ceil(denominator) as u64)
+    }
+}
+
 /// Mul and Bit
 ///
 /// This function is used to calculate the integral and decimal pert of a tick
@@ -66,6 +110,37 @@ pub fn _tick_to_price(tick: u64) -> u128 {
     return _percentage128(tick, _BASE_PRICE);
 }
 
+/// Price to Tick
+///
+/// Calculates the tick for a particular price ,the reverse of tick to price
+///
+/// Note:The resulting tick is not rounded to a basis point and is only used as a reference price
+pub fn _price_to_tick(price: u128) -> u64 {
+    ((price * (100 * _ONE_PERCENT as u128)) / _BASE_PRICE) as u64
+}
+
+/// Mark Tick
+///
+/// Calculates the mark tick utilised for valuing positions from the spot ,perp time weighted average and mid ticks
+///
+/// Params
+///  - Spot Tick :The tick of the oracle spot price ,none if the spot price is stale
+///  - Twap Tick :The time weighted average tick of the perp
+///  - Mid Tick :The tick midway between the best bid and the best ask
+///
+/// Returns
+///  - Mark Tick :The median of the three ticks or the average of the twap and mid ticks if the spot price is stale
+pub fn _mark_tick(spot_tick: Option<u64>, twap_tick: u64, mid_tick: u64) -> u64 {
+    match spot_tick {
+        Some(spot_tick) => {
+            let mut ticks = [spot_tick, twap_tick, mid_tick];
+            ticks.sort_unstable();
+            ticks[1]
+        }
+        None => (twap_tick + mid_tick) / 2,
+    }
+}
+
 #[cfg(test)]
 
 mod unit_test {
@@ -93,4 +168,58 @@ mod unit_test {
         assert_eq!(mul2, 1992);
         assert_eq!(bit2, 0);
     }
+
+    #[test]
+    fn test_liquidation_tick() {
+        let entry_tick = 200 * _ONE_PERCENT;
+
+        // a 5x long with a 5% maintenance margin
+        let tick = _liquidation_tick(true, entry_tick, 5_000_000, 4_000_000, 5 * _ONE_PERCENT);
+
+        // 5_000_000 * (tick / entry_tick) * 95% = 4_000_000
+        assert_eq!(tick, Some(16842105));
+
+        let tick = _liquidation_tick(false, entry_tick, 5_000_000, 4_000_000, 5 * _ONE_PERCENT);
+
+        // 5_000_000 * (entry_tick / tick) * 95% = 4_000_000
+        assert_eq!(tick, Some(23750000));
+
+        assert_eq!(_liquidation_tick(true, entry_tick, 5_000_000, 0, 0), None);
+    }
+
+    #[test]
+    fn test_price_to_tick() {
+        let tick = 19_920_000;
+
+        let price = _tick_to_price(tick);
+
+        assert_eq!(price, 1_992_000_000);
+        assert_eq!(_price_to_tick(price), tick);
+    }
+
+    #[test]
+    fn test_mark_tick() {
+        let spot_tick = 200 * _ONE_PERCENT;
+
+        // a single trade moving the mid tick does not move the mark tick past the spot tick
+        assert_eq!(
+            _mark_tick(Some(spot_tick), 201 * _ONE_PERCENT, 150 * _ONE_PERCENT),
+            spot_tick
+        );
+
+        assert_eq!(
+            _mark_tick(Some(spot_tick), 199 * _ONE_PERCENT, 202 * _ONE_PERCENT),
+            spot_tick
+        );
+
+        assert_eq!(
+            _mark_tick(Some(spot_tick), 205 * _ONE_PERCENT, 210 * _ONE_PERCENT),
+            205 * _ONE_PERCENT
+        );
+
+        assert_eq!(
+            _mark_tick(None, 200 * _ONE_PERCENT, 210 * _ONE_PERCENT),
+            205 * _ONE_PERCENT
+        );
+    }
 }
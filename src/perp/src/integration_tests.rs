@@ -8,10 +8,25 @@ use std::fs;
 use crate::close_position;
 use crate::{
     //  corelib::order_lib::LimitOrder,
-    types::{Asset, AssetClass, MarketDetails, StateDetails, Tick},
+    types::{
+        Asset, AssetClass, FundingConfig, FundingRecord, MarkPrice, MarketDetails, OracleDetails,
+        OracleKind, StateDetails, Tick,
+    },
     Amount, // OrderType, PositionDetails,
+    Candle,
+    CandleInterval,
+    OrderBookPage,
+    OrderBookSide,
+    OrderFlags,
     OrderType,
     PositionDetails,
+    PositionId,
+    PositionOrderType,
+    PositionRecord,
+    PositionReduction,
+    SwapQuote,
+    TimeInForce,
+    TradeRecord,
 };
 
 type Subaccount = [u8; 32];
@@ -197,93 +212,1675 @@ fn test_open_market_order_long() {
     let admin = _get_principals()[0];
     let pic = _setup_market(admin);
 
+    let second_caller = _get_principals()[1];
+
+    let (position_id, _position) = _open_market_long_against_ask(&pic, admin, second_caller);
+
+    let StateDetails { current_tick, .. } = _get_state(&pic);
+
+    assert_eq!(current_tick, 100000 * 200);
+
+    let _ = _open_position(
+        &pic,
+        Principal::anonymous(),
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 199),
+    );
+    let collateral = _close_position(&pic, second_caller, position_id);
+
+    println!("The pnl of this position is {}", collateral)
+}
+
+#[test]
+fn test_open_market_short() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
     let caller = _get_principals()[0];
 
-    let init_tick = 100000 * 199;
+    let init_tick = 100000 * 200;
 
     _set_state(&pic, admin, init_tick, 100, 0);
+
     // open limit order
-    let reference_tick_1 = 100000 * 200;
+    let reference_tick_1 = 19940_000;
 
     let _ = _open_position(
         &pic,
         caller,
-        1_000_000_000,
-        false,
+        1_000_000_000_000,
+        true,
         OrderType::Limit,
         20,
         Some(reference_tick_1),
     );
 
-    let StateDetails { current_tick, .. } = _get_state(&pic);
+    let second_caller = _get_principals()[1];
+
+    let result = _open_position(
+        &pic,
+        second_caller,
+        1_000_000,
+        false,
+        OrderType::Market,
+        20,
+        Some(reference_tick_1),
+    );
+
+    println!("The position is {:?}", result)
+}
 
-    assert_eq!(current_tick, reference_tick_1);
+#[test]
+fn test_position_triggers() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
 
     let second_caller = _get_principals()[1];
 
-    let result = _open_position(
+    let (position_id, _position) = _open_market_long_against_ask(&pic, admin, second_caller);
+
+    // take profit below current tick is invalid for a long position
+    let result = _set_position_triggers(&pic, second_caller, position_id, None, Some(100000 * 198));
+
+    assert!(result.is_err());
+
+    let stop_loss_tick = 100000 * 190;
+    let take_profit_tick = 100000 * 210;
+
+    let position = _set_position_triggers(
         &pic,
         second_caller,
+        position_id,
+        Some(stop_loss_tick),
+        Some(take_profit_tick),
+    )
+    .unwrap();
+
+    assert_eq!(position.stop_loss_tick, Some(stop_loss_tick));
+    assert_eq!(position.take_profit_tick, Some(take_profit_tick));
+}
+
+#[test]
+fn test_position_triggers_execution() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    // asks at two ticks with no liquidity in between and bids below the current tick
+    let _ = _open_position(
+        &pic,
+        admin,
         1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 202),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 198),
+    )
+    .unwrap();
+
+    let (position_id, _) = _open_position(
+        &pic,
+        caller,
+        100_000,
         true,
         OrderType::Market,
         20,
-        Some(reference_tick_1),
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    let take_profit_tick = 100000 * 201;
+
+    _set_position_triggers(&pic, caller, position_id, None, Some(take_profit_tick)).unwrap();
+
+    // buying through the take profit tick triggers the position
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(100000 * 202),
+    )
+    .unwrap();
+
+    assert!(_get_state(&pic).current_tick > take_profit_tick);
+
+    pic.advance_time(Duration::from_secs(3));
+    pic.tick();
+    pic.tick();
+
+    let account = _get_user_account(&pic, caller);
+
+    assert!(_get_account_positions(&pic, account).is_empty());
+}
+
+#[test]
+fn test_position_triggers_between_current_tick_and_best_offer() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let (position_id, _) = _open_market_long_against_ask(&pic, admin, caller);
+
+    let current_tick = _get_state(&pic).current_tick;
+
+    // the best bid is below the stop loss ,leaving the stop loss in the empty gap under the current tick
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 195),
+    )
+    .unwrap();
+
+    let stop_loss_tick = 100000 * 197;
+
+    _set_position_triggers(&pic, caller, position_id, Some(stop_loss_tick), None).unwrap();
+
+    // selling starts at the best bid but still passes the stop loss from the current tick
+    let _ = _open_position(&pic, admin, 1_000_000, false, OrderType::Market, 20, None).unwrap();
+
+    assert!(_get_best_offer(&pic, false) < stop_loss_tick);
+    assert!(current_tick > stop_loss_tick);
+
+    pic.advance_time(Duration::from_secs(3));
+    pic.tick();
+    pic.tick();
+
+    let account = _get_user_account(&pic, caller);
+
+    assert!(_get_account_positions(&pic, account).is_empty());
+}
+
+#[test]
+fn test_reduce_and_increase_position() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let second_caller = _get_principals()[1];
+
+    let (position_id, position) = _open_market_long_against_ask(&pic, admin, second_caller);
+
+    // the remaining ask is moved up so that the added size executes at a higher tick
+    let admin_account = _get_user_account(&pic, admin);
+    let (ask_position_id, _) = _get_account_positions(&pic, admin_account)[0];
+
+    _close_position(&pic, admin, ask_position_id);
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 202),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 198),
+    )
+    .unwrap();
+
+    let increased_position =
+        _increase_position(&pic, second_caller, position_id, 1_000_000, 20).unwrap();
+
+    assert_eq!(
+        increased_position.collateral_value,
+        position.collateral_value + 1_000_000
     );
+    assert!(increased_position.volume_share > position.volume_share);
 
-    if let Ok(_position) = result {
-        let _ = _open_position(
-            &pic,
-            Principal::anonymous(),
-            1_000_000_000,
-            true,
-            OrderType::Limit,
-            20,
-            Some(init_tick),
-        );
-        let collateral = _close_position(&pic, second_caller);
+    // the entry tick is blended between the entry tick and the added size execution tick
+    assert!(increased_position.entry_tick > position.entry_tick);
+    assert!(increased_position.entry_tick < 100000 * 202);
 
-        println!("The pnl of this position is {}", collateral)
-    }
+    let _ = _reduce_position(
+        &pic,
+        second_caller,
+        position_id,
+        PositionReduction::Fraction(50 * 100000),
+    )
+    .unwrap();
+
+    let account = _get_user_account(&pic, second_caller);
+    let reduced_position = _get_account_position(&pic, account, position_id);
+
+    assert_eq!(
+        reduced_position.collateral_value,
+        increased_position.collateral_value - increased_position.collateral_value / 2
+    );
+    assert_eq!(
+        reduced_position.debt_value,
+        increased_position.debt_value - increased_position.debt_value / 2
+    );
+    assert_eq!(
+        reduced_position.volume_share,
+        increased_position.volume_share - increased_position.volume_share / 2
+    );
+    assert_eq!(reduced_position.entry_tick, increased_position.entry_tick);
 }
 
 #[test]
-fn test_open_market_short() {
+fn test_multiple_positions_per_account() {
     let admin = _get_principals()[0];
     let pic = _setup_market(admin);
 
     let caller = _get_principals()[0];
 
-    let init_tick = 100000 * 200;
+    let init_tick = 100000 * 199;
 
     _set_state(&pic, admin, init_tick, 100, 0);
 
     // open limit order
-    let reference_tick_1 = 19940_000;
+    let reference_tick_1 = 100000 * 200;
 
-    let _ = _open_position(
+    let (limit_position_id, _) = _open_position(
         &pic,
         caller,
-        1_000_000_000_000,
-        true,
+        1_000_000_000,
+        false,
         OrderType::Limit,
         20,
         Some(reference_tick_1),
-    );
+    )
+    .unwrap();
+
+    // same account opens a market position while the limit position is still open
+    let (market_position_id, _) = _open_position(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick_1),
+    )
+    .unwrap();
+
+    assert_ne!(limit_position_id, market_position_id);
+
+    let account = _get_user_account(&pic, caller);
+
+    let positions = _get_account_positions(&pic, account);
+
+    assert_eq!(positions.len(), 2);
+
+    let _ = _close_position(&pic, caller, market_position_id);
+
+    let positions = _get_account_positions(&pic, account);
+
+    assert_eq!(positions.len(), 1);
+    assert_eq!(positions[0].0, limit_position_id);
+}
+
+#[test]
+fn test_liquidate_healthy_position_failing() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[0];
+
+    let init_tick = 100000 * 199;
 
     let second_caller = _get_principals()[1];
 
-    let result = _open_position(
-        &pic,
-        second_caller,
-        1_000_000,
-        false,
-        OrderType::Market,
-        20,
-        Some(reference_tick_1),
+    let (position_id, _) = _open_market_long_against_ask(&pic, admin, second_caller);
+
+    let result = _liquidate_position(&pic, caller, second_caller, position_id);
+
+    assert!(result.is_err());
+
+    let account = _get_user_account(&pic, second_caller);
+
+    // a 2x long is only liquidatable well below the entry tick
+    let liquidation_tick = _get_liquidation_price(&pic, account, position_id).unwrap();
+
+    assert!(liquidation_tick < init_tick);
+
+    // position is left untouched
+    let positions = _get_account_positions(&pic, account);
+
+    assert_eq!(positions.len(), 1);
+}
+
+#[test]
+fn test_partial_liquidation() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    _set_state(&pic, admin, 100000 * 199, 100, 0);
+
+    let state_details = StateDetails {
+        maintenance_margin: 9 * 100000,
+        liquidation_fee: 100000,
+        keeper_share: 50 * 100000,
+        .._get_state(&pic)
+    };
+
+    _update_state_details(&pic, admin, state_details).unwrap();
+
+    let (ask_position_id, _) = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    // a 9x long is liquidatable a little below the entry tick
+    let (position_id, position) = _open_position(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Market,
+        90,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    _close_position(&pic, admin, ask_position_id);
+
+    // a thin bid within the liquidation slippage and deep bids outside it
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        true,
+        OrderType::Limit,
+        10,
+        Some(100000 * 194),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 190),
+    )
+    .unwrap();
+
+    // asks just above the bids move the mark below the liquidation tick over time
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 195),
+    )
+    .unwrap();
+
+    pic.advance_time(Duration::from_secs(10 * 3600));
+
+    let _ = _liquidate_position(&pic, admin, caller, position_id).unwrap();
+
+    // only the part filled by the thin bid is unwound
+    let account = _get_user_account(&pic, caller);
+
+    let liquidated_position = _get_account_position(&pic, account, position_id);
+
+    assert!(liquidated_position.volume_share > 0);
+    assert!(liquidated_position.volume_share < position.volume_share);
+    assert_eq!(_get_best_offer(&pic, false), 100000 * 190);
+}
+
+#[test]
+fn test_retry_account_error_owner_only() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let user = _get_principals()[1];
+
+    let Ok(WasmResult::Reply(res)) = pic.update_call(
+        _get_canister_id(),
+        Principal::anonymous(),
+        "retryAccountError",
+        encode_one(user).unwrap(),
+    ) else {
+        panic!("failed to retry account error")
+    };
+
+    let result: Result<(), String> = decode_one(&res).unwrap();
+
+    assert!(result.is_err());
+
+    for caller in [user, admin] {
+        let Ok(WasmResult::Reply(res)) = pic.update_call(
+            _get_canister_id(),
+            caller,
+            "retryAccountError",
+            encode_one(user).unwrap(),
+        ) else {
+            panic!("failed to retry account error")
+        };
+
+        let result: Result<(), String> = decode_one(&res).unwrap();
+
+        assert!(result.is_ok());
+    }
+}
+
+#[test]
+fn test_liquidation_sweeper_admin_only() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let canister_id = _get_canister_id();
+
+    let not_admin = _get_principals()[1];
+
+    let result = pic.update_call(
+        canister_id,
+        not_admin,
+        "startLiquidationSweeper",
+        encode_args(()).unwrap(),
+    );
+
+    assert!(!matches!(result, Ok(WasmResult::Reply(_))));
+
+    let result = pic.update_call(
+        canister_id,
+        admin,
+        "setSweeperBatchSize",
+        encode_one(50u64).unwrap(),
+    );
+
+    assert!(matches!(result, Ok(WasmResult::Reply(_))));
+
+    for method in ["startLiquidationSweeper", "stopLiquidationSweeper"] {
+        let result = pic.update_call(canister_id, admin, method, encode_args(()).unwrap());
+
+        assert!(matches!(result, Ok(WasmResult::Reply(_))));
+    }
+}
+
+#[test]
+fn test_liquidation_sweeper_liquidates_unhealthy_position() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    _set_state(&pic, admin, 100000 * 199, 100, 0);
+
+    let state_details = StateDetails {
+        maintenance_margin: 9 * 100000,
+        .._get_state(&pic)
+    };
+
+    _update_state_details(&pic, admin, state_details).unwrap();
+
+    let (ask_position_id, _) = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 194),
+    )
+    .unwrap();
+
+    // a 9x long is liquidatable a little below the entry tick
+    let (position_id, _) = _open_position(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Market,
+        90,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    // the remaining asks are removed and the price is pushed down to the bids
+    _close_position(&pic, admin, ask_position_id);
+
+    let _ = _open_position(&pic, admin, 1_000_000, false, OrderType::Market, 20, None).unwrap();
+
+    pic.advance_time(Duration::from_secs(3600));
+
+    let account = _get_user_account(&pic, caller);
+
+    assert_eq!(_get_account_positions(&pic, account).len(), 1);
+
+    let Ok(WasmResult::Reply(_)) = pic.update_call(
+        _get_canister_id(),
+        admin,
+        "startLiquidationSweeper",
+        encode_args(()).unwrap(),
+    ) else {
+        panic!("failed to start liquidation sweeper")
+    };
+
+    pic.advance_time(Duration::from_secs(11));
+    pic.tick();
+    pic.tick();
+
+    let positions = _get_account_positions(&pic, account);
+
+    assert!(positions
+        .iter()
+        .all(|(swept_position_id, _)| *swept_position_id != position_id));
+}
+
+#[test]
+fn test_trading_fees() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let mut state_details = _get_state(&pic);
+
+    // maker rebate can not exceed the taker fee
+    state_details.taker_fee = 10 * 1000;
+    state_details.maker_fee = -20 * 1000;
+
+    assert!(_update_state_details(&pic, admin, state_details).is_err());
+
+    state_details.maker_fee = -2 * 1000;
+
+    assert!(_update_state_details(&pic, admin, state_details).is_ok());
+
+    let reference_tick = 100000 * 200;
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    let collateral = 1_000_000;
+
+    let (_, position) = _open_position(
+        &pic,
+        caller,
+        collateral,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    // taker fee of 0.1% on the position value of twice the collateral
+    assert_eq!(position.collateral_value, collateral - 2_000);
+}
+
+#[test]
+fn test_post_only_and_reduce_only_orders() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let reference_tick = 100000 * 200;
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    // buy liquidity for reducing the long position
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 198),
+    )
+    .unwrap();
+
+    let post_only = OrderFlags {
+        post_only: true,
+        reduce_only: None,
+        time_in_force: None,
+    };
+
+    // post only long limit order at the best sell offer is rejected
+    let result = _open_position_with_flags(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+        post_only,
+    );
+
+    assert_eq!(
+        result.err(),
+        Some("Post only order would cross the best offer".to_string())
+    );
+
+    // without the flag the limit order is executed at market
+    let (position_id, position) = _open_position(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    assert!(matches!(position.order_type, PositionOrderType::Market));
+
+    let reduce_only = OrderFlags {
+        post_only: false,
+        reduce_only: Some(position_id),
+        time_in_force: None,
+    };
+
+    // reduce only order in the same direction as the position is rejected
+    let result = _open_position_with_flags(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Market,
+        20,
+        None,
+        reduce_only,
+    );
+
+    assert!(result.is_err());
+
+    let (reduced_position_id, reduced_position) = _open_position_with_flags(
+        &pic,
+        caller,
+        500_000,
+        false,
+        OrderType::Market,
+        20,
+        None,
+        reduce_only,
+    )
+    .unwrap();
+
+    assert_eq!(reduced_position_id, position_id);
+    assert!(reduced_position.volume_share < position.volume_share);
+}
+
+#[test]
+fn test_fill_or_kill_and_immediate_or_cancel_orders() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let reference_tick = 100000 * 200;
+
+    // small sell liquidity at the reference tick
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    let flags = |time_in_force: TimeInForce| OrderFlags {
+        post_only: false,
+        reduce_only: None,
+        time_in_force: Some(time_in_force),
+    };
+
+    // market orders can not rest in the book
+    let result = _open_position_with_flags(
+        &pic,
+        caller,
+        1_000_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick),
+        flags(TimeInForce::Gtc),
+    );
+
+    assert!(result.is_err());
+
+    // fill or kill order larger than the liquidity is reverted
+    let result = _open_position_with_flags(
+        &pic,
+        caller,
+        1_000_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick),
+        flags(TimeInForce::Fok),
+    );
+
+    assert_eq!(
+        result.err(),
+        Some("Fill or kill order could not be fully filled".to_string())
+    );
+
+    // the liquidity is left untouched
+    assert_eq!(_get_best_offer(&pic, false), reference_tick);
+
+    // immediate or cancel order is partially filled
+    let (_, position) = _open_position_with_flags(
+        &pic,
+        caller,
+        1_000_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick),
+        flags(TimeInForce::Ioc),
+    )
+    .unwrap();
+
+    assert!(position.collateral_value + position.debt_value < 2 * 1_000_000_000);
+}
+
+#[test]
+fn test_good_till_time_limit_order_expiry() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let now = pic
+        .get_time()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap();
+
+    let flags = |expiry: Duration| OrderFlags {
+        post_only: false,
+        reduce_only: None,
+        time_in_force: Some(TimeInForce::Gtt(expiry.as_nanos() as u64)),
+    };
+
+    // expiry time in the past is rejected
+    let result = _open_position_with_flags(
+        &pic,
+        caller,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 200),
+        flags(now - Duration::from_secs(1)),
+    );
+
+    assert!(result.is_err());
+
+    // the later expiry is armed first and replaced by the earlier one
+    for expiry in [
+        now + Duration::from_secs(180),
+        now + Duration::from_secs(60),
+    ] {
+        let _ = _open_position_with_flags(
+            &pic,
+            caller,
+            1_000_000_000,
+            false,
+            OrderType::Limit,
+            20,
+            Some(100000 * 200),
+            flags(expiry),
+        )
+        .unwrap();
+    }
+
+    let account = _get_user_account(&pic, caller);
+
+    assert_eq!(_get_account_positions(&pic, account).len(), 2);
+
+    pic.advance_time(Duration::from_secs(120));
+    pic.tick();
+    pic.tick();
+
+    // unfilled limit position is closed on expiry
+    assert_eq!(_get_account_positions(&pic, account).len(), 1);
+
+    // the next expiry is armed after each run
+    pic.advance_time(Duration::from_secs(120));
+    pic.tick();
+    pic.tick();
+
+    assert_eq!(_get_account_positions(&pic, account).len(), 0);
+}
+
+#[test]
+fn test_crossed_tick_orders_execution() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let reference_tick = 100000 * 200;
+
+    let (limit_position_id, _) = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    // market order large enough to cross the reference tick
+    let _ = _open_position(
+        &pic,
+        caller,
+        1_000_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    pic.tick();
+    pic.tick();
+
+    assert_eq!(_get_execution_queue_depth(&pic), (0, 0));
+
+    let account = _get_user_account(&pic, admin);
+
+    let position = _get_account_position(&pic, account, limit_position_id);
+
+    assert!(matches!(position.order_type, PositionOrderType::Market));
+}
+
+#[test]
+fn test_queues_and_timers_survive_upgrade() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    _set_state(&pic, admin, 100000 * 199, 100, 0);
+
+    let state_details = StateDetails {
+        maintenance_margin: 9 * 100000,
+        .._get_state(&pic)
+    };
+
+    _update_state_details(&pic, admin, state_details).unwrap();
+
+    let (crossed_position_id, _) = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 202),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 194),
+    )
+    .unwrap();
+
+    // a 9x long for the sweeper and a 2x long with a take profit for the triggers
+    let (swept_position_id, _) = _open_position(
+        &pic,
+        caller,
+        100_000,
+        true,
+        OrderType::Market,
+        90,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    let (triggered_position_id, _) = _open_position(
+        &pic,
+        caller,
+        100_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    _set_position_triggers(
+        &pic,
+        caller,
+        triggered_position_id,
+        None,
+        Some(100000 * 201),
+    )
+    .unwrap();
+
+    let now = pic
+        .get_time()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap();
+
+    let (expiring_position_id, _) = _open_position_with_flags(
+        &pic,
+        caller,
+        1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 210),
+        OrderFlags {
+            post_only: false,
+            reduce_only: None,
+            time_in_force: Some(TimeInForce::Gtt(
+                (now + Duration::from_secs(60)).as_nanos() as u64
+            )),
+        },
+    )
+    .unwrap();
+
+    let Ok(WasmResult::Reply(_)) = pic.update_call(
+        _get_canister_id(),
+        admin,
+        "startLiquidationSweeper",
+        encode_args(()).unwrap(),
+    ) else {
+        panic!("failed to start liquidation sweeper")
+    };
+
+    // crosses the tick at 200 and buys through the take profit tick
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(100000 * 202),
+    )
+    .unwrap();
+
+    assert!(_get_execution_queue_depth(&pic).1 > 0);
+
+    // timers set before the upgrade are lost
+    _upgrade_canister(&pic, admin);
+
+    let admin_account = _get_user_account(&pic, admin);
+    let account = _get_user_account(&pic, caller);
+
+    // the crossed ticks queue is drained
+    pic.tick();
+    pic.tick();
+
+    assert_eq!(_get_execution_queue_depth(&pic), (0, 0));
+
+    let crossed_position = _get_account_position(&pic, admin_account, crossed_position_id);
+
+    assert!(matches!(
+        crossed_position.order_type,
+        PositionOrderType::Market
+    ));
+
+    // the reached take profit is executed
+    pic.advance_time(Duration::from_secs(3));
+    pic.tick();
+    pic.tick();
+
+    let position_ids = |positions: Vec<(PositionId, PositionDetails)>| -> Vec<PositionId> {
+        positions
+            .iter()
+            .map(|(position_id, _)| *position_id)
+            .collect()
+    };
+
+    assert!(!position_ids(_get_account_positions(&pic, account)).contains(&triggered_position_id));
+
+    // the good till time limit position expires
+    pic.advance_time(Duration::from_secs(120));
+    pic.tick();
+    pic.tick();
+
+    assert!(!position_ids(_get_account_positions(&pic, account)).contains(&expiring_position_id));
+
+    // asks just above the bids move the mark below the liquidation tick over time and the sweeper liquidates the position
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 195),
+    )
+    .unwrap();
+
+    pic.advance_time(Duration::from_secs(10 * 3600));
+    pic.tick();
+    pic.tick();
+
+    assert!(!position_ids(_get_account_positions(&pic, account)).contains(&swept_position_id));
+}
+
+#[test]
+fn test_crossed_tick_orders_settled_on_read() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let reference_tick = 100000 * 200;
+
+    let (limit_position_id, _) = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        caller,
+        1_000_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    // the filled position is read as a market position without waiting for the execution queue
+    let account = _get_user_account(&pic, admin);
+
+    let position = _get_account_position(&pic, account, limit_position_id);
+
+    assert!(matches!(position.order_type, PositionOrderType::Market));
+}
+
+#[test]
+fn test_order_book_levels() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    for reference_tick in [100000 * 200, 100000 * 201] {
+        let _ = _open_position(
+            &pic,
+            admin,
+            1_000_000,
+            false,
+            OrderType::Limit,
+            20,
+            Some(reference_tick),
+        )
+        .unwrap();
+    }
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 198),
+    )
+    .unwrap();
+
+    let first_page = _get_order_book(&pic, 1, OrderBookSide::Asks, None);
+
+    assert_eq!(first_page.levels.len(), 1);
+    assert_eq!(first_page.levels[0].tick, 100000 * 200);
+    assert_eq!(first_page.next_tick, Some(100000 * 201));
+
+    let second_page = _get_order_book(&pic, 1, OrderBookSide::Asks, first_page.next_tick);
+
+    assert_eq!(second_page.levels[0].tick, 100000 * 201);
+    assert_eq!(second_page.next_tick, None);
+
+    let bids = _get_order_book(&pic, 10, OrderBookSide::Bids, None);
+
+    assert_eq!(bids.levels.len(), 1);
+    assert_eq!(bids.levels[0].tick, 100000 * 198);
+    assert!(bids.levels[0].liquidity > 0);
+}
+
+#[test]
+fn test_quote_swap_does_not_mutate_state() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let reference_tick = 100000 * 200;
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    let quote = _quote_swap(&pic, 1_000_000, true, Some(reference_tick));
+
+    assert_eq!(quote.amount_in + quote.amount_remaining, 1_000_000);
+    assert!(quote.amount_out > 0);
+    assert_eq!(quote.resulting_tick, reference_tick);
+    assert!(quote.average_price > 0);
+
+    // quoting again returns the same fill since the order book is unchanged
+    let second_quote = _quote_swap(&pic, 1_000_000, true, Some(reference_tick));
+
+    assert_eq!(second_quote.amount_out, quote.amount_out);
+    assert_eq!(_get_best_offer(&pic, false), reference_tick);
+}
+
+#[test]
+fn test_trade_and_positions_history() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let reference_tick = 100000 * 200;
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    let (market_position_id, _) = _open_position(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    let caller_account = _get_user_account(&pic, caller);
+
+    let trades = _get_trade_history(&pic, 0, 10);
+
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].account, caller_account);
+    assert!(trades[0].buy);
+    assert!(trades[0].average_price > 0);
+
+    let history = _get_account_positions_history(&pic, caller_account, None, 10);
+
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].0, market_position_id);
+    assert_eq!(history[0].1.closed_at, None);
+
+    // an unfilled limit order closed is recorded with the whole collateral withdrawn
+    let (limit_position_id, _) = _open_position(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 198),
+    )
+    .unwrap();
+
+    _close_position(&pic, caller, limit_position_id);
+
+    let history =
+        _get_account_positions_history(&pic, caller_account, Some(market_position_id), 10);
+
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].0, limit_position_id);
+    assert!(history[0].1.closed_at.is_some());
+    assert_eq!(history[0].1.realised_pnl, 0);
+}
+
+#[test]
+fn test_candles_from_executed_swaps() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    let init_tick = 100000 * 199;
+
+    _set_state(&pic, admin, init_tick, 100, 0);
+
+    let reference_tick = 100000 * 200;
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    // no swap has been executed yet
+    assert!(_get_candles(&pic, CandleInterval::OneMinute).is_empty());
+
+    let _ = _open_position(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(reference_tick),
+    )
+    .unwrap();
+
+    for interval in [CandleInterval::OneMinute, CandleInterval::OneDay] {
+        let candles = _get_candles(&pic, interval);
+
+        assert_eq!(candles.len(), 1);
+
+        let (_, candle) = candles[0];
+
+        assert!(candle.volume > 0);
+        assert!(candle.high >= candle.low);
+        assert_eq!(candle.open, candle.low);
+    }
+}
+
+#[test]
+fn test_stale_oracle_pauses_leverage() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    _set_state(&pic, admin, 100000 * 199, 100, 0);
+
+    // no spot price has been fetched yet
+    let (oracle_details, stale) = _get_oracle_health(&pic);
+
+    assert!(stale);
+    assert_eq!(oracle_details.last_updated, 0);
+    assert_eq!(oracle_details.consecutive_failures, 0);
+
+    let funding_config = FundingConfig {
+        pause_leverage_on_stale_oracle: true,
+        ..FundingConfig::default()
+    };
+
+    // only the admin can update the funding config
+    let result = pic.update_call(
+        _get_canister_id(),
+        caller,
+        "updateFundingConfig",
+        encode_one(funding_config).unwrap(),
+    );
+
+    assert!(!matches!(result, Ok(WasmResult::Reply(_))));
+
+    let invalid_config = FundingConfig {
+        max_oracle_staleness: 0,
+        ..funding_config
+    };
+
+    assert!(_update_funding_config(&pic, admin, invalid_config).is_err());
+
+    _update_funding_config(&pic, admin, funding_config).unwrap();
+
+    let result = _open_position(&pic, caller, 1_000_000, true, OrderType::Market, 20, None);
+
+    assert_eq!(result.unwrap_err(), "Oracle price is stale".to_string());
+}
+
+#[test]
+fn test_funding_settled_on_mock_oracle_price() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let keeper = _get_principals()[1];
+
+    // perp price is 1.99 times the base price
+    _set_state(&pic, admin, 100000 * 199, 100, 0);
+
+    // only the admin or the price keeper can push prices
+    assert!(_push_oracle_price(&pic, keeper, 1_000_000_000, 9).is_err());
+
+    let Ok(WasmResult::Reply(_)) = pic.update_call(
+        _get_canister_id(),
+        admin,
+        "setPriceKeeper",
+        encode_one(Some(keeper)).unwrap(),
+    ) else {
+        panic!("failed to set price keeper")
+    };
+
+    // prices scaled beyond the max oracle decimals are rejected
+    assert_eq!(
+        _push_oracle_price(&pic, keeper, 1_000_000_000, 39),
+        Err("Decimals exceed the max oracle decimals".to_string())
+    );
+
+    // spot price is the base price
+    _push_oracle_price(&pic, keeper, 1_000_000_000, 9).unwrap();
+
+    let Ok(WasmResult::Reply(_)) = pic.update_call(
+        _get_canister_id(),
+        admin,
+        "startTimer",
+        encode_one(()).unwrap(),
+    ) else {
+        panic!("failed to start timer")
+    };
+
+    pic.advance_time(Duration::from_secs(3601));
+    pic.tick();
+    pic.tick();
+
+    let funding_history = _get_funding_history(&pic);
+
+    assert_eq!(funding_history.len(), 1);
+
+    let (_, funding_record) = funding_history[0];
+
+    assert_eq!(funding_record.spot_price, 1_000_000_000);
+    assert!(funding_record.perp_price > funding_record.spot_price);
+
+    // the premium is clamped to the max funding rate ,longs pay shorts
+    assert_eq!(
+        funding_record.funding_rate,
+        FundingConfig::default().max_funding_rate as i64
+    );
+
+    let (oracle_details, stale) = _get_oracle_health(&pic);
+
+    assert!(!stale);
+    assert_eq!(oracle_details.last_price, 1_000_000_000);
+    assert_eq!(oracle_details.consecutive_failures, 0);
+}
+
+#[test]
+fn test_positions_valued_at_mark_price() {
+    let admin = _get_principals()[0];
+    let pic = _setup_market(admin);
+
+    let caller = _get_principals()[1];
+
+    _set_state(&pic, admin, 100000 * 199, 100, 0);
+
+    let state_details = StateDetails {
+        maintenance_margin: 9 * 100000,
+        .._get_state(&pic)
+    };
+
+    _update_state_details(&pic, admin, state_details).unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    // a thin bid above the deep bids
+    let _ = _open_position(
+        &pic,
+        admin,
+        100_000,
+        true,
+        OrderType::Limit,
+        10,
+        Some(100000 * 194),
+    )
+    .unwrap();
+
+    let _ = _open_position(
+        &pic,
+        admin,
+        1_000_000_000,
+        true,
+        OrderType::Limit,
+        20,
+        Some(100000 * 191),
+    )
+    .unwrap();
+
+    let (position_id, _) = _open_position(
+        &pic,
+        caller,
+        1_000_000,
+        true,
+        OrderType::Market,
+        90,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    pic.advance_time(Duration::from_secs(3600));
+
+    // a single trade clears the thin bid and moves the last traded tick to the deep bids
+    let _ = _open_position(&pic, admin, 1_000_000, false, OrderType::Market, 20, None).unwrap();
+
+    let last_traded_tick = _get_trade_history(&pic, 0, 10)
+        .last()
+        .unwrap()
+        .resulting_tick;
+
+    assert_eq!(last_traded_tick, 100000 * 191);
+    assert_eq!(_get_best_offer(&pic, false), 100000 * 191);
+
+    let account = _get_user_account(&pic, caller);
+
+    let liquidation_tick = _get_liquidation_price(&pic, account, position_id).unwrap();
+
+    // the position would be liquidatable at the last traded tick
+    assert!(last_traded_tick <= liquidation_tick);
+
+    // the oracle has never been fetched ,so the mark is the average of the twap and the mid ticks
+    let mark_price = _get_mark_price(&pic);
+
+    assert_eq!(mark_price.spot_tick, None);
+    assert_eq!(mark_price.mid_tick, (100000 * 191 + 100000 * 200) / 2);
+    assert_eq!(
+        mark_price.mark_tick,
+        (mark_price.twap_tick + mark_price.mid_tick) / 2
+    );
+    assert!(mark_price.mark_tick > liquidation_tick);
+
+    let result = _liquidate_position(&pic, admin, caller, position_id);
+
+    assert!(result.is_err());
+
+    // the loss is valued at the mark tick ,about 1% rather than the 4.5% drop of the current tick
+    let position = _get_account_position(&pic, account, position_id);
+
+    let pnl = _get_pnl(&pic, position);
+
+    assert!(pnl < 0);
+    assert!(pnl > -3 * 100000);
+
+    // funding is settled on the mark price
+    _push_oracle_price(&pic, admin, 2_000_000_000, 9).unwrap();
+
+    let Ok(WasmResult::Reply(_)) = pic.update_call(
+        _get_canister_id(),
+        admin,
+        "startTimer",
+        encode_one(()).unwrap(),
+    ) else {
+        panic!("failed to start timer")
+    };
+
+    pic.advance_time(Duration::from_secs(3601));
+    pic.tick();
+    pic.tick();
+
+    let funding_history = _get_funding_history(&pic);
+
+    assert_eq!(funding_history.len(), 1);
+
+    let (_, funding_record) = funding_history[0];
+
+    let mark_price = _get_mark_price(&pic);
+
+    assert!(mark_price.spot_tick.is_some());
+
+    // ticks are scaled to the 9 decimals of the spot price
+    assert_eq!(
+        funding_record.perp_price,
+        u128::from(mark_price.mark_tick) * 100
     );
-
-    println!("The position is {:?}", result)
+    assert!(funding_record.perp_price > u128::from(last_traded_tick) * 100);
 }
 
 ///////////////////////////////////////////////////////////////////////
@@ -297,7 +1894,30 @@ fn _open_position(
     order_type: OrderType,
     leverage: u8,
     max_tick: Option<Tick>,
-) -> Result<PositionDetails, String> {
+) -> Result<(PositionId, PositionDetails), String> {
+    _open_position_with_flags(
+        pic,
+        principal,
+        collateral,
+        long,
+        order_type,
+        leverage,
+        max_tick,
+        OrderFlags::default(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn _open_position_with_flags(
+    pic: &PocketIc,
+    principal: Principal,
+    collateral: Amount,
+    long: bool,
+    order_type: OrderType,
+    leverage: u8,
+    max_tick: Option<Tick>,
+    order_flags: OrderFlags,
+) -> Result<(PositionId, PositionDetails), String> {
     let canister_id = _get_canister_id();
 
     let returns;
@@ -306,7 +1926,17 @@ fn _open_position(
         canister_id,
         principal,
         "openPosition",
-        encode_args((collateral, long, order_type, leverage, max_tick, 1u64, 1u64)).unwrap(),
+        encode_args((
+            collateral,
+            long,
+            order_type,
+            leverage,
+            max_tick,
+            Option::<Tick>::None,
+            Option::<Tick>::None,
+            order_flags,
+        ))
+        .unwrap(),
     ) {
         Ok(reply) => {
             if let WasmResult::Reply(val) = reply {
@@ -326,7 +1956,121 @@ fn _open_position(
     return reply;
 }
 
-fn _close_position(pic: &PocketIc, sender: Principal) -> u128 {
+fn _open_market_long_against_ask(
+    pic: &PocketIc,
+    admin: Principal,
+    taker: Principal,
+) -> (PositionId, PositionDetails) {
+    _set_state(pic, admin, 100000 * 199, 100, 0);
+
+    let _ = _open_position(
+        pic,
+        admin,
+        1_000_000_000,
+        false,
+        OrderType::Limit,
+        20,
+        Some(100000 * 200),
+    )
+    .unwrap();
+
+    _open_position(
+        pic,
+        taker,
+        1_000_000,
+        true,
+        OrderType::Market,
+        20,
+        Some(100000 * 200),
+    )
+    .expect("failed to open market long")
+}
+
+fn _set_position_triggers(
+    pic: &PocketIc,
+    principal: Principal,
+    position_id: PositionId,
+    stop_loss_tick: Option<Tick>,
+    take_profit_tick: Option<Tick>,
+) -> Result<PositionDetails, String> {
+    let canister_id = _get_canister_id();
+
+    let Ok(WasmResult::Reply(res)) = pic.update_call(
+        canister_id,
+        principal,
+        "setPositionTriggers",
+        encode_args((position_id, stop_loss_tick, take_profit_tick)).unwrap(),
+    ) else {
+        panic!("failed to set position triggers")
+    };
+
+    decode_one(&res).unwrap()
+}
+
+fn _reduce_position(
+    pic: &PocketIc,
+    principal: Principal,
+    position_id: PositionId,
+    reduction: PositionReduction,
+) -> Result<Amount, String> {
+    let canister_id = _get_canister_id();
+
+    let max_tick: Option<Tick> = Option::None;
+    let Ok(WasmResult::Reply(res)) = pic.update_call(
+        canister_id,
+        principal,
+        "reducePosition",
+        encode_args((position_id, reduction, max_tick)).unwrap(),
+    ) else {
+        panic!("failed to reduce position")
+    };
+
+    decode_one(&res).unwrap()
+}
+
+fn _increase_position(
+    pic: &PocketIc,
+    principal: Principal,
+    position_id: PositionId,
+    collateral_value: Amount,
+    leveragex10: u8,
+) -> Result<PositionDetails, String> {
+    let canister_id = _get_canister_id();
+
+    let max_tick: Option<Tick> = Option::None;
+    let Ok(WasmResult::Reply(res)) = pic.update_call(
+        canister_id,
+        principal,
+        "increasePosition",
+        encode_args((position_id, collateral_value, leveragex10, max_tick)).unwrap(),
+    ) else {
+        panic!("failed to increase position")
+    };
+
+    decode_one(&res).unwrap()
+}
+
+fn _liquidate_position(
+    pic: &PocketIc,
+    keeper: Principal,
+    owner: Principal,
+    position_id: PositionId,
+) -> Result<(Tick, Vec<Tick>), String> {
+    let canister_id = _get_canister_id();
+
+    let Ok(WasmResult::Reply(res)) = pic.update_call(
+        canister_id,
+        keeper,
+        "liquidatePosition",
+        encode_args((owner, position_id)).unwrap(),
+    ) else {
+        panic!("failed to liquidate position")
+    };
+
+    decode_one(&res).unwrap()
+}
+
+fn _close_position(pic: &PocketIc, sender: Principal, position_id: PositionId) -> u128 {
     let canister_id = _get_canister_id();
 
     let max_tick: Option<Tick> = Option::None;
@@ -334,7 +2078,7 @@ fn _close_position(pic: &PocketIc, sender: Principal) -> u128 {
         canister_id,
         sender,
         "closePosition",
-        encode_one(max_tick).unwrap(),
+        encode_args((position_id, max_tick)).unwrap(),
     ) else {
         panic!("failed to close position")
     };
@@ -360,6 +2104,181 @@ fn _get_state(pic: &PocketIc) -> StateDetails {
     decode_one(&val).unwrap()
 }
 
+fn _get_execution_queue_depth(pic: &PocketIc) -> (u64, u64) {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getExecutionQueueDepth",
+        encode_one(()).unwrap(),
+    ) else {
+        panic!("execution queue depth could not be found")
+    };
+
+    decode_args(&val).unwrap()
+}
+
+fn _push_oracle_price(
+    pic: &PocketIc,
+    caller: Principal,
+    price: u128,
+    decimals: u32,
+) -> Result<(), String> {
+    let canister_id = _get_canister_id();
+
+    let Ok(WasmResult::Reply(res)) = pic.update_call(
+        canister_id,
+        caller,
+        "pushOraclePrice",
+        encode_args((price, decimals)).unwrap(),
+    ) else {
+        return Err("caller can not push prices".to_string());
+    };
+
+    decode_one(&res).unwrap()
+}
+
+fn _get_funding_history(pic: &PocketIc) -> Vec<(u64, FundingRecord)> {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getFundingHistory",
+        encode_args((0u64, 100u64)).unwrap(),
+    ) else {
+        panic!("funding history could not be found")
+    };
+
+    decode_one(&val).unwrap()
+}
+
+fn _get_mark_price(pic: &PocketIc) -> MarkPrice {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getMarkPrice",
+        encode_one(()).unwrap(),
+    ) else {
+        panic!("mark price could not be found")
+    };
+
+    decode_one(&val).unwrap()
+}
+
+fn _get_oracle_health(pic: &PocketIc) -> (OracleDetails, bool) {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getOracleHealth",
+        encode_one(()).unwrap(),
+    ) else {
+        panic!("oracle health could not be found")
+    };
+
+    decode_args(&val).unwrap()
+}
+
+fn _update_funding_config(
+    pic: &PocketIc,
+    caller: Principal,
+    funding_config: FundingConfig,
+) -> Result<(), String> {
+    let canister_id = _get_canister_id();
+
+    let Ok(WasmResult::Reply(res)) = pic.update_call(
+        canister_id,
+        caller,
+        "updateFundingConfig",
+        encode_one(funding_config).unwrap(),
+    ) else {
+        panic!("failed to update funding config")
+    };
+
+    decode_one(&res).unwrap()
+}
+
+fn _get_candles(pic: &PocketIc, interval: CandleInterval) -> Vec<(u64, Candle)> {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getCandles",
+        encode_args((interval, 0u64, u64::MAX)).unwrap(),
+    ) else {
+        panic!("candles could not be found")
+    };
+
+    decode_one(&val).unwrap()
+}
+
+fn _get_trade_history(pic: &PocketIc, start: u64, limit: u64) -> Vec<TradeRecord> {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getTradeHistory",
+        encode_args((start, limit)).unwrap(),
+    ) else {
+        panic!("trade history could not be found")
+    };
+
+    decode_one(&val).unwrap()
+}
+
+fn _get_account_positions_history(
+    pic: &PocketIc,
+    account: Subaccount,
+    start_after: Option<PositionId>,
+    limit: u64,
+) -> Vec<(PositionId, PositionRecord)> {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getAccountPositionsHistory",
+        encode_args((account, start_after, limit)).unwrap(),
+    ) else {
+        panic!("positions history could not be found")
+    };
+
+    decode_one(&val).unwrap()
+}
+
+fn _quote_swap(pic: &PocketIc, amount: Amount, buy: bool, max_tick: Option<Tick>) -> SwapQuote {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "quoteSwap",
+        encode_args((amount, buy, max_tick)).unwrap(),
+    ) else {
+        panic!("swap could not be quoted")
+    };
+
+    decode_one(&val).unwrap()
+}
+
+fn _get_order_book(
+    pic: &PocketIc,
+    levels: u64,
+    side: OrderBookSide,
+    start_tick: Option<Tick>,
+) -> OrderBookPage {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getOrderBook",
+        encode_args((levels, side, start_tick)).unwrap(),
+    ) else {
+        panic!("order book could not be found")
+    };
+
+    decode_one(&val).unwrap()
+}
+
 ///
 fn _get_best_offer(pic: &PocketIc, buy: bool) -> Tick {
     let canister_id = _get_canister_id();
@@ -409,12 +2328,33 @@ fn _get_user_account(pic: &PocketIc, principal: Principal) -> Subaccount {
 }
 
 /// Get Account Position
-fn _get_account_position(pic: &PocketIc, account: Subaccount) -> PositionDetails {
+fn _get_account_position(
+    pic: &PocketIc,
+    account: Subaccount,
+    position_id: PositionId,
+) -> PositionDetails {
     let canister_id = _get_canister_id();
     let Ok(WasmResult::Reply(val)) = pic.query_call(
         canister_id,
         Principal::anonymous(),
         "getAccountPosition",
+        encode_args((account, position_id)).unwrap(),
+    ) else {
+        panic!("Account could not be gotten")
+    };
+    decode_one(&val).unwrap()
+}
+
+/// Get Account Positions
+fn _get_account_positions(
+    pic: &PocketIc,
+    account: Subaccount,
+) -> Vec<(PositionId, PositionDetails)> {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getAccountPositions",
         encode_one(account).unwrap(),
     ) else {
         panic!("Account could not be gotten")
@@ -424,13 +2364,34 @@ fn _get_account_position(pic: &PocketIc, account: Subaccount) -> PositionDetails
     return reply;
 }
 
-fn _get_position_status(pic: &PocketIc, account: Subaccount) -> (bool, bool) {
+fn _get_liquidation_price(
+    pic: &PocketIc,
+    account: Subaccount,
+    position_id: PositionId,
+) -> Option<Tick> {
+    let canister_id = _get_canister_id();
+    let Ok(WasmResult::Reply(val)) = pic.query_call(
+        canister_id,
+        Principal::anonymous(),
+        "getLiquidationPrice",
+        encode_args((account, position_id)).unwrap(),
+    ) else {
+        panic!("Liquidation price could not be gotten")
+    };
+    decode_one(&val).unwrap()
+}
+
+fn _get_position_status(
+    pic: &PocketIc,
+    account: Subaccount,
+    position_id: PositionId,
+) -> (bool, bool) {
     let canister_id = _get_canister_id();
     let Ok(WasmResult::Reply(val)) = pic.query_call(
         canister_id,
         Principal::anonymous(),
         "positionStatus",
-        encode_one(account).unwrap(),
+        encode_args((account, position_id)).unwrap(),
     ) else {
         panic!("Account could not be gotten")
     };
@@ -460,6 +2421,11 @@ fn _set_state(
         max_leveragex10,
         min_collateral,
         base_token_multiple: 1,
+        liquidation_fee: 0,
+        keeper_share: 0,
+        maintenance_margin: 5 * 100000,
+        taker_fee: 0,
+        maker_fee: 0,
     };
 
     let Ok(WasmResult::Reply(_)) = pic.update_call(
@@ -472,6 +2438,37 @@ fn _set_state(
     };
 }
 
+fn _update_state_details(
+    pic: &PocketIc,
+    caller: Principal,
+    state_details: StateDetails,
+) -> Result<(), String> {
+    let canister_id = _get_canister_id();
+
+    let Ok(WasmResult::Reply(res)) = pic.update_call(
+        canister_id,
+        caller,
+        "updateStateDetails",
+        encode_one(state_details).unwrap(),
+    ) else {
+        panic!("failed to update state details")
+    };
+
+    decode_one(&res).unwrap()
+}
+
+fn _upgrade_canister(pic: &PocketIc, admin: Principal) {
+    let wasm = fs::read(_BACKEND_WASM).expect("Wasm file not found, run 'dfx build'.");
+
+    pic.upgrade_canister(
+        _get_canister_id(),
+        wasm,
+        encode_args(()).unwrap(),
+        Some(admin),
+    )
+    .expect("failed to upgrade canister");
+}
+
 fn _setup_market(admin: Principal) -> PocketIc {
     let pic = PocketIc::new();
 
@@ -493,6 +2490,7 @@ fn _setup_market(admin: Principal) -> PocketIc {
         xrc_id: admin,
         vault_id: admin,
         collateral_decimal: 1,
+        oracle: OracleKind::Mock,
     };
 
     pic.install_canister(
@@ -1,36 +1,45 @@
 use candid::{CandidType, Decode, Encode, Principal};
-use ic_cdk::{export_candid, storage};
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk::export_candid;
 
 use ic_cdk_timers::TimerId;
 
 use sha2::{Digest, Sha256};
 
-use corelib::calc_lib::{_calc_interest, _percentage128};
-use corelib::constants::{_BASE_PRICE, _ONE_PERCENT};
+use corelib::calc_lib::{_calc_interest, _calc_liquidation_fee, _percentage128, _percentage64};
+use corelib::constants::{_BASE_PRICE, _ONE_BASIS_POINT, _ONE_PERCENT, _PRICE_DECIMAL};
+use corelib::funding_lib::{_apply_funding, _calc_funding_rate, _calc_premium};
 use corelib::order_lib::{CloseOrderParams, LimitOrder, OpenOrderParams};
 use corelib::price_lib::_equivalent;
-use corelib::swap_lib::{SwapParams, _get_best_offer};
-use corelib::tick_lib::{_def_max_tick, _tick_to_price};
+use corelib::swap_lib::{_get_best_offer, _get_offers, SwapFees, SwapParams};
+use corelib::tick_lib::{
+    _def_max_tick, _liquidation_tick, _mark_tick, _price_to_tick, _tick_to_price,
+};
 use types::{
-    FundingRateTracker, GetExchangeRateRequest, GetExchangeRateResult, MarketDetails, StateDetails,
-    TickDetails,
+    Asset, ExchangeRate, FundingConfig, FundingRateTracker, FundingRecord, GetExchangeRateRequest,
+    GetExchangeRateResult, MarkPrice, MarketDetails, OracleDetails, OracleError, OracleKind,
+    PriceAccumulator, PriceFeed, SpotPrice, StateDetails, TickDetails,
 };
 
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::fmt::Debug;
+use std::ops::Bound::{Excluded, Included, Unbounded};
 use std::time::Duration;
 
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{storable::Bound, Storable};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, StableVec};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, StableLog};
 
 type Time = u64;
 type Amount = u128;
 type Tick = u64;
 type Subaccount = [u8; 32];
+type PositionId = u64;
+type PositionKey = (Subaccount, PositionId);
+type TickOrderKey = (Tick, Subaccount, PositionId);
+type TakerFees = (Amount, Amount);
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -46,18 +55,91 @@ const _INTEGRALS_BITMAPS_MEMORY: MemoryId = MemoryId::new(5);
 
 const _FUNDING_RATE_TRACKER_MEMORY: MemoryId = MemoryId::new(6);
 
-const _ACCOUNTS_POSITION_MEMORY: MemoryId = MemoryId::new(7);
+// positions and error logs keyed by the account only ,migrated on upgrade (see Migrate Accounts Positions)
+const _LEGACY_ACCOUNTS_POSITION_MEMORY: MemoryId = MemoryId::new(7);
+
+const _LEGACY_ACCOUNT_ERROR_LOGS_MEMORY: MemoryId = MemoryId::new(8);
+
+// the executable limit orders accounts before the crossed ticks queue
+const _LEGACY_EXECUTABLE_ORDERS_MEMORY: MemoryId = MemoryId::new(9);
+
+const _POSITIONS_TRIGGERS_MEMORY: MemoryId = MemoryId::new(10);
+
+const _POSITION_ID_COUNTER_MEMORY: MemoryId = MemoryId::new(11);
+
+const _ACCOUNTS_OWNER_MEMORY: MemoryId = MemoryId::new(12);
+
+const _LIMIT_ORDERS_EXPIRY_MEMORY: MemoryId = MemoryId::new(13);
+
+const _LIMIT_ORDERS_RECORD_MEMORY: MemoryId = MemoryId::new(14);
+
+const _CROSSED_TICKS_QUEUE_MEMORY: MemoryId = MemoryId::new(15);
+
+const _TRADES_LOG_INDEX_MEMORY: MemoryId = MemoryId::new(16);
+
+const _TRADES_LOG_DATA_MEMORY: MemoryId = MemoryId::new(17);
+
+const _POSITIONS_HISTORY_MEMORY: MemoryId = MemoryId::new(18);
+
+const _CANDLES_MEMORY: MemoryId = MemoryId::new(19);
+
+const _PRICE_ACCUMULATOR_MEMORY: MemoryId = MemoryId::new(20);
+
+const _FUNDING_HISTORY_MEMORY: MemoryId = MemoryId::new(21);
+
+const _FUNDING_CONFIG_MEMORY: MemoryId = MemoryId::new(22);
 
-const _ACCOUNT_ERROR_LOGS_MEMORY: MemoryId = MemoryId::new(8);
+const _ORACLE_DETAILS_MEMORY: MemoryId = MemoryId::new(23);
 
-const _EXECUTABLE_ORDERS_MEMORY: MemoryId = MemoryId::new(9);
+const _PRICE_FEED_MEMORY: MemoryId = MemoryId::new(24);
+
+const _MARK_PRICE_MEMORY: MemoryId = MemoryId::new(25);
+
+const _ACCOUNTS_POSITION_MEMORY: MemoryId = MemoryId::new(26);
+
+const _ACCOUNT_ERROR_LOGS_MEMORY: MemoryId = MemoryId::new(27);
+
+const _SWEEPER_CONFIG_MEMORY: MemoryId = MemoryId::new(28);
 
 const ONE_SECOND: u64 = 1_000_000_000;
 
+const ONE_MINUTE: u64 = 60 * ONE_SECOND;
+
 const ONE_HOUR: u64 = 3_600_000_000_000;
 
+const ONE_DAY: u64 = 24 * ONE_HOUR;
+
 const _DEFAULT_SWAP_SLIPPAGE: u64 = 30_000; //0.3%
 
+const _SWEEP_INTERVAL: u64 = 10 * ONE_SECOND;
+
+const _DEFAULT_SWEEP_BATCH_SIZE: u64 = 20;
+
+const _MAX_TRADING_FEE: u64 = 100 * _ONE_BASIS_POINT; //1%
+
+const _MAX_FUNDING_RATE: u64 = 10 * _ONE_PERCENT; //10%
+
+/// the max number of times fetching the spot price is retried within a funding interval
+const _MAX_ORACLE_RETRIES: u32 = 3;
+
+/// the delay before the first retry ,doubled on every subsequent retry
+const _ORACLE_RETRY_DELAY: u64 = 30 * ONE_SECOND;
+
+/// the max decimals of a pushed price ,prices are scaled by 10^decimals against the mark price without overflowing
+const _MAX_ORACLE_DECIMALS: u32 = 18;
+
+const _EXPIRY_BATCH_SIZE: usize = 20;
+
+const _EXECUTION_INSTRUCTION_BUDGET: u64 = 5_000_000_000;
+
+const _MAX_ORDER_BOOK_LEVELS: u64 = 100;
+
+const _ORDER_BOOK_RANGE: u64 = 50 * _ONE_PERCENT; //50%
+
+const _MAX_HISTORY_PAGE_SIZE: u64 = 100;
+
+const _MAX_CANDLES: usize = 500;
+
 thread_local! {
 
     static MEMORY_MANAGER:RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(MemoryManager::init(DefaultMemoryImpl::default())) ;
@@ -82,6 +164,41 @@ thread_local! {
         s.borrow().get(_FUNDING_RATE_TRACKER_MEMORY)
     }),FundingRateTracker::default()).unwrap());
 
+    /// Funding Config
+    ///
+    /// the parameters for calculating the funding rate ,set by the admin
+    static FUNDING_CONFIG:RefCell<StableCell<FundingConfig,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_FUNDING_CONFIG_MEMORY)
+    }),FundingConfig::default()).unwrap());
+
+    /// Oracle Details
+    ///
+    /// the last good spot price fetched from the XRC canister and the failures since
+    static ORACLE_DETAILS:RefCell<StableCell<OracleDetails,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_ORACLE_DETAILS_MEMORY)
+    }),OracleDetails::default()).unwrap());
+
+    /// Price Feed
+    ///
+    /// the price pushed by the admin or the price keeper
+    static PRICE_FEED:RefCell<StableCell<PriceFeed,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_PRICE_FEED_MEMORY)
+    }),PriceFeed::default()).unwrap());
+
+    /// Mark Price
+    ///
+    /// the mark tick utilised for the pnl and liquidation of positions and the funding rate premium
+    static MARK_PRICE:RefCell<StableCell<MarkPrice,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_MARK_PRICE_MEMORY)
+    }),MarkPrice::default()).unwrap());
+
+    /// Price Accumulator
+    ///
+    /// time weighted accumulator of the current tick utilised for the funding rate premium
+    static PRICE_ACCUMULATOR:RefCell<StableCell<PriceAccumulator,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_PRICE_ACCUMULATOR_MEMORY)
+    }),PriceAccumulator::default()).unwrap());
+
     static TICKS_DETAILS:RefCell<StableBTreeMap<Tick,TickDetails,Memory>>= RefCell::new(StableBTreeMap::init(MEMORY_MANAGER.with_borrow(
         |mem|{mem.get(_TICKS_DETAILS_MEMORY)})));
 
@@ -89,24 +206,118 @@ thread_local! {
         mem.get(_INTEGRALS_BITMAPS_MEMORY)
     }))) ;
 
-    static ACCOUNTS_POSITION:RefCell<StableBTreeMap<Subaccount,PositionDetails,Memory>> = RefCell::new(
+    static ACCOUNTS_POSITION:RefCell<StableBTreeMap<PositionKey,PositionDetails,Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
         s.borrow().get(_ACCOUNTS_POSITION_MEMORY)
     })));
 
 
-    static ACCOUNTS_ERROR_LOGS:RefCell<StableBTreeMap<Subaccount,PositionUpdateErrorLog,Memory>> = RefCell::new(
+    /// Accounts Error Logs
+    ///
+    /// the failed vault notifications of each account keyed by the account and the error index
+    static ACCOUNTS_ERROR_LOGS:RefCell<StableBTreeMap<(Subaccount,u64),PositionUpdateErrorLog,Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
         s.borrow().get(_ACCOUNT_ERROR_LOGS_MEMORY)
     })));
 
-    static EXECUTABLE_LIMIT_ORDERS_ACCOUNTS:RefCell<StableVec<Subaccount,Memory>> = RefCell::new(StableVec::new(MEMORY_MANAGER.with(|s|{
-        s.borrow().get(_EXECUTABLE_ORDERS_MEMORY)
-    })).unwrap());
+    /// Crossed Ticks Queue
+    ///
+    /// the limit orders at crossed ticks waiting to be executed ,recorded as they were when the tick was crossed
+    static CROSSED_TICKS_QUEUE:RefCell<StableBTreeMap<TickOrderKey,(),Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_CROSSED_TICKS_QUEUE_MEMORY)
+    })));
+
+    /// Positions Triggers
+    ///
+    /// stop loss and take profit triggers of market positions keyed by the trigger tick ,the account and the position id
+    /// and valued by the position owner
+    static POSITIONS_TRIGGERS:RefCell<StableBTreeMap<TickOrderKey,(Principal,bool),Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_POSITIONS_TRIGGERS_MEMORY)
+    })));
+
+    /// Position Id Counter
+    ///
+    /// the id to be given to the next opened position
+    static POSITION_ID_COUNTER:RefCell<StableCell<PositionId,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_POSITION_ID_COUNTER_MEMORY)
+    }),0).unwrap());
+
+    /// Accounts Owner
+    ///
+    /// the principal owning each account ,utilised for settling positions without the owner as caller i.e liquidation sweeps
+    static ACCOUNTS_OWNER:RefCell<StableBTreeMap<Subaccount,Principal,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_ACCOUNTS_OWNER_MEMORY)
+    })));
+
+    /// Limit Orders Expiry
+    ///
+    /// good till time limit positions keyed by the expiry time ,the account and the position id
+    /// and valued by the position owner
+    static LIMIT_ORDERS_EXPIRY:RefCell<StableBTreeMap<(Time,Subaccount,PositionId),Principal,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_LIMIT_ORDERS_EXPIRY_MEMORY)
+    })));
+
+    /// Trades Log
+    ///
+    /// append only log of the fills of every executed swap
+    static TRADES_LOG:RefCell<StableLog<TradeRecord,Memory,Memory>> = RefCell::new(
+        StableLog::init(MEMORY_MANAGER.with_borrow(|s|{s.get(_TRADES_LOG_INDEX_MEMORY)}),
+        MEMORY_MANAGER.with_borrow(|s|{s.get(_TRADES_LOG_DATA_MEMORY)})).unwrap());
+
+    /// Positions History
+    ///
+    /// the record of every position opened by an account keyed by the account and the position id ,kept after the position is closed
+    static POSITIONS_HISTORY:RefCell<StableBTreeMap<PositionKey,PositionRecord,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_POSITIONS_HISTORY_MEMORY)
+    })));
+
+    /// Candles
+    ///
+    /// OHLCV candles of the executed swaps keyed by the candle interval (in nanoseconds) and the candle start time
+    static CANDLES:RefCell<StableBTreeMap<(u64,Time),Candle,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_CANDLES_MEMORY)
+    })));
+
+    /// Funding History
+    ///
+    /// the funding rate set at each funding rate settlement keyed by the settlement time
+    static FUNDING_HISTORY:RefCell<StableBTreeMap<Time,FundingRecord,Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_FUNDING_HISTORY_MEMORY)
+    })));
 
     static PENDING_TIMER:RefCell<TimerId>= RefCell::new(TimerId::default());
 
-    static LIMIT_ORDERS_RECORD :RefCell<HashMap<Tick,Vec<Subaccount>>> = RefCell::new(HashMap::new());
+    static LIQUIDATION_SWEEPER:RefCell<LiquidationSweeper> = RefCell::new(LiquidationSweeper {
+        timer_id: TimerId::default(),
+        sweep_scheduled: false,
+    });
+
+    /// Expiry Timer
+    ///
+    /// the expiry time and the timer id of the single armed limit orders expiry timer ,default if no timer is armed
+    static EXPIRY_TIMER:RefCell<(Time,TimerId)> = RefCell::new((0, TimerId::default()));
+
+    /// Sweeper Config
+    ///
+    /// the stored state of the liquidation sweeper ,restored on upgrade
+    static SWEEPER_CONFIG:RefCell<StableCell<SweeperConfig,Memory>> = RefCell::new(StableCell::new(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_SWEEPER_CONFIG_MEMORY)
+    }),SweeperConfig::default()).unwrap());
+
+    /// Limit Orders Record
+    ///
+    /// the limit positions resting at each tick keyed by the tick ,the account and the position id
+    static LIMIT_ORDERS_RECORD:RefCell<StableBTreeMap<TickOrderKey,(),Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|s|{
+        s.borrow().get(_LIMIT_ORDERS_RECORD_MEMORY)
+    })));
 
 }
 
@@ -128,6 +339,36 @@ fn get_state_details() -> StateDetails {
     _get_state_details()
 }
 
+/// Get Funding Config
+///
+/// Returns the parameters for calculating the funding rate
+#[ic_cdk::query(name = "getFundingConfig")]
+fn get_funding_config() -> FundingConfig {
+    _get_funding_config()
+}
+
+/// Get Mark Price
+///
+/// Returns the mark tick utilised for valuing positions and the ticks it is derived from
+#[ic_cdk::query(name = "getMarkPrice")]
+fn get_mark_price() -> MarkPrice {
+    MARK_PRICE.with_borrow(|reference| *reference.get())
+}
+
+/// Get Oracle Health
+///
+/// Returns
+///  - Oracle Details :The last good spot price ,its timestamp and the failures since
+///  - Stale :true if the last good spot price is older than the max oracle staleness
+#[ic_cdk::query(name = "getOracleHealth")]
+fn get_oracle_health() -> (OracleDetails, bool) {
+    let oracle_details = _get_oracle_details();
+
+    let stale = _is_oracle_stale(&oracle_details, &_get_funding_config());
+
+    (oracle_details, stale)
+}
+
 /// Get Market Details
 ///
 ///  Returns the Market Details
@@ -157,10 +398,36 @@ fn get_user_account(_user: Principal) -> Subaccount {
 
 /// Get Account Position
 ///
-/// Gets an account position or panics if account has no position
+/// Gets an account position or panics if account has no position with that id
 #[ic_cdk::query(name = "getAccountPosition")]
-fn get_account_position(_account: Subaccount) -> PositionDetails {
-    return _get_account_position(&_account);
+fn get_account_position(_account: Subaccount, _position_id: PositionId) -> PositionDetails {
+    _settle_crossed_tick_order((_account, _position_id));
+
+    _get_account_position(&(_account, _position_id))
+}
+
+/// Get Account Positions
+///
+/// Gets all positions of an account with their position ids
+#[ic_cdk::query(name = "getAccountPositions")]
+fn get_account_positions(_account: Subaccount) -> Vec<(PositionId, PositionDetails)> {
+    let position_ids: Vec<PositionId> = ACCOUNTS_POSITION.with_borrow(|reference| {
+        reference
+            .range((_account, 0)..=(_account, PositionId::MAX))
+            .map(|((_, position_id), _)| position_id)
+            .collect()
+    });
+
+    for position_id in position_ids {
+        _settle_crossed_tick_order((_account, position_id));
+    }
+
+    ACCOUNTS_POSITION.with_borrow(|reference| {
+        reference
+            .range((_account, 0)..=(_account, PositionId::MAX))
+            .map(|((_, position_id), position)| (position_id, position))
+            .collect()
+    })
 }
 
 /// Try Close Function
@@ -171,18 +438,306 @@ fn get_account_position(_account: Subaccount) -> PositionDetails {
 /// - Is Fully Filled: true if position is limit order has been fully filled
 /// - Is Partially Filled: true is position is partially filled
 #[ic_cdk::query(name = "positionStatus")]
-fn position_status(_account: Subaccount) -> (bool, bool) {
-    return _convert_account_limit_position(_account);
+fn position_status(_account: Subaccount, _position_id: PositionId) -> (bool, bool) {
+    let (is_fully_filled, is_partially_filled, _, _) =
+        _convert_account_limit_position((_account, _position_id));
+
+    (is_fully_filled, is_partially_filled)
+}
+
+/// Get Liquidation Price
+///
+/// Gets the tick at which a market position becomes liquidatable
+///
+/// Returns
+///  - Liquidation Tick :A long position is liquidatable at or below this tick and a short position at or above it ,
+/// none if the position is a limit position or has no debt
+///
+/// Note :The liquidation tick moves as interest accrues on the position debt
+#[ic_cdk::query(name = "getLiquidationPrice")]
+fn get_liquidation_price(_account: Subaccount, _position_id: PositionId) -> Option<Tick> {
+    _settle_crossed_tick_order((_account, _position_id));
+
+    let position = _get_account_position(&(_account, _position_id));
+
+    let StateDetails {
+        maintenance_margin, ..
+    } = _get_state_details();
+
+    _get_position_liquidation_tick(position, maintenance_margin)
+}
+
+/// Get Execution Queue Depth
+///
+/// Returns
+///  - Crossed Ticks :The number of crossed ticks whose limit orders are waiting to be executed
+///  - Pending Orders :The number of limit orders at those ticks waiting to be converted into market positions
+#[ic_cdk::query(name = "getExecutionQueueDepth")]
+fn get_execution_queue_depth() -> (u64, u64) {
+    let mut crossed_ticks: Vec<Tick> = CROSSED_TICKS_QUEUE
+        .with_borrow(|reference| reference.iter().map(|((tick, _, _), _)| tick).collect());
+
+    let pending_orders = crossed_ticks.len() as u64;
+
+    crossed_ticks.dedup();
+
+    (crossed_ticks.len() as u64, pending_orders)
+}
+
+/// Quote Swap
+///
+/// Simulates a swap against the current order book without executing it
+///
+/// Params
+///  - Amount :The amount to swap ,in the quote token for a buy and in the perp asset for a sell
+///  - Buy :The swap direction
+///  - Max Tick :The max executing tick ,if set to none or outside the required range default max tick is used
+///
+/// Returns
+///  - Swap Quote :The expected fill of the swap
+#[ic_cdk::query(name = "quoteSwap")]
+fn quote_swap(amount: Amount, buy: bool, max_tick: Option<Tick>) -> SwapQuote {
+    let StateDetails { current_tick, .. } = _get_state_details();
+
+    let stopping_tick = max_or_default_max(max_tick, current_tick, buy);
+
+    _quote_swap(amount, buy, current_tick, stopping_tick)
+}
+
+/// Quote Open Position
+///
+/// Simulates the swap of a market order opening a position without opening it
+///
+/// Params
+///  - Collateral Value :The collateral of the position
+///  - Leverage X 10 :The leverage of the position multiplied by ten
+///  - Long :The position direction
+///  - Max Tick :The max executing tick ,if set to none or outside the required range default max tick is used
+///
+/// Returns
+///  - Swap Quote :The expected fill of the position ,amounts for a short position are in the perp asset
+///
+/// Note
+///  - The quote does not check the vault liquidity for the position debt
+#[ic_cdk::query(name = "quoteOpenPosition")]
+fn quote_open_position(
+    _collateral_value: Amount,
+    _leveragex10: u8,
+    _long: bool,
+    _max_tick: Option<Tick>,
+) -> Result<SwapQuote, String> {
+    let StateDetails {
+        current_tick,
+        max_leveragex10,
+        min_collateral,
+        ..
+    } = _get_state_details();
+
+    if _leveragex10 < 10 || _leveragex10 >= max_leveragex10 || _collateral_value < min_collateral {
+        return Err("Max leverage exceeded or collateral is too small".to_string());
+    }
+
+    if _leveragex10 > 10 && _leverage_paused_by_oracle() {
+        return Err("Oracle price is stale".to_string());
+    }
+
+    let position_value = (u128::from(_leveragex10) * _collateral_value) / 10;
+
+    let stopping_tick = max_or_default_max(_max_tick, current_tick, _long);
+
+    if _long {
+        return Ok(_quote_swap(
+            position_value,
+            true,
+            current_tick,
+            stopping_tick,
+        ));
+    }
+
+    // short positions swap the perp asset equivalent of the position value at the best buy offer
+    let order_size = match get_best_offer(false, current_tick, Some(stopping_tick)) {
+        Some(tick) => _equivalent(position_value, _tick_to_price(tick), true),
+        None => _equivalent(position_value, _tick_to_price(current_tick), true),
+    };
+
+    Ok(_quote_swap(order_size, false, current_tick, stopping_tick))
+}
+
+/// Get Trade History
+///
+/// Returns a page of the trades log ,oldest trades first
+///
+/// Params
+///  - Start :The index of the first trade in the log
+///  - Limit :The max number of trades to return (capped at 100)
+#[ic_cdk::query(name = "getTradeHistory")]
+fn get_trade_history(start: u64, limit: u64) -> Vec<TradeRecord> {
+    let limit = limit.min(_MAX_HISTORY_PAGE_SIZE);
+
+    TRADES_LOG.with_borrow(|reference| {
+        (start..reference.len().min(start.saturating_add(limit)))
+            .filter_map(|index| reference.get(index))
+            .collect()
+    })
+}
+
+/// Get Account Positions History
+///
+/// Returns a page of the positions opened by an account including closed positions
+///
+/// Params
+///  - Account :The account
+///  - Start After :The position id after which the page starts ,none to start from the first position
+///  - Limit :The max number of positions to return (capped at 100)
+#[ic_cdk::query(name = "getAccountPositionsHistory")]
+fn get_account_positions_history(
+    _account: Subaccount,
+    _start_after: Option<PositionId>,
+    _limit: u64,
+) -> Vec<(PositionId, PositionRecord)> {
+    let start = match _start_after {
+        Some(position_id) => Excluded((_account, position_id)),
+        None => Included((_account, 0)),
+    };
+
+    POSITIONS_HISTORY.with_borrow(|reference| {
+        reference
+            .range((start, Included((_account, PositionId::MAX))))
+            .take(_limit.min(_MAX_HISTORY_PAGE_SIZE) as usize)
+            .map(|((_, position_id), position_record)| (position_id, position_record))
+            .collect()
+    })
+}
+
+/// Get Candles
+///
+/// Returns the OHLCV candles of an interval starting within a time range
+///
+/// Params
+///  - Interval :The candle interval
+///  - From :The start of the time range
+///  - To :The end of the time range
+///
+/// Returns
+///  - Candles :The start time of each candle and the candle ,oldest first (capped at 500)
+///
+/// Note:Intervals without any executed swap have no candle
+#[ic_cdk::query(name = "getCandles")]
+fn get_candles(interval: CandleInterval, from: Time, to: Time) -> Vec<(Time, Candle)> {
+    let duration = interval.duration();
+
+    let from = from - (from % duration);
+
+    if from > to {
+        return Vec::new();
+    }
+
+    CANDLES.with_borrow(|reference| {
+        reference
+            .range((duration, from)..=(duration, to))
+            .take(_MAX_CANDLES)
+            .map(|((_, start_time), candle)| (start_time, candle))
+            .collect()
+    })
+}
+
+/// Get Funding History
+///
+/// Returns the funding rates set at past funding rate settlements ,oldest first
+///
+/// Params
+///  - From :The time from which to return settlements
+///  - Limit :The max number of settlements to return (capped at 100)
+#[ic_cdk::query(name = "getFundingHistory")]
+fn get_funding_history(from: Time, limit: u64) -> Vec<(Time, FundingRecord)> {
+    FUNDING_HISTORY.with_borrow(|reference| {
+        reference
+            .range(from..)
+            .take(limit.min(_MAX_HISTORY_PAGE_SIZE) as usize)
+            .collect()
+    })
+}
+
+/// Get Order Book
+///
+/// Returns a page of the aggregated liquidity of limit orders on one side of the order book
+///
+/// Params
+///  - Levels :The max number of ticks to return (capped at 100)
+///  - Side :The side of the order book
+///  - Start Tick :The tick to start the page from ,none to start from the best offer
+///
+/// Returns
+///  - Order Book Page :The ticks ordered from the best offer and the start tick of the next page
+///
+/// Note
+///  - Only ticks within 50% of the current tick are returned
+///  - A start tick on the wrong side of the current tick starts the page from the current tick
+#[ic_cdk::query(name = "getOrderBook")]
+fn get_order_book(levels: u64, side: OrderBookSide, start_tick: Option<Tick>) -> OrderBookPage {
+    let StateDetails { current_tick, .. } = _get_state_details();
+
+    // asks are filled by buy orders and bids by sell orders
+    let buy = matches!(side, OrderBookSide::Asks);
+
+    let range = _percentage64(_ORDER_BOOK_RANGE, current_tick);
+
+    let stopping_tick = if buy {
+        current_tick + range
+    } else {
+        current_tick - range
+    };
+
+    let start_tick = match start_tick {
+        Some(tick) if (buy && tick > current_tick) || (!buy && tick < current_tick) => tick,
+        _ => current_tick,
+    };
+
+    let (offers, next_tick) = TICKS_DETAILS.with_borrow(|ticks_details| {
+        INTEGRAL_BITMAPS.with_borrow(|integrals_bitmaps| {
+            _get_offers(
+                buy,
+                start_tick,
+                stopping_tick,
+                levels.min(_MAX_ORDER_BOOK_LEVELS) as usize,
+                integrals_bitmaps,
+                ticks_details,
+            )
+        })
+    });
+
+    let levels = offers
+        .into_iter()
+        .map(|(tick, liquidity)| OrderBookLevel {
+            tick,
+            price: _tick_to_price(tick),
+            liquidity,
+        })
+        .collect();
+
+    OrderBookPage { levels, next_tick }
 }
 
 #[ic_cdk::query(name = "getPositionPNL")]
-fn get_position_pnl(position: PositionDetails) -> i64 {
+fn get_position_pnl(mut position: PositionDetails) -> i64 {
+    // a filled limit position is valued as the market position it is converted into ,
+    // the order is closed only within this query
+    if let PositionOrderType::Limit(order) = position.order_type {
+        let (amount_out, amount_remaining) = _close_order(&order);
+
+        if amount_out > 0 {
+            let amount_remaining_value = _limit_order_remaining_value(&position, amount_remaining);
+
+            _convert_limit_position(&mut position, amount_remaining_value);
+        }
+    }
+
     let (pnl, _) = _calculate_position_pnl_and_net_debt_value(position);
     return pnl;
 }
 /// Open PositionDetails function
 ///
-/// opens a new position for user (given that user has no existing position)
+/// opens a new position for user ,an account can hold several positions at once each identified by a position id
 ///
 /// Params
 /// - Collateral Value :: The amount in collatreal token to utilise as collateral
@@ -190,13 +745,21 @@ fn get_position_pnl(position: PositionDetails) -> i64 {
 /// - Leverage :: The leverage for the required position multiplies by 10 i.e a 1.5 levarage is 1.5 * 10 = 15
 /// - Long :: Indicating if its a long position or not ,true if long and false otherwise
 /// - Order Type :: the type of order to create
+/// - Stop Loss Tick :: tick at which a market position is closed to stop further loss ,set to none for no stop loss
+/// - Take Profit Tick :: tick at which a market position is closed to take profit ,set to none for no take profit
+/// - Order Flags :: the post only ,reduce only and time in force flags of the order (see OrderFlags)
 ///
 /// Returns
+///  - Position Id:the id of the position within the account
 ///  - Position:the details of the position
 ///
 /// Note
 ///  - If Order type is a limit order ,max tick coinsides with the reference tick for the limit order
-///  - ANON TICKS are for future purposes and have no effect for now
+///  - A limit order whose reference tick crosses the best offer is executed as a market order up to the reference tick ,unless it is post only
+///  - An immediate or cancel or fill or kill limit order is executed as a market order up to the reference tick
+///  - A good till time limit position is closed once its expiry time is reached ,any filled part is kept as a market position
+///  - Stop loss and take profit ticks can only be attached to market positions
+///  - A reduce only order returns the id and the remaining details of the reduced position
 #[ic_cdk::update(name = "openPosition")]
 async fn open_position(
     _collateral_value: Amount,
@@ -204,17 +767,16 @@ async fn open_position(
     _order_type: OrderType,
     _leveragex10: u8,
     _max_tick: Option<Tick>,
-    _anon_tick1: Tick,
-    _anon_tick2: Tick,
-) -> Result<PositionDetails, String> {
+    _stop_loss_tick: Option<Tick>,
+    _take_profit_tick: Option<Tick>,
+    _order_flags: OrderFlags,
+) -> Result<(PositionId, PositionDetails), String> {
     let user = ic_cdk::caller();
 
     let account = user._to_subaccount();
 
-    let failed_initial_check = _has_position_or_pending_error_log(&account);
-
-    if failed_initial_check {
-        return Err("Account has pending error or unclosed position ".to_string());
+    if _has_pending_error_log(&account) {
+        return Err("Account has pending error".to_string());
     }
 
     let mut state_details = _get_state_details();
@@ -229,63 +791,144 @@ async fn open_position(
         return Err("Max leverage exceeded or collateral is too small".to_string());
     }
 
-    let market_details = _get_market_details();
+    if _leveragex10 > 10 && _leverage_paused_by_oracle() {
+        return Err("Oracle price is stale".to_string());
+    }
 
-    let vault = Vault::init(market_details.vault_id);
+    let time_in_force = match (_order_flags.time_in_force, _order_type) {
+        (Some(TimeInForce::Gtc), OrderType::Market)
+        | (Some(TimeInForce::Gtt(_)), OrderType::Market) => {
+            return Err("Market orders can not rest in the book".to_string());
+        }
+        (Some(TimeInForce::Gtt(expiry)), _) if expiry <= ic_cdk::api::time() => {
+            return Err("Expiry time must be in the future".to_string());
+        }
+        (Some(time_in_force), _) => time_in_force,
+        (None, OrderType::Limit) => TimeInForce::Gtc,
+        (None, OrderType::Market) => TimeInForce::Ioc,
+    };
 
-    // levarage is always given as a multiple of ten
-    let debt_value = (u128::from(_leveragex10 - 10) * _collateral_value) / 10;
+    if let Some(position_id) = _order_flags.reduce_only {
+        if _stop_loss_tick.is_some() || _take_profit_tick.is_some() {
+            return Err("Triggers can not be attached to reduce only orders".to_string());
+        }
 
-    // Checks if user has sufficient balance and vault contains free liquidity greater or equal to debt_value and then calculate interest rate
+        if time_in_force == TimeInForce::Fok {
+            return Err("Reduce only orders can not be fill or kill".to_string());
+        }
 
-    let (valid, interest_rate) = vault
-        .create_position_validity_check(user, _collateral_value, debt_value)
-        .await;
+        let order_value = (u128::from(_leveragex10) * _collateral_value) / 10;
 
-    if valid == false {
-        return Err("Not enough liquidity for debt".to_string());
-    };
+        return _open_reduce_only_order(
+            user,
+            (account, position_id),
+            _long,
+            _order_type,
+            order_value,
+            _max_tick,
+        );
+    }
 
     let stopping_tick = max_or_default_max(_max_tick, state_details.current_tick, _long);
 
-    match _open_position(
-        account,
-        _long,
-        _order_type,
-        _collateral_value,
-        debt_value,
-        interest_rate,
+    if _order_flags.post_only {
+        if !time_in_force.rests_in_book() {
+            return Err("Only resting limit orders can be post only".to_string());
+        }
+
+        if _crosses_best_offer(_long, stopping_tick, state_details.current_tick) {
+            return Err("Post only order would cross the best offer".to_string());
+        }
+    }
+
+    if _stop_loss_tick.is_some() || _take_profit_tick.is_some() {
+        if let OrderType::Limit = _order_type {
+            return Err("Triggers can only be attached to market positions".to_string());
+        }
+
+        if !_valid_triggers(
+            _long,
+            state_details.current_tick,
+            _stop_loss_tick,
+            _take_profit_tick,
+        ) {
+            return Err("Invalid stop loss or take profit tick".to_string());
+        }
+    }
+
+    let market_details = _get_market_details();
+
+    let vault = Vault::init(market_details.vault_id);
+
+    // levarage is always given as a multiple of ten
+    let debt_value = (u128::from(_leveragex10 - 10) * _collateral_value) / 10;
+
+    // Checks if user has sufficient balance and vault contains free liquidity greater or equal to debt_value and then calculate interest rate
+
+    let (valid, interest_rate) = vault
+        .create_position_validity_check(user, _collateral_value, debt_value)
+        .await;
+
+    if valid == false {
+        return Err("Not enough liquidity for debt".to_string());
+    };
+
+    let position_key = (account, _next_position_id());
+
+    _insert_account_owner(account, user);
+
+    match _open_position(
+        position_key,
+        _long,
+        _order_type,
+        time_in_force,
+        _collateral_value,
+        debt_value,
+        interest_rate,
         state_details.current_tick,
         stopping_tick,
     ) {
-        Some((position, resulting_tick, crossed_ticks)) => {
+        Some((mut position, resulting_tick, crossed_ticks, (taker_fee, trading_fee))) => {
             // update current tick
             state_details.current_tick = resulting_tick;
 
             _set_state_details(state_details);
 
-            if let OrderType::Limit = _order_type {
-                store_tick_order(stopping_tick, account);
+            if let PositionOrderType::Limit(_) = position.order_type {
+                store_tick_order(stopping_tick, position_key);
+
+                if let TimeInForce::Gtt(expiry) = time_in_force {
+                    _insert_limit_order_expiry(expiry, position_key, user);
+                }
             } else {
                 _schedule_execution_for_ticks_orders(crossed_ticks);
 
-                if position.debt_value != debt_value
-                    || _collateral_value != position.collateral_value
-                {
-                    let un_used_collateral = _collateral_value - position.collateral_value;
-                    vault.manage_position_update(
+                if _stop_loss_tick.is_some() || _take_profit_tick.is_some() {
+                    _set_position_triggers(
                         user,
-                        un_used_collateral,
-                        ManageDebtParams::init(
-                            debt_value,
-                            debt_value,
-                            debt_value - position.debt_value,
-                        ),
+                        position_key,
+                        &mut position,
+                        _stop_loss_tick,
+                        _take_profit_tick,
                     );
                 }
+
+                let un_used_collateral = _collateral_value - position.collateral_value - taker_fee;
+
+                let mut manage_debt_params = ManageDebtParams::init(
+                    debt_value,
+                    debt_value,
+                    debt_value - position.debt_value,
+                );
+
+                manage_debt_params.trading_fee = trading_fee;
+
+                if un_used_collateral != 0 || manage_debt_params.requires_update() {
+                    vault.manage_position_update(user, un_used_collateral, manage_debt_params);
+                }
             }
 
-            return Ok(position);
+            Ok((position_key.1, position))
         }
         None => {
             // send back
@@ -295,6 +938,10 @@ async fn open_position(
                 ManageDebtParams::init(debt_value, debt_value, debt_value),
             );
 
+            if time_in_force == TimeInForce::Fok {
+                return Err("Fill or kill order could not be fully filled".to_string());
+            }
+
             return Err("Failed to open position".to_string());
         }
     }
@@ -304,6 +951,10 @@ async fn open_position(
 ///
 /// Closes user position and sends back collateral
 ///
+/// Params
+///  - Position Id :: The id of the position to close
+///  - Max Tick :: max executing tick ,if set to none or set outside the required range ,default max tick is used
+///
 /// Returns
 ///  - Profit :The amount to send to position owner
 ///
@@ -313,1368 +964,3879 @@ async fn open_position(
 ///  - If not filled at all ,the collateral is sent back and the debt fully reapid without any interest
 ///  - If it is partially filled ,the position_type is converted into a market position with the amount filled as the entire position value and the ampount remaining is sent back    
 #[ic_cdk::update(name = "closePosition")]
-async fn close_position(_max_tick: Option<Tick>) -> Amount {
+async fn close_position(_position_id: PositionId, _max_tick: Option<Tick>) -> Amount {
     let user = ic_cdk::caller();
 
-    let account = user._to_subaccount();
-
-    let mut position = _get_account_position(&account);
+    let position_key = (user._to_subaccount(), _position_id);
 
-    let market_details = _get_market_details();
+    _settle_account_position(position_key);
 
-    let vault = Vault::init(market_details.vault_id);
+    let mut position = _get_account_position(&position_key);
 
     match position.order_type {
         PositionOrderType::Market => {
-            let mut state_details = _get_state_details();
+            let profit = _close_market_position(user, position_key, &mut position, _max_tick, true);
 
-            let current_tick = state_details.current_tick;
+            _store_market_position(position_key, position);
 
-            let stopping_tick = max_or_default_max(_max_tick, current_tick, !position.long);
+            profit
+        }
+        PositionOrderType::Limit(_) => _close_limit_position(user, position_key, &mut position),
+    }
+}
 
-            let (collateral_value, resulting_tick, crossed_ticks, manage_debt_params) = if position
-                .long
-            {
-                _close_market_long_position(account, &mut position, current_tick, stopping_tick)
-            } else {
-                _close_market_short_position(account, &mut position, current_tick, stopping_tick)
-            };
+/// Close Limit Position
+///
+/// Closes the limit order of a limit position ,refunds the unfilled collateral and debt to the vault
+/// and converts any filled part into a market position
+///
+/// Params
+///  - User :The owner of the position
+///  - Position Key :The account and the id of the position
+///  - Position :The limit position to close
+///
+/// Returns
+///  - Removed Collateral :The collateral sent back to the position owner
+fn _close_limit_position(
+    user: Principal,
+    position_key: PositionKey,
+    position: &mut PositionDetails,
+) -> Amount {
+    let market_details = _get_market_details();
 
-            state_details.current_tick = resulting_tick;
+    let vault = Vault::init(market_details.vault_id);
 
-            _set_state_details(state_details);
+    let (removed_collateral, manage_debt_params) = if position.long {
+        _close_limit_long_position(position_key, position)
+    } else {
+        _close_limit_short_position(position_key, position)
+    };
 
-            _schedule_execution_for_ticks_orders(crossed_ticks);
+    remove_tick_order(position.entry_tick, position_key);
 
-            if manage_debt_params.amount_repaid != 0 {
-                vault.manage_position_update(user, collateral_value, manage_debt_params);
-            }
+    _update_position_record(
+        position_key,
+        0,
+        removed_collateral,
+        manage_debt_params.interest_value(),
+    );
 
-            return collateral_value;
-        }
-        PositionOrderType::Limit(_) => {
-            let (removed_collateral, manage_debt_params) = if position.long {
-                _close_limit_long_position(account, &mut position)
-            } else {
-                _close_limit_short_position(account, &mut position)
-            };
+    if manage_debt_params.requires_update() {
+        vault.manage_position_update(user, removed_collateral, manage_debt_params);
+    }
 
-            remove_tick_order(position.entry_tick, account);
+    removed_collateral
+}
 
-            if manage_debt_params.amount_repaid != 0 {
-                vault.manage_position_update(user, removed_collateral, manage_debt_params);
-            }
+/// Reduce Position Function
+///
+/// Closes part of the caller's market position and keeps the rest open
+///
+/// Params
+///  - Position Id :: The id of the position to reduce
+///  - Reduction :: The part of the position to close ,either a fraction of the position or an amount of the position value
+///  - Max Tick :: max executing tick ,if set to none or set outside the required range ,default max tick is used
+///
+/// Returns
+///  - Profit :The amount sent back to the position owner for the closed part
+///
+/// Note
+///  - The closed part sells a proportional volume share and repays a matching part of the debt
+///  - Reducing by the whole position or more closes the position
+#[ic_cdk::update(name = "reducePosition")]
+fn reduce_position(
+    _position_id: PositionId,
+    _reduction: PositionReduction,
+    _max_tick: Option<Tick>,
+) -> Result<Amount, String> {
+    let user = ic_cdk::caller();
 
-            return removed_collateral;
-        }
-    };
+    let position_key = (user._to_subaccount(), _position_id);
+
+    let (profit, _) = _reduce_position(user, position_key, _reduction, _max_tick)?;
+
+    Ok(profit)
 }
 
-/// Liquidate Function
+/// Reduce Position
 ///
-/// liquidates an account's position to avoid bad debt by checking if the current leverage exceeds the max leverage
+/// Closes part of a market position ,see Reduce Position Function
 ///
-/// Note : Position is closed at the current tick
-#[ic_cdk::update(name = "liquidatePosition")]
-fn liquidate_position(_user: Principal) {
-    let account = _user._to_subaccount();
-    let state_details = _get_state_details();
+/// Returns
+///  - Profit :The amount sent back to the position owner for the closed part
+///  - Position :The remaining position ,cleared if the position was fully closed
+fn _reduce_position(
+    user: Principal,
+    position_key: PositionKey,
+    _reduction: PositionReduction,
+    _max_tick: Option<Tick>,
+) -> Result<(Amount, PositionDetails), String> {
+    _settle_account_position(position_key);
 
-    let market_details = _get_market_details();
+    let mut position = match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key))
+    {
+        Some(position) => position,
+        None => return Err("Position not found".to_string()),
+    };
 
-    let position = _get_account_position(&account);
+    if let PositionOrderType::Limit(_) = position.order_type {
+        return Err("Only market positions can be reduced".to_string());
+    }
 
-    let (to_liquidate, collateral_remaining, net_debt_value) =
-        _liquidation_status(position, state_details.max_leveragex10);
+    let fraction = match _reduction {
+        PositionReduction::Fraction(fraction) => fraction,
+        PositionReduction::Amount(amount) => {
+            let position_value = position.collateral_value + position.debt_value;
 
-    if to_liquidate {
-        let vault = Vault::init(market_details.vault_id);
+            ((amount * u128::from(100 * _ONE_PERCENT)) / position_value) as u64
+        }
+    };
 
-        let (collateral, amount_repaid) = if collateral_remaining > 0 {
-            (collateral_remaining.abs() as u128, net_debt_value)
-        } else {
-            (0, net_debt_value - (collateral_remaining.abs() as u128))
-        };
+    if fraction == 0 {
+        return Err("Reduction is too small".to_string());
+    }
 
-        let manage_debt_params =
-            ManageDebtParams::init(position.debt_value, net_debt_value, amount_repaid);
+    if fraction >= 100 * _ONE_PERCENT {
+        let profit = _close_market_position(user, position_key, &mut position, _max_tick, true);
 
-        vault.manage_position_update(_user, collateral, manage_debt_params);
+        _store_market_position(position_key, position);
 
-        _remove_account_position(&account);
+        return Ok((profit, position));
     }
+
+    let mut position_slice = _split_position(&mut position, fraction);
+
+    let profit = _close_market_position(user, position_key, &mut position_slice, _max_tick, false);
+
+    // any part of the slice that could not be closed remains within the position
+    position.funding_index = _blend_funding_index(
+        position.collateral_value + position.debt_value,
+        position.funding_index,
+        position_slice.collateral_value + position_slice.debt_value,
+        position_slice.funding_index,
+    );
+    position.collateral_value += position_slice.collateral_value;
+    position.debt_value += position_slice.debt_value;
+    position.volume_share += position_slice.volume_share;
+
+    _store_market_position(position_key, position);
+
+    Ok((profit, position))
 }
 
-/// Open PositionDetails (Private)
+/// Increase Position Function
+///
+/// Adds collateral and size to the caller's market position at the current price
 ///
-/// opens a position for user if possible
 /// Params
-///  - Account :The owner of the position
-///  - Long : Position direction ,true if long or false otherwise
-///  - Limit : true for opening a limit order and false for a market order
-///  - Collateral Value : amount of collateral asset being put in as collateral
-///  - Debt Value : The amount of collateral_asset used as debt for opening position
-///  - Interest Rate : The current interest rate for opening a position
-///  - Entry Tick : The entry tick or the current state tick for this market
-///  - Max Tick : The maximum tick to execute swap ,also seen as maximum price
+///  - Position Id :: The id of the position to increase
+///  - Collateral Value :: The amount in collateral token to add as collateral
+///  - Leverage :: The leverage for the added size multiplied by 10 ,a leverage of 10 adds size without debt
+///  - Max Tick :: max executing tick ,if set to none or set outside the required range ,default max tick is used
 ///
 /// Returns
-///  - Option containing
-///  - - Position Details :The details of the position created
-///  - - Resulting Tick :The resuting tick from swapping
-///  - - Crossed Ticks :A vector of all crossed ticks during swap
-/// Note
-///  - If position can not be opened it returns none and both collateral and debt gets refunded back and swap is reverted afterwards
+///  - Position :The updated details of the position
 ///
-fn _open_position(
-    _account: Subaccount,
-    _long: bool,
-    _order_type: OrderType,
+/// Note
+///  - The entry tick is blended with the execution tick of the added size (see Merge Positions)
+///  - Interest accrued on the existing debt is added to the position debt
+#[ic_cdk::update(name = "increasePosition")]
+async fn increase_position(
+    _position_id: PositionId,
     _collateral_value: Amount,
-    _debt_value: Amount,
-    _interest_rate: u32,
-    _current_tick: Tick,
-    _max_tick: Tick,
-) -> Option<(PositionDetails, Tick, Vec<Tick>)> {
-    match _order_type {
-        OrderType::Limit => {
-            if _long {
-                _open_limit_long_position(
-                    _account,
-                    _long,
-                    _collateral_value,
-                    _debt_value,
-                    _interest_rate,
-                    _current_tick,
-                    _max_tick,
-                )
-            } else {
-                _open_limit_short_position(
-                    _account,
-                    _long,
-                    _collateral_value,
-                    _debt_value,
-                    _interest_rate,
-                    _current_tick,
-                    _max_tick,
-                )
-            }
-        }
+    _leveragex10: u8,
+    _max_tick: Option<Tick>,
+) -> Result<PositionDetails, String> {
+    let user = ic_cdk::caller();
 
-        OrderType::Market => {
-            if _long {
-                _open_market_long_position(
-                    _account,
-                    _collateral_value,
-                    _debt_value,
-                    _interest_rate,
-                    _current_tick,
-                    _max_tick,
-                )
-            } else {
-                _open_market_short_position(
-                    _account,
-                    _collateral_value,
-                    _debt_value,
-                    _interest_rate,
-                    _current_tick,
-                    _max_tick,
-                )
-            }
-        }
-    }
-}
+    let account = user._to_subaccount();
 
-fn _open_limit_short_position(
-    _account: Subaccount,
-    _long: bool,
-    _collateral_value: Amount,
-    _debt_value: Amount,
-    _interest_rate: u32,
-    _current_tick: Tick,
-    _max_tick: Tick,
-) -> Option<(PositionDetails, Tick, Vec<Tick>)> {
-    let equivalent = |amount: Amount, tick: Tick, buy: bool| -> Amount {
-        let tick_price = _tick_to_price(tick);
-        _equivalent(amount, tick_price, buy)
-    };
+    if _has_pending_error_log(&account) {
+        return Err("Account has pending error".to_string());
+    }
 
-    let entry_tick = _max_tick;
+    let position_key = (account, _position_id);
 
-    let best_buy_offer_tick = match get_best_offer(false, _current_tick, None) {
-        Some(tick) => tick,
-        None => _def_max_tick(_current_tick, false),
-    };
+    _settle_account_position(position_key);
 
-    if entry_tick <= best_buy_offer_tick {
-        return None;
+    match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key)) {
+        Some(position) => {
+            if let PositionOrderType::Limit(_) = position.order_type {
+                return Err("Only market positions can be increased".to_string());
+            }
+        }
+        None => return Err("Position not found".to_string()),
     };
 
-    let (collateral, debt) = (
-        equivalent(_collateral_value, entry_tick, true),
-        equivalent(_debt_value, entry_tick, true),
-    );
+    let state_details = _get_state_details();
 
-    let mut order = LimitOrder::new(collateral + debt, entry_tick, _long);
+    assert!(state_details.not_paused);
 
-    _open_order(&mut order);
+    if _leveragex10 >= state_details.max_leveragex10
+        || _collateral_value < state_details.min_collateral
+    {
+        return Err("Max leverage exceeded or collateral is too small".to_string());
+    }
 
-    let position = PositionDetails {
-        long: _long,
-        entry_tick,
-        collateral_value: _collateral_value,
-        debt_value: _debt_value,
-        interest_rate: _interest_rate,
-        volume_share: 0, // not initialised yet
-        order_type: PositionOrderType::Limit(order),
-        timestamp: 0, //not initialised
-    };
+    if _leveragex10 > 10 && _leverage_paused_by_oracle() {
+        return Err("Oracle price is stale".to_string());
+    }
 
-    _insert_account_position(_account, position);
+    let market_details = _get_market_details();
 
-    let new_current_tick = get_best_offer(true, best_buy_offer_tick, Some(entry_tick)).unwrap();
+    let vault = Vault::init(market_details.vault_id);
 
-    return Some((position, new_current_tick, Vec::new()));
-}
+    let debt_value = (u128::from(_leveragex10 - 10) * _collateral_value) / 10;
 
-fn _open_limit_long_position(
-    _account: Subaccount,
-    _long: bool,
-    _collateral_value: Amount,
-    _debt_value: Amount,
-    _interest_rate: u32,
-    _current_tick: Tick,
-    _max_tick: Tick,
-) -> Option<(PositionDetails, Tick, Vec<Tick>)> {
-    let entry_tick = _max_tick;
+    let (valid, interest_rate) = vault
+        .create_position_validity_check(user, _collateral_value, debt_value)
+        .await;
 
-    let best_sell_offer_tick = match get_best_offer(true, _current_tick, None) {
-        Some(tick) => tick,
-        None => _def_max_tick(_current_tick, true),
+    if !valid {
+        return Err("Not enough liquidity for debt".to_string());
     };
 
-    if entry_tick >= best_sell_offer_tick {
-        return None;
-    }
-    let (collateral, debt) = (_collateral_value, _debt_value);
-
-    let mut order = LimitOrder::new(collateral + debt, entry_tick, true);
-
-    _open_order(&mut order);
+    // position could have been closed while awaiting vault
+    let mut position = match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key))
+    {
+        Some(position) => position,
+        None => {
+            vault.manage_position_update(
+                user,
+                _collateral_value,
+                ManageDebtParams::init(debt_value, debt_value, debt_value),
+            );
 
-    let position = PositionDetails {
-        long: true,
-        entry_tick,
-        collateral_value: _collateral_value,
-        debt_value: _debt_value,
-        interest_rate: _interest_rate,
-        volume_share: 0, // not initialised yet
-        order_type: PositionOrderType::Limit(order),
-        timestamp: 0, //not initialised
+            return Err("Position not found".to_string());
+        }
     };
 
-    _insert_account_position(_account, position);
+    let mut state_details = _get_state_details();
 
-    let new_current_tick = if entry_tick > _current_tick {
-        best_sell_offer_tick
+    let stopping_tick = max_or_default_max(_max_tick, state_details.current_tick, position.long);
+
+    let result = if position.long {
+        _open_market_long_position(
+            account,
+            _collateral_value,
+            debt_value,
+            interest_rate,
+            state_details.current_tick,
+            stopping_tick,
+            false,
+        )
     } else {
-        _current_tick
+        _open_market_short_position(
+            account,
+            _collateral_value,
+            debt_value,
+            interest_rate,
+            state_details.current_tick,
+            stopping_tick,
+            false,
+        )
     };
 
-    return Some((position, new_current_tick, Vec::new()));
-}
+    match result {
+        Some((added_position, resulting_tick, crossed_ticks, (taker_fee, trading_fee))) => {
+            state_details.current_tick = resulting_tick;
 
-/// Open Market Long Position'
-///
-/// Params :See Open Position for params definition
-fn _open_market_long_position(
-    account: Subaccount,
-    collateral_value: Amount,
-    debt_value: Amount,
-    interest_rate: u32,
-    current_tick: Tick,
-    max_tick: Tick,
-) -> Option<(PositionDetails, Tick, Vec<Tick>)> {
-    let (collateral, debt) = (collateral_value, debt_value);
+            _set_state_details(state_details);
 
-    let (amount_out, amount_remaining_value, resulting_tick, crossed_ticks) =
-        _swap(collateral + debt, true, current_tick, max_tick);
+            _schedule_execution_for_ticks_orders(crossed_ticks);
 
-    if amount_out == 0 {
-        return None;
-    }
+            let un_used_collateral =
+                _collateral_value - added_position.collateral_value - taker_fee;
 
-    let (un_used_debt_value, un_used_collateral_value) = if amount_remaining_value >= debt_value {
-        (debt_value, amount_remaining_value - debt_value)
-    } else {
-        (amount_remaining_value, 0)
-    };
+            let mut manage_debt_params = ManageDebtParams::init(
+                debt_value,
+                debt_value,
+                debt_value - added_position.debt_value,
+            );
 
-    let resulting_debt_value = debt_value - un_used_debt_value;
-    let resulting_collateral_value = collateral_value - un_used_collateral_value;
+            manage_debt_params.trading_fee = trading_fee;
 
-    let position_value = collateral_value + debt_value - amount_remaining_value;
+            if un_used_collateral != 0 || manage_debt_params.requires_update() {
+                vault.manage_position_update(user, un_used_collateral, manage_debt_params);
+            }
 
-    let volume_share = _calc_position_volume_share(position_value, true);
+            let interest_value = _calc_interest(
+                position.debt_value,
+                position.interest_rate,
+                position.timestamp,
+            );
 
-    let position = PositionDetails {
-        long: true,
-        entry_tick: resulting_tick,
-        collateral_value: resulting_collateral_value,
-        debt_value: resulting_debt_value, //actual debt
-        interest_rate,
-        volume_share,
-        order_type: PositionOrderType::Market,
-        timestamp: ic_cdk::api::time(),
-    };
-    _insert_account_position(account, position);
+            _update_position_record(
+                position_key,
+                added_position.collateral_value + taker_fee,
+                0,
+                interest_value,
+            );
 
-    // get the best sell offer or lowest sell offer
-    let new_current_tick = match get_best_offer(true, resulting_tick, None) {
-        Some(tick) => tick,
-        None => resulting_tick,
-    };
+            if interest_value != 0 {
+                let net_debt_value = position.debt_value + interest_value;
 
-    return Some((position, new_current_tick, crossed_ticks));
-}
+                vault.manage_position_update(
+                    user,
+                    0,
+                    ManageDebtParams::init(position.debt_value, net_debt_value, 0),
+                );
 
-/// Open Market Short Position
-///
-/// Similar to Open Market Long position but for opening short positions
-fn _open_market_short_position(
-    account: Subaccount,
-    collateral_value: Amount,
-    debt_value: Amount,
-    interest_rate: u32,
-    initial_tick: Tick,
-    max_tick: Tick,
-) -> Option<(PositionDetails, Tick, Vec<Tick>)> {
-    let equivalent = |amount: Amount, tick: Tick, buy: bool| -> Amount {
-        let tick_price = _tick_to_price(tick);
-        _equivalent(amount, tick_price, buy)
-    };
+                position.debt_value = net_debt_value;
+            }
 
-    let best_buy_offer_tick = match get_best_offer(false, initial_tick, Some(max_tick)) {
-        Some(tick) => tick,
-        None => return None,
-    };
+            _merge_positions(&mut position, added_position);
 
-    let (collateral, debt) = (
-        equivalent(collateral_value, best_buy_offer_tick, true),
-        equivalent(debt_value, best_buy_offer_tick, true),
-    );
+            _insert_account_position(position_key, position);
 
-    let (amount_out_value, amount_remaining, resulting_tick, crossed_ticks) =
-        _swap(collateral + debt, false, best_buy_offer_tick, max_tick);
+            Ok(position)
+        }
+        None => {
+            vault.manage_position_update(
+                user,
+                _collateral_value,
+                ManageDebtParams::init(debt_value, debt_value, debt_value),
+            );
 
-    if amount_out_value == 0 {
-        return None;
+            Err("Failed to increase position".to_string())
+        }
     }
+}
 
-    let amount_remaining_value = equivalent(amount_remaining, best_buy_offer_tick, false);
+/// Set Position Triggers
+///
+/// Sets or changes the stop loss and take profit ticks of the caller's market position
+///
+/// Params
+/// - Position Id :: The id of the position
+/// - Stop Loss Tick :: tick at which position is closed to stop further loss ,none removes the stop loss
+/// - Take Profit Tick :: tick at which position is closed to take profit ,none removes the take profit
+///
+/// Returns
+///  - Position:the updated details of the position
+///
+/// Note
+///  - For a long position ,stop loss tick must be below the current tick and take profit tick above it ,the reverse for a short position
+#[ic_cdk::update(name = "setPositionTriggers")]
+fn set_position_triggers(
+    _position_id: PositionId,
+    _stop_loss_tick: Option<Tick>,
+    _take_profit_tick: Option<Tick>,
+) -> Result<PositionDetails, String> {
+    let user = ic_cdk::caller();
 
-    let (un_used_debt_value, un_used_collateral_value) = if amount_remaining_value >= debt_value {
-        (debt_value, amount_remaining_value - debt_value)
-    } else {
-        (amount_remaining_value, 0)
+    let position_key = (user._to_subaccount(), _position_id);
+
+    _settle_account_position(position_key);
+
+    let mut position = match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key))
+    {
+        Some(position) => position,
+        None => return Err("Position not found".to_string()),
     };
 
-    let resulting_debt_value = debt_value - un_used_debt_value;
-    let resulting_collateral_value = collateral_value - un_used_collateral_value;
+    if let PositionOrderType::Limit(_) = position.order_type {
+        return Err("Triggers can only be attached to market positions".to_string());
+    }
 
-    let position_value = amount_out_value;
+    let StateDetails { current_tick, .. } = _get_state_details();
 
-    let volume_share = _calc_position_volume_share(position_value, false);
+    if !_valid_triggers(
+        position.long,
+        current_tick,
+        _stop_loss_tick,
+        _take_profit_tick,
+    ) {
+        return Err("Invalid stop loss or take profit tick".to_string());
+    }
 
-    let position = PositionDetails {
-        long: false,
-        entry_tick: resulting_tick,
-        collateral_value: resulting_collateral_value,
-        debt_value: resulting_debt_value, //actual debt
-        interest_rate,
-        volume_share,
-        order_type: PositionOrderType::Market,
-        timestamp: ic_cdk::api::time(), //change to time()
-    };
-    _insert_account_position(account, position);
+    _set_position_triggers(
+        user,
+        position_key,
+        &mut position,
+        _stop_loss_tick,
+        _take_profit_tick,
+    );
 
-    return Some((position, initial_tick, crossed_ticks));
+    Ok(position)
 }
 
-/// Close Long PositionDetails
+/// Liquidate Function
 ///
-///closes a user's  long position if position can be fully closed and  repays debt
+/// liquidates an account's position to avoid bad debt by checking if the current leverage exceeds the max leverage
 ///
 /// Params
-/// - User :The user (position owner)
-/// - PositionDetails :The PositionDetails
-/// - Current Tick :The current tick of market's state
-/// - Stopping Tick : The max tick,corresponds to max price
-/// - Vault : Vault canister
+///  - User :The owner of the position
+///  - Position Id :The id of the position
 ///
 /// Returns
-///  - Current Collateral :The amount to send to position owner after paying debt ,this amount is zero if debt is not fully paid
-///  - Resulting Tick :The resulting tick from swapping
-///  - Crosssed Ticks :An array of ticks that have been crossed during swapping
-///   
+///  - Execution Tick :The last tick at which the position was executed
+///  - Crossed Ticks :An array of ticks that have been crossed while unwinding the position
+///
 /// Note
-///  - If position can not be closed fully ,the position is partially closed (updated) and debt is paid back either fully or partially
-fn _close_market_long_position(
-    account: Subaccount,
-    position: &mut PositionDetails,
-    initial_tick: Tick,
-    stopping_tick: Tick,
-) -> (Amount, Tick, Vec<Tick>, ManageDebtParams) {
-    let entry_price = _tick_to_price(position.entry_tick);
-    let equivalent_at_entry_price =
-        |amount: Amount, buy: bool| -> Amount { _equivalent(amount, entry_price, buy) };
+///  - Position is unwound through the same swap path as a market close ,bounded by the default swap slippage from the best offer
+///  - If position can not be unwound fully within the slippage ,the remaining part stays open as a smaller position
+///  - A liquidation fee on the unwound part of the position is taken from the remaining collateral ,the caller gets the keeper share of the fee and the rest is added to the vault's insurance fund
+///  - Any debt that can not be repaid is covered by the insurance fund before it reduces the vault's free liquidity
+///  - The mark price is refreshed before the position is checked (see Update Mark Price)
+#[ic_cdk::update(name = "liquidatePosition")]
+fn liquidate_position(
+    _user: Principal,
+    _position_id: PositionId,
+) -> Result<(Tick, Vec<Tick>), String> {
+    let keeper = ic_cdk::caller();
 
-    let position_realised_value = _calc_position_realised_value(position.volume_share, true);
+    let position_key = (_user._to_subaccount(), _position_id);
 
-    let realised_position_size = equivalent_at_entry_price(position_realised_value, true);
+    // the twap ,the oracle spot price and the best bid and ask can move without the current tick moving
+    _update_mark_price();
 
-    let best_buy_offer_tick = match get_best_offer(false, initial_tick, Some(stopping_tick)) {
-        Some(tick) => tick,
-        None => {
-            return (
-                0,
-                initial_tick,
-                Vec::new(),
-                ManageDebtParams::init(position.debt_value, position.debt_value, 0),
-            )
-        }
+    _liquidate_position(_user, position_key, Some(keeper))
+}
+
+/// Liquidate Position (Private)
+///
+/// Liquidates a position if it is liquidatable (see Liquidate Function)
+///
+/// Params
+///  - Owner :The owner of the position
+///  - Position Key :The account of the owner and the id of the position
+///  - Keeper :The caller of the liquidation ,if none the whole liquidation fee is added to the insurance fund
+fn _liquidate_position(
+    owner: Principal,
+    position_key: PositionKey,
+    keeper: Option<Principal>,
+) -> Result<(Tick, Vec<Tick>), String> {
+    _settle_account_position(position_key);
+
+    let mut state_details = _get_state_details();
+
+    let mut position = match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key))
+    {
+        Some(position) => position,
+        None => return Err("Position not found".to_string()),
     };
 
-    let (amount_out_value, amount_remaining, _, crossed_ticks) = _swap(
-        realised_position_size,
-        false,
-        best_buy_offer_tick,
-        stopping_tick,
-    );
+    let to_liquidate = _liquidation_status(position, state_details.maintenance_margin);
 
-    let interest_value = _calc_interest(
-        position.debt_value,
-        position.interest_rate,
-        position.timestamp,
-    );
+    if !to_liquidate {
+        return Err("Position can not be liquidated".to_string());
+    }
 
-    let profit: u128;
+    let position_value = position.collateral_value + position.debt_value;
 
-    let manage_debt_params: ManageDebtParams;
+    let init_volume_share = position.volume_share;
 
-    if amount_remaining > 0 {
-        let amount_remaining_value = equivalent_at_entry_price(amount_remaining, false);
+    let current_tick = state_details.current_tick;
 
-        (profit, manage_debt_params) = _update_market_position_after_swap(
-            position,
-            amount_out_value,
-            amount_remaining_value,
-            interest_value,
-        );
+    let stopping_tick = _liquidation_stopping_tick(current_tick, position.long);
 
-        _insert_account_position(account, position.clone());
-    } else {
-        let net_debt = position.debt_value + interest_value;
-        (profit, manage_debt_params) = (
-            amount_out_value - net_debt,
-            ManageDebtParams::init(position.debt_value, net_debt, net_debt),
-        );
-        _remove_account_position(&account);
+    if get_best_offer(!position.long, current_tick, Some(stopping_tick)).is_none() {
+        return Err("Not enough liquidity to liquidate position".to_string());
     }
 
-    return (profit, initial_tick, crossed_ticks, manage_debt_params);
-}
+    let (collateral_remaining, resulting_tick, execution_tick, crossed_ticks, manage_debt_params) =
+        if position.long {
+            _close_market_long_position(position_key.0, &mut position, current_tick, stopping_tick)
+        } else {
+            _close_market_short_position(position_key.0, &mut position, current_tick, stopping_tick)
+        };
 
-/// Close Short Position
-///
-/// similar to Close Long Function,but for short positions
-fn _close_market_short_position(
-    account: Subaccount,
-    position: &mut PositionDetails,
-    init_tick: Tick,
-    stopping_tick: Tick,
-) -> (Amount, Tick, Vec<Tick>, ManageDebtParams) {
-    let position_realised_value = _calc_position_realised_value(position.volume_share, false);
+    state_details.current_tick = resulting_tick;
 
-    let realised_position_size = position_realised_value;
+    _set_state_details(state_details);
 
-    let best_sell_offer_tick = match get_best_offer(true, init_tick, Some(stopping_tick)) {
-        Some(tick) => tick,
-        None => {
-            return (
-                0,
-                init_tick,
-                Vec::new(),
-                ManageDebtParams::init(position.debt_value, position.debt_value, 0),
-            )
-        }
-    };
+    _schedule_execution_for_ticks_orders(crossed_ticks.clone());
 
-    let (amount_out, amount_remaining_value, resulting_tick, crossed_ticks) = _swap(
-        realised_position_size,
-        true,
-        best_sell_offer_tick,
-        stopping_tick,
+    // liquidation fee is charged on the unwound part only and can not exceed the remaining collateral
+    let liquidation_fee = _calc_liquidation_fee(
+        state_details.liquidation_fee,
+        position_value,
+        init_volume_share,
+        position.volume_share,
+        collateral_remaining,
     );
 
-    let best_price = _tick_to_price(best_sell_offer_tick);
+    let (keeper, keeper_fee) = match keeper {
+        Some(keeper) => (
+            keeper,
+            _percentage128(state_details.keeper_share, liquidation_fee),
+        ),
+        None => (ic_cdk::id(), 0),
+    };
 
-    let amount_out_value = _equivalent(amount_out, best_price, false);
+    let liquidation_fees = LiquidationFees {
+        keeper,
+        keeper_fee,
+        insurance_fee: liquidation_fee - keeper_fee,
+    };
 
-    let interest_value = _calc_interest(
-        position.debt_value,
-        position.interest_rate,
-        position.timestamp,
-    );
+    let market_details = _get_market_details();
 
-    let profit: u128;
-    let manage_debt_params: ManageDebtParams;
+    let vault = Vault::init(market_details.vault_id);
 
-    if amount_remaining_value > 0 {
-        (profit, manage_debt_params) = _update_market_position_after_swap(
-            position,
-            amount_out_value,
-            amount_remaining_value,
-            interest_value,
-        );
+    _update_position_record(
+        position_key,
+        0,
+        collateral_remaining - liquidation_fee,
+        manage_debt_params.interest_value(),
+    );
 
-        _insert_account_position(account, position.clone());
-    } else {
-        let net_debt = position.debt_value + interest_value;
-        (profit, manage_debt_params) = (
-            amount_out_value - net_debt,
-            ManageDebtParams::init(position.debt_value, net_debt, net_debt),
-        );
+    // the shortfall of a fully closed position is settled by the vault ,a partially closed position keeps it's remaining debt
+    let position_closed = position.volume_share == 0;
 
-        _remove_account_position(&account);
-    }
+    vault.manage_liquidation(
+        owner,
+        collateral_remaining - liquidation_fee,
+        liquidation_fees,
+        manage_debt_params,
+        position_closed,
+    );
 
-    let new_current_tick = match get_best_offer(true, resulting_tick, None) {
-        Some(tick) => tick,
-        None => resulting_tick,
-    };
+    _store_market_position(position_key, position);
 
-    return (profit, new_current_tick, crossed_ticks, manage_debt_params);
+    Ok((execution_tick, crossed_ticks))
 }
 
-/// Close Limit Position
+/// Liquidation Stopping Tick
 ///
+/// Gets the stopping tick for unwinding a liquidated position ,bounded by the default swap slippage from the best offer
 ///
-/// Closes a limit position at a particular tick by closing removing the limit order if the order is not filled
-///
-/// Params
-///  - User : The owner of the position
-///  - Position : The particular position to close
-///  - Vault :The vault type representing the vault canister  
-fn _close_limit_long_position(
-    account: Subaccount,
-    position: &mut PositionDetails,
-) -> (Amount, ManageDebtParams) {
-    match position.order_type {
-        PositionOrderType::Limit(order) => {
-            let (amount_received, amount_remaining_value) = _close_order(&order);
-
-            let (removed_collateral, manage_debt_params);
+/// Note:A long position is unwound by selling and a short position by buying
+fn _liquidation_stopping_tick(current_tick: Tick, long: bool) -> Tick {
+    let best_offer_tick = get_best_offer(!long, current_tick, None).unwrap_or(current_tick);
 
-            if amount_received == 0 {
-                (removed_collateral, manage_debt_params) = (
-                    position.collateral_value,
-                    ManageDebtParams::init(
-                        position.debt_value,
-                        position.debt_value,
-                        position.debt_value,
-                    ),
-                );
+    let slippage = _percentage64(_DEFAULT_SWAP_SLIPPAGE, best_offer_tick);
 
-                _remove_account_position(&account);
+    if long {
+        best_offer_tick - slippage
+    } else {
+        best_offer_tick + slippage
+    }
+}
+
+/// Open PositionDetails (Private)
+///
+/// opens a position for user if possible
+/// Params
+///  - Position Key :The account of the owner and the id of the position
+///  - Long : Position direction ,true if long or false otherwise
+///  - Limit : true for opening a limit order and false for a market order
+///  - Collateral Value : amount of collateral asset being put in as collateral
+///  - Debt Value : The amount of collateral_asset used as debt for opening position
+///  - Interest Rate : The current interest rate for opening a position
+///  - Entry Tick : The entry tick or the current state tick for this market
+///  - Max Tick : The maximum tick to execute swap ,also seen as maximum price
+///
+/// Returns
+///  - Option containing
+///  - - Position Details :The details of the position created
+///  - - Resulting Tick :The resuting tick from swapping
+///  - - Crossed Ticks :A vector of all crossed ticks during swap
+///  - - Fees :The taker fee deducted from the collateral and the part of it forwarded to the vault ,both zero for a limit position
+/// Note
+///  - If position can not be opened it returns none and both collateral and debt gets refunded back and swap is reverted afterwards
+///
+fn _open_position(
+    _position_key: PositionKey,
+    _long: bool,
+    _order_type: OrderType,
+    _time_in_force: TimeInForce,
+    _collateral_value: Amount,
+    _debt_value: Amount,
+    _interest_rate: u32,
+    _current_tick: Tick,
+    _max_tick: Tick,
+) -> Option<(PositionDetails, Tick, Vec<Tick>, TakerFees)> {
+    // a limit order crossing the best offer or not good till cancelled is executed at market up to the limit tick
+    let marketable = match _order_type {
+        OrderType::Limit => {
+            !_time_in_force.rests_in_book() || _crosses_best_offer(_long, _max_tick, _current_tick)
+        }
+        OrderType::Market => true,
+    };
+
+    let fill_or_kill = _time_in_force == TimeInForce::Fok;
+
+    let result = if marketable {
+        if _long {
+            _open_market_long_position(
+                _position_key.0,
+                _collateral_value,
+                _debt_value,
+                _interest_rate,
+                _current_tick,
+                _max_tick,
+                fill_or_kill,
+            )
+        } else {
+            _open_market_short_position(
+                _position_key.0,
+                _collateral_value,
+                _debt_value,
+                _interest_rate,
+                _current_tick,
+                _max_tick,
+                fill_or_kill,
+            )
+        }
+    } else if _long {
+        _open_limit_long_position(
+            _long,
+            _collateral_value,
+            _debt_value,
+            _interest_rate,
+            _current_tick,
+            _max_tick,
+        )
+    } else {
+        _open_limit_short_position(
+            _long,
+            _collateral_value,
+            _debt_value,
+            _interest_rate,
+            _current_tick,
+            _max_tick,
+        )
+    };
+
+    if let Some((position, _, _, (taker_fee, _))) = result {
+        _insert_account_position(_position_key, position);
+
+        _insert_position_record(
+            _position_key,
+            &position,
+            position.collateral_value + taker_fee,
+        );
+    }
+
+    result
+}
+
+/// Crosses Best Offer
+///
+/// Checks if a limit order at the entry tick would be filled immediately
+///
+/// Returns
+///  - Crosses :true if a long entry tick is at or above the best sell offer or a short entry tick is at or below the best buy offer
+fn _crosses_best_offer(long: bool, entry_tick: Tick, current_tick: Tick) -> bool {
+    let best_offer_tick = match get_best_offer(long, current_tick, None) {
+        Some(tick) => tick,
+        None => _def_max_tick(current_tick, long),
+    };
+
+    if long {
+        return entry_tick >= best_offer_tick;
+    }
+    entry_tick <= best_offer_tick
+}
+
+/// Open Reduce Only Order
+///
+/// Executes a reduce only order by closing part of an existing market position in the opposite direction
+///
+/// Params
+///  - User :The owner of the position
+///  - Position Key :The account and the id of the position to reduce
+///  - Long :The order direction ,must be opposite to the position direction
+///  - Order Type :The order type ,only market orders can be reduce only
+///  - Order Value :The value of the order ,the position is closed if the order value exceeds the position value
+///  - Max Tick :The max executing tick
+///
+/// Returns
+///  - Position Id :The id of the reduced position
+///  - Position :The remaining details of the reduced position
+///
+/// Note
+///  - No collateral is taken for a reduce only order ,so it can never open or flip a position
+fn _open_reduce_only_order(
+    user: Principal,
+    position_key: PositionKey,
+    long: bool,
+    order_type: OrderType,
+    order_value: Amount,
+    max_tick: Option<Tick>,
+) -> Result<(PositionId, PositionDetails), String> {
+    if let OrderType::Limit = order_type {
+        return Err("Only market orders can be reduce only".to_string());
+    }
+
+    let position = match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key)) {
+        Some(position) => position,
+        None => return Err("Position to reduce not found".to_string()),
+    };
+
+    if position.long == long {
+        return Err("Reduce only order must be opposite to the position direction".to_string());
+    }
+
+    let (_, position) = _reduce_position(
+        user,
+        position_key,
+        PositionReduction::Amount(order_value),
+        max_tick,
+    )?;
+
+    Ok((position_key.1, position))
+}
+
+fn _open_limit_short_position(
+    _long: bool,
+    _collateral_value: Amount,
+    _debt_value: Amount,
+    _interest_rate: u32,
+    _current_tick: Tick,
+    _max_tick: Tick,
+) -> Option<(PositionDetails, Tick, Vec<Tick>, TakerFees)> {
+    let equivalent = |amount: Amount, tick: Tick, buy: bool| -> Amount {
+        let tick_price = _tick_to_price(tick);
+        _equivalent(amount, tick_price, buy)
+    };
+
+    let entry_tick = _max_tick;
+
+    let best_buy_offer_tick = match get_best_offer(false, _current_tick, None) {
+        Some(tick) => tick,
+        None => _def_max_tick(_current_tick, false),
+    };
+
+    if entry_tick <= best_buy_offer_tick {
+        return None;
+    };
+
+    let (collateral, debt) = (
+        equivalent(_collateral_value, entry_tick, true),
+        equivalent(_debt_value, entry_tick, true),
+    );
+
+    let mut order = LimitOrder::new(collateral + debt, entry_tick, _long);
+
+    _open_order(&mut order);
+
+    let position = PositionDetails {
+        long: _long,
+        entry_tick,
+        collateral_value: _collateral_value,
+        debt_value: _debt_value,
+        interest_rate: _interest_rate,
+        volume_share: 0, // not initialised yet
+        order_type: PositionOrderType::Limit(order),
+        timestamp: 0, //not initialised
+        stop_loss_tick: None,
+        take_profit_tick: None,
+        funding_index: 0,
+    };
+
+    let new_current_tick = get_best_offer(true, best_buy_offer_tick, Some(entry_tick)).unwrap();
+
+    Some((position, new_current_tick, Vec::new(), (0, 0)))
+}
+
+fn _open_limit_long_position(
+    _long: bool,
+    _collateral_value: Amount,
+    _debt_value: Amount,
+    _interest_rate: u32,
+    _current_tick: Tick,
+    _max_tick: Tick,
+) -> Option<(PositionDetails, Tick, Vec<Tick>, TakerFees)> {
+    let entry_tick = _max_tick;
+
+    let best_sell_offer_tick = match get_best_offer(true, _current_tick, None) {
+        Some(tick) => tick,
+        None => _def_max_tick(_current_tick, true),
+    };
+
+    if entry_tick >= best_sell_offer_tick {
+        return None;
+    }
+    let (collateral, debt) = (_collateral_value, _debt_value);
+
+    let mut order = LimitOrder::new(collateral + debt, entry_tick, true);
+
+    _open_order(&mut order);
+
+    let position = PositionDetails {
+        long: true,
+        entry_tick,
+        collateral_value: _collateral_value,
+        debt_value: _debt_value,
+        interest_rate: _interest_rate,
+        volume_share: 0, // not initialised yet
+        order_type: PositionOrderType::Limit(order),
+        timestamp: 0, //not initialised
+        stop_loss_tick: None,
+        take_profit_tick: None,
+        funding_index: 0,
+    };
+
+    let new_current_tick = if entry_tick > _current_tick {
+        best_sell_offer_tick
+    } else {
+        _current_tick
+    };
+
+    Some((position, new_current_tick, Vec::new(), (0, 0)))
+}
+
+/// Open Market Long Position'
+///
+/// Params :See Open Position for params definition
+///  - Fill Or Kill :if true no position is opened unless the order can be fully filled up to the max tick
+fn _open_market_long_position(
+    account: Subaccount,
+    collateral_value: Amount,
+    debt_value: Amount,
+    interest_rate: u32,
+    current_tick: Tick,
+    max_tick: Tick,
+    fill_or_kill: bool,
+) -> Option<(PositionDetails, Tick, Vec<Tick>, TakerFees)> {
+    let (collateral, debt) = (collateral_value, debt_value);
+
+    if fill_or_kill && _dry_run_swap(collateral + debt, true, current_tick, max_tick).1 > 0 {
+        return None;
+    }
+
+    let (amount_out, amount_remaining_value, resulting_tick, crossed_ticks, swap_fees) =
+        _swap(account, collateral + debt, true, current_tick, max_tick);
+
+    if amount_out == 0 {
+        return None;
+    }
+
+    let (un_used_debt_value, un_used_collateral_value) = if amount_remaining_value >= debt_value {
+        (debt_value, amount_remaining_value - debt_value)
+    } else {
+        (amount_remaining_value, 0)
+    };
+
+    let resulting_debt_value = debt_value - un_used_debt_value;
+    let resulting_collateral_value = collateral_value - un_used_collateral_value;
+
+    let (taker_fee, trading_fee) = _collect_swap_fees(swap_fees, resulting_collateral_value);
+
+    let position_value = collateral_value + debt_value - amount_remaining_value - taker_fee;
+
+    let volume_share = _calc_position_volume_share(position_value, true);
+
+    let position = PositionDetails {
+        long: true,
+        entry_tick: resulting_tick,
+        collateral_value: resulting_collateral_value - taker_fee,
+        debt_value: resulting_debt_value, //actual debt
+        interest_rate,
+        volume_share,
+        order_type: PositionOrderType::Market,
+        timestamp: ic_cdk::api::time(),
+        stop_loss_tick: None,
+        take_profit_tick: None,
+        funding_index: _get_funding_index(true),
+    };
+
+    // get the best sell offer or lowest sell offer
+    let new_current_tick = match get_best_offer(true, resulting_tick, None) {
+        Some(tick) => tick,
+        None => resulting_tick,
+    };
+
+    Some((
+        position,
+        new_current_tick,
+        crossed_ticks,
+        (taker_fee, trading_fee),
+    ))
+}
+
+/// Open Market Short Position
+///
+/// Similar to Open Market Long position but for opening short positions
+fn _open_market_short_position(
+    account: Subaccount,
+    collateral_value: Amount,
+    debt_value: Amount,
+    interest_rate: u32,
+    initial_tick: Tick,
+    max_tick: Tick,
+    fill_or_kill: bool,
+) -> Option<(PositionDetails, Tick, Vec<Tick>, TakerFees)> {
+    let equivalent = |amount: Amount, tick: Tick, buy: bool| -> Amount {
+        let tick_price = _tick_to_price(tick);
+        _equivalent(amount, tick_price, buy)
+    };
+
+    let best_buy_offer_tick = match get_best_offer(false, initial_tick, Some(max_tick)) {
+        Some(tick) => tick,
+        None => return None,
+    };
+
+    let (collateral, debt) = (
+        equivalent(collateral_value, best_buy_offer_tick, true),
+        equivalent(debt_value, best_buy_offer_tick, true),
+    );
+
+    if fill_or_kill && _dry_run_swap(collateral + debt, false, best_buy_offer_tick, max_tick).1 > 0
+    {
+        return None;
+    }
+
+    let (amount_out_value, amount_remaining, resulting_tick, crossed_ticks, swap_fees) = _swap(
+        account,
+        collateral + debt,
+        false,
+        best_buy_offer_tick,
+        max_tick,
+    );
+
+    if amount_out_value == 0 {
+        return None;
+    }
+
+    let amount_remaining_value = equivalent(amount_remaining, best_buy_offer_tick, false);
+
+    let (un_used_debt_value, un_used_collateral_value) = if amount_remaining_value >= debt_value {
+        (debt_value, amount_remaining_value - debt_value)
+    } else {
+        (amount_remaining_value, 0)
+    };
+
+    let resulting_debt_value = debt_value - un_used_debt_value;
+    let resulting_collateral_value = collateral_value - un_used_collateral_value;
+
+    let (taker_fee, trading_fee) = _collect_swap_fees(swap_fees, resulting_collateral_value);
+
+    let position_value = amount_out_value - taker_fee;
+
+    let volume_share = _calc_position_volume_share(position_value, false);
+
+    let position = PositionDetails {
+        long: false,
+        entry_tick: resulting_tick,
+        collateral_value: resulting_collateral_value - taker_fee,
+        debt_value: resulting_debt_value, //actual debt
+        interest_rate,
+        volume_share,
+        order_type: PositionOrderType::Market,
+        timestamp: ic_cdk::api::time(), //change to time()
+        stop_loss_tick: None,
+        take_profit_tick: None,
+        funding_index: _get_funding_index(false),
+    };
+
+    Some((
+        position,
+        initial_tick,
+        crossed_ticks,
+        (taker_fee, trading_fee),
+    ))
+}
+
+/// Close Market Position
+///
+/// Closes a market position at the current state tick ,updates the current tick ,schedules the execution of crossed ticks orders
+/// and settles the position with the vault
+///
+/// Params
+/// - User :The owner of the position
+/// - Position Key :The account and the id of the position
+/// - Position :The position to close
+/// - Max Tick :The max executing tick ,if set to none or outside the required range default max tick is used
+/// - Cover Shortfall :If true and the position is fully closed without repaying it's debt ,the shortfall is covered by the insurance fund (see Manage Liquidation)
+///
+/// Returns
+///  - Profit :The amount sent back to the position owner
+///
+/// Note
+///  - Storing the position is left to the caller (see Store Market Position)
+///  - If the shortfall is not covered ,the unpaid debt is left as the position's debt value
+fn _close_market_position(
+    user: Principal,
+    position_key: PositionKey,
+    position: &mut PositionDetails,
+    _max_tick: Option<Tick>,
+    cover_shortfall: bool,
+) -> Amount {
+    let mut state_details = _get_state_details();
+
+    let current_tick = state_details.current_tick;
+
+    let stopping_tick = max_or_default_max(_max_tick, current_tick, !position.long);
+
+    let (collateral_value, resulting_tick, _, crossed_ticks, manage_debt_params) = if position.long
+    {
+        _close_market_long_position(position_key.0, position, current_tick, stopping_tick)
+    } else {
+        _close_market_short_position(position_key.0, position, current_tick, stopping_tick)
+    };
+
+    state_details.current_tick = resulting_tick;
+
+    _set_state_details(state_details);
+
+    _schedule_execution_for_ticks_orders(crossed_ticks);
+
+    _update_position_record(
+        position_key,
+        0,
+        collateral_value,
+        manage_debt_params.interest_value(),
+    );
+
+    let market_details = _get_market_details();
+
+    let vault = Vault::init(market_details.vault_id);
+
+    if cover_shortfall && position.volume_share == 0 && position.debt_value > 0 {
+        position.debt_value = 0;
+
+        let liquidation_fees = LiquidationFees {
+            keeper: ic_cdk::id(),
+            keeper_fee: 0,
+            insurance_fee: 0,
+        };
+
+        vault.manage_liquidation(
+            user,
+            collateral_value,
+            liquidation_fees,
+            manage_debt_params,
+            true,
+        );
+    } else if manage_debt_params.requires_update() {
+        vault.manage_position_update(user, collateral_value, manage_debt_params);
+    }
+
+    collateral_value
+}
+
+/// Close Long PositionDetails
+///
+///closes a user's  long position if position can be fully closed and  repays debt
+///
+/// Params
+/// - PositionDetails :The PositionDetails
+/// - Current Tick :The current tick of market's state
+/// - Stopping Tick : The max tick,corresponds to max price
+/// - Vault : Vault canister
+///
+/// Returns
+///  - Current Collateral :The amount to send to position owner after paying debt ,this amount is zero if debt is not fully paid
+///  - Resulting Tick :The resulting tick from swapping
+///  - Execution Tick :The last tick at which the position was executed
+///  - Crosssed Ticks :An array of ticks that have been crossed during swapping
+///   
+/// Note
+///  - If position can not be closed fully ,the position is partially closed (updated) and debt is paid back either fully or partially
+///  - If position is fully closed ,it is cleared (see Clear Position) ,storing the position is left to the caller
+///  - If the value gotten can not pay back the debt ,all of it is repaid and the shortfall is left as the position's debt value (see Settle Closed Position Debt)
+///  - The taker fee is deducted from the value gotten before repaying the debt (see Collect Swap Fees)
+fn _close_market_long_position(
+    account: Subaccount,
+    position: &mut PositionDetails,
+    initial_tick: Tick,
+    stopping_tick: Tick,
+) -> (Amount, Tick, Tick, Vec<Tick>, ManageDebtParams) {
+    let entry_price = _tick_to_price(position.entry_tick);
+    let equivalent_at_entry_price =
+        |amount: Amount, buy: bool| -> Amount { _equivalent(amount, entry_price, buy) };
+
+    let best_buy_offer_tick = match get_best_offer(false, initial_tick, Some(stopping_tick)) {
+        Some(tick) => tick,
+        None => {
+            return (
+                0,
+                initial_tick,
+                initial_tick,
+                Vec::new(),
+                ManageDebtParams::init(position.debt_value, position.debt_value, 0),
+            )
+        }
+    };
+
+    let position_realised_value = _apply_position_funding(
+        position,
+        _calc_position_realised_value(position.volume_share, true),
+    );
+
+    let realised_position_size = equivalent_at_entry_price(position_realised_value, true);
+
+    let (amount_out_value, amount_remaining, execution_tick, crossed_ticks, swap_fees) = _swap(
+        account,
+        realised_position_size,
+        false,
+        best_buy_offer_tick,
+        stopping_tick,
+    );
+
+    let (taker_fee, trading_fee) = _collect_swap_fees(swap_fees, amount_out_value);
+
+    let amount_out_value = amount_out_value - taker_fee;
+
+    let interest_value = _calc_interest(
+        position.debt_value,
+        position.interest_rate,
+        position.timestamp,
+    );
+
+    let profit: u128;
+
+    let mut manage_debt_params: ManageDebtParams;
+
+    if amount_remaining > 0 {
+        let amount_remaining_value = equivalent_at_entry_price(amount_remaining, false);
+
+        (profit, manage_debt_params) = _update_market_position_after_swap(
+            position,
+            amount_out_value,
+            amount_remaining_value,
+            interest_value,
+        );
+    } else {
+        (profit, manage_debt_params) =
+            _settle_closed_position_debt(position, amount_out_value, interest_value);
+    }
+
+    manage_debt_params.trading_fee = trading_fee;
+
+    (
+        profit,
+        initial_tick,
+        execution_tick,
+        crossed_ticks,
+        manage_debt_params,
+    )
+}
+
+/// Close Short Position
+///
+/// similar to Close Long Function,but for short positions
+fn _close_market_short_position(
+    account: Subaccount,
+    position: &mut PositionDetails,
+    init_tick: Tick,
+    stopping_tick: Tick,
+) -> (Amount, Tick, Tick, Vec<Tick>, ManageDebtParams) {
+    let best_sell_offer_tick = match get_best_offer(true, init_tick, Some(stopping_tick)) {
+        Some(tick) => tick,
+        None => {
+            return (
+                0,
+                init_tick,
+                init_tick,
+                Vec::new(),
+                ManageDebtParams::init(position.debt_value, position.debt_value, 0),
+            )
+        }
+    };
+
+    let position_realised_value = _apply_position_funding(
+        position,
+        _calc_position_realised_value(position.volume_share, false),
+    );
+
+    let realised_position_size = position_realised_value;
+
+    let (amount_out, amount_remaining_value, resulting_tick, crossed_ticks, swap_fees) = _swap(
+        account,
+        realised_position_size,
+        true,
+        best_sell_offer_tick,
+        stopping_tick,
+    );
+
+    let best_price = _tick_to_price(best_sell_offer_tick);
+
+    let amount_out_value = _equivalent(amount_out, best_price, false);
+
+    let (taker_fee, trading_fee) = _collect_swap_fees(swap_fees, amount_out_value);
+
+    let amount_out_value = amount_out_value - taker_fee;
+
+    let interest_value = _calc_interest(
+        position.debt_value,
+        position.interest_rate,
+        position.timestamp,
+    );
+
+    let profit: u128;
+    let mut manage_debt_params: ManageDebtParams;
+
+    if amount_remaining_value > 0 {
+        (profit, manage_debt_params) = _update_market_position_after_swap(
+            position,
+            amount_out_value,
+            amount_remaining_value,
+            interest_value,
+        );
+    } else {
+        (profit, manage_debt_params) =
+            _settle_closed_position_debt(position, amount_out_value, interest_value);
+    }
+
+    manage_debt_params.trading_fee = trading_fee;
+
+    let new_current_tick = match get_best_offer(true, resulting_tick, None) {
+        Some(tick) => tick,
+        None => resulting_tick,
+    };
+
+    (
+        profit,
+        new_current_tick,
+        resulting_tick,
+        crossed_ticks,
+        manage_debt_params,
+    )
+}
+
+/// Close Limit Position
+///
+///
+/// Closes a limit position at a particular tick by closing removing the limit order if the order is not filled
+///
+/// Params
+///  - User : The owner of the position
+///  - Position : The particular position to close
+///  - Vault :The vault type representing the vault canister  
+fn _close_limit_long_position(
+    position_key: PositionKey,
+    position: &mut PositionDetails,
+) -> (Amount, ManageDebtParams) {
+    match position.order_type {
+        PositionOrderType::Limit(order) => {
+            let (amount_received, amount_remaining_value) = _close_order(&order);
+
+            let (removed_collateral, manage_debt_params);
+
+            if amount_received == 0 {
+                (removed_collateral, manage_debt_params) = (
+                    position.collateral_value,
+                    ManageDebtParams::init(
+                        position.debt_value,
+                        position.debt_value,
+                        position.debt_value,
+                    ),
+                );
+
+                _remove_account_position(&position_key);
+            } else {
+                (removed_collateral, manage_debt_params) =
+                    _convert_limit_position(position, amount_remaining_value);
+
+                _insert_account_position(position_key, *position);
+            };
+
+            return (removed_collateral, manage_debt_params);
+        }
+        PositionOrderType::Market => (0, ManageDebtParams::default()),
+    }
+}
+
+/// Close Limit Short Function
+///
+/// Similar to close limit long position function but for long position
+fn _close_limit_short_position(
+    position_key: PositionKey,
+    position: &mut PositionDetails,
+) -> (Amount, ManageDebtParams) {
+    match position.order_type {
+        PositionOrderType::Limit(order) => {
+            let (amount_received, amount_remaining) = _close_order(&order);
+
+            let (removed_collateral, manage_debt_params);
+
+            if amount_received == 0 {
+                (removed_collateral, manage_debt_params) = (
+                    position.collateral_value,
+                    ManageDebtParams::init(
+                        position.debt_value,
+                        position.debt_value,
+                        position.debt_value,
+                    ),
+                );
+                _remove_account_position(&position_key);
+            } else {
+                let entry_price = _tick_to_price(position.entry_tick);
+
+                let amount_remaining_value = _equivalent(amount_remaining, entry_price, false);
+                (removed_collateral, manage_debt_params) =
+                    _convert_limit_position(position, amount_remaining_value);
+
+                _insert_account_position(position_key, *position);
+            };
+
+            return (removed_collateral, manage_debt_params);
+        }
+        // unreachable code
+        PositionOrderType::Market => return (0, ManageDebtParams::default()),
+    }
+}
+
+/// Update Market Position After Swap Function
+///
+/// This function updates a  market position if it can not be closed i.e amount remaining after swapping to close position is greater than 0
+///
+/// It
+///   - Updates the position debt ,the position collateral value , the position volume share
+///   - Derives the update asset params that pays the debt either fully or partially
+///
+/// Params
+///  - Position :A mutable reference to the particular position
+///  - Resulting Tick : The resulting tick after swapping to closing the position
+///  - Amount Out Value :The value of the amount gotten from swapping
+///  - Amount Remaining Value :The value of the amount remaining after swapping
+///  - Interest Value : The value of the interest accrued on current position debt
+///
+/// Returns
+///  - Profit : The amount of profit for position owner or the amount of removable collateral from position
+///  - Manage Debt Params : for repaying debt ,specifying the current debt and the previous debt and interest paid
+fn _update_market_position_after_swap(
+    position: &mut PositionDetails,
+    amount_out_value: Amount,
+    amount_remaining_value: Amount,
+    interest_value: Amount,
+) -> (Amount, ManageDebtParams) {
+    let init_debt_value = position.debt_value;
+
+    let net_debt_value = init_debt_value + interest_value;
+
+    let profit;
+    let manage_debt_params;
+
+    if amount_out_value < net_debt_value {
+        position.debt_value = net_debt_value - amount_out_value;
+
+        profit = 0;
+
+        manage_debt_params =
+            ManageDebtParams::init(init_debt_value, net_debt_value, amount_out_value);
+    } else {
+        position.debt_value = 0;
+        position.collateral_value = amount_remaining_value;
+
+        profit = amount_out_value - net_debt_value;
+
+        manage_debt_params =
+            ManageDebtParams::init(init_debt_value, net_debt_value, net_debt_value);
+    }
+
+    let new_volume_share = _calc_position_volume_share(amount_remaining_value, position.long);
+
+    position.volume_share = new_volume_share;
+
+    // funding on the whole position has been applied to the swapped value
+    position.funding_index = _get_funding_index(position.long);
+
+    // if position last time updated is greater than one hour ago ,position time is updated to current timestamp
+    if position.timestamp + ONE_HOUR > ic_cdk::api::time() {
+        position.timestamp = ic_cdk::api::time()
+    }
+
+    return (profit, manage_debt_params);
+}
+
+/// Settle Closed Position Debt
+///
+/// Derives the profit and the update asset params for repaying the debt of a fully closed market position and clears the position (see Clear Position)
+///
+/// Returns
+///  - Profit :The amount left for the position owner after paying the debt and interest
+///  - Manage Debt Params :for repaying debt ,if the amount out value is less than the debt and interest the whole amount out value is repaid
+///
+/// Note
+///  - If the debt is not fully repaid ,the unpaid debt is left as the position's debt value to be settled by the caller
+fn _settle_closed_position_debt(
+    position: &mut PositionDetails,
+    amount_out_value: Amount,
+    interest_value: Amount,
+) -> (Amount, ManageDebtParams) {
+    let init_debt = position.debt_value;
+
+    let net_debt = init_debt + interest_value;
+
+    _clear_position(position);
+
+    if amount_out_value < net_debt {
+        position.debt_value = net_debt - amount_out_value;
+
+        return (
+            0,
+            ManageDebtParams::init(init_debt, net_debt, amount_out_value),
+        );
+    }
+
+    (
+        amount_out_value - net_debt,
+        ManageDebtParams::init(init_debt, net_debt, net_debt),
+    )
+}
+
+/// Clear Position
+///
+/// Clears a fully closed market position by setting it's volume share ,collateral value and debt value to zero
+fn _clear_position(position: &mut PositionDetails) {
+    position.volume_share = 0;
+    position.collateral_value = 0;
+    position.debt_value = 0;
+}
+
+/// Split Position
+///
+/// Splits a fraction of a market position's collateral ,debt and volume share into a new position slice
+///
+/// Params
+///  - Position :A mutable reference to the position ,reduced by the slice
+///  - Fraction :The fraction of the position to split (in percentage)
+///
+/// Returns
+///  - Position Slice :The position slice with the same direction ,entry tick and interest rate as the position
+fn _split_position(position: &mut PositionDetails, fraction: u64) -> PositionDetails {
+    let mut position_slice = *position;
+
+    position_slice.collateral_value = _percentage128(fraction, position.collateral_value);
+    position_slice.debt_value = _percentage128(fraction, position.debt_value);
+    position_slice.volume_share = _percentage128(fraction, position.volume_share);
+
+    position.collateral_value -= position_slice.collateral_value;
+    position.debt_value -= position_slice.debt_value;
+    position.volume_share -= position_slice.volume_share;
+
+    position_slice
+}
+
+/// Merge Positions
+///
+/// Merges a newly opened market position into an existing market position of the same direction
+///
+/// Params
+///  - Position :A mutable reference to the existing position
+///  - Added Position :The newly opened position
+///
+/// Note
+///  - The entry tick is blended so that the merged position size is the sum of both positions sizes
+///  - The interest rate is blended by debt and interest accrues from the current timestamp
+///  - The funding index is blended by value (see Blend Funding Index)
+fn _merge_positions(position: &mut PositionDetails, added_position: PositionDetails) {
+    let position_value = position.collateral_value + position.debt_value;
+    let added_position_value = added_position.collateral_value + added_position.debt_value;
+
+    let position_size = _equivalent(position_value, _tick_to_price(position.entry_tick), true);
+    let added_position_size = _equivalent(
+        added_position_value,
+        _tick_to_price(added_position.entry_tick),
+        true,
+    );
+
+    let entry_price = ((position_value + added_position_value) * _PRICE_DECIMAL)
+        / (position_size + added_position_size);
+
+    let net_debt_value = position.debt_value + added_position.debt_value;
+
+    if let Some(interest_rate) = (u128::from(position.interest_rate) * position.debt_value
+        + u128::from(added_position.interest_rate) * added_position.debt_value)
+        .checked_div(net_debt_value)
+    {
+        position.interest_rate = interest_rate as u32;
+    }
+
+    position.funding_index = _blend_funding_index(
+        position_value,
+        position.funding_index,
+        added_position_value,
+        added_position.funding_index,
+    );
+
+    position.entry_tick = _price_to_tick(entry_price);
+    position.collateral_value += added_position.collateral_value;
+    position.debt_value = net_debt_value;
+    position.volume_share += added_position.volume_share;
+    position.timestamp = ic_cdk::api::time();
+}
+
+/// Convert Account Limit Position
+///
+/// Params:
+///  - Position Key :The account of the owner and the id of the position
+///
+/// Returns
+///   - is Fully Filled :Returns true  the limit order has been fully filled or returns false otherwise
+///   - is Partially Filled :true if the position partially filled
+///   - Removed Collateral :The collateral refunded for the unfilled part of the order
+///   - Manage Debt Params :The params carrying the debt refunded for the unfilled part of the order and the maker fee charged on conversion
+fn _convert_account_limit_position(
+    position_key: PositionKey,
+) -> (bool, bool, Amount, ManageDebtParams) {
+    let mut position = _get_account_position(&position_key);
+
+    if let PositionOrderType::Limit(order) = position.order_type {
+        let (amount_out, amount_remaining) = _close_order(&order);
+
+        let is_fully_filled = amount_remaining == 0;
+        let is_partially_filled = amount_out > 0;
+
+        let amount_remaining_value = _limit_order_remaining_value(&position, amount_remaining);
+
+        let (removed_collateral, manage_debt_params) =
+            _convert_limit_position(&mut position, amount_remaining_value);
+        _insert_account_position(position_key, position);
+
+        return (
+            is_fully_filled,
+            is_partially_filled,
+            removed_collateral,
+            manage_debt_params,
+        );
+    }
+    (false, false, 0, ManageDebtParams::default())
+}
+
+/// Limit Order Remaining Value
+///
+/// Params
+///  - Position :The limit position
+///  - Amount Remaining :The unfilled amount of the position's limit order ,in the perp asset for a short position
+///
+/// Returns
+///  - Amount Remaining Value :The value of the unfilled amount in the collateral token
+fn _limit_order_remaining_value(position: &PositionDetails, amount_remaining: Amount) -> Amount {
+    if position.long {
+        return amount_remaining;
+    }
+
+    let entry_price = _tick_to_price(position.entry_tick);
+
+    _equivalent(amount_remaining, entry_price, false)
+}
+
+/// Convert Limit Position function
+///
+/// Converts a limit position into a market position after the reference limit order of that position has been filled fully or partially
+/// any unfilled amount is refunded first as debt and if still remaining it is refunded back to the position owner and the position is updated to a market position
+///
+/// The maker fee is charged on (or the maker rebate added to) the filled value (see Charge Maker Fee)
+///
+/// Params
+///  - Position : A mutable reference to the cuurent position
+///  - Amount Remaining Value : The value of the amount of  unfilled liquidity of the particular order
+///
+/// Returns
+///  - Removed Collateral : The amount of collateral removed from that position
+///  - Update Asset Details Params :The update asset details params for updating asset detailsin params   
+fn _convert_limit_position(
+    position: &mut PositionDetails,
+    amount_remaining_value: Amount,
+) -> (Amount, ManageDebtParams) {
+    let initial_collateral_value: u128 = position.collateral_value;
+
+    let initial_debt_value = position.debt_value;
+
+    let removed_collateral;
+    if amount_remaining_value > initial_debt_value {
+        removed_collateral = amount_remaining_value - initial_debt_value;
+
+        position.debt_value = 0;
+        position.collateral_value -= removed_collateral;
+    } else {
+        removed_collateral = 0;
+
+        position.debt_value -= amount_remaining_value;
+    }
+
+    let filled_order_value = initial_collateral_value + initial_debt_value - amount_remaining_value;
+
+    let (remaining_order_value, trading_fee) = _charge_maker_fee(position, filled_order_value);
+
+    let volume_share = _calc_position_volume_share(remaining_order_value, position.long);
+
+    position.volume_share = volume_share;
+    position.order_type = PositionOrderType::Market;
+    position.timestamp = ic_cdk::api::time();
+    position.funding_index = _get_funding_index(position.long);
+
+    let mut manage_debt_params = ManageDebtParams::init(
+        initial_debt_value,
+        initial_debt_value,
+        initial_debt_value - position.debt_value,
+    );
+
+    manage_debt_params.trading_fee = trading_fee;
+
+    return (removed_collateral, manage_debt_params);
+}
+
+/// Liquidation Status Function
+///
+/// Checks if a position is to be liquidated
+///
+/// Params ;
+///  - Position :The Position to check
+///  - Maintenance Margin :The current maintenance margin of the market
+///
+/// Returns
+///  - To Liquidate :true if position should be liquidated
+///
+/// Note :A position is liquidated once the mark tick reaches it's liquidation tick (see Get Position Liquidation Tick)
+fn _liquidation_status(position: PositionDetails, maintenance_margin: u64) -> bool {
+    let liquidation_tick = match _get_position_liquidation_tick(position, maintenance_margin) {
+        Some(tick) => tick,
+        None => return false,
+    };
+
+    let mark_tick = _get_mark_tick();
+
+    if position.long {
+        mark_tick <= liquidation_tick
+    } else {
+        mark_tick >= liquidation_tick
+    }
+}
+
+/// Get Position Liquidation Tick
+///
+/// Gets the tick at which a market position becomes liquidatable with it's current debt and accrued interest
+///
+/// Returns
+///  - Liquidation Tick :none if position is not a market position or has no debt
+fn _get_position_liquidation_tick(
+    position: PositionDetails,
+    maintenance_margin: u64,
+) -> Option<Tick> {
+    if let PositionOrderType::Limit(_) = position.order_type {
+        return None;
+    }
+
+    let position_realised_value = _apply_position_funding(
+        &position,
+        _get_position_realised_value(position.volume_share, position.long),
+    );
+
+    let interest_value = _calc_interest(
+        position.debt_value,
+        position.interest_rate,
+        position.timestamp,
+    );
+
+    _liquidation_tick(
+        position.long,
+        position.entry_tick,
+        position_realised_value,
+        position.debt_value + interest_value,
+        maintenance_margin,
+    )
+}
+
+/// Opens Order Functions
+///
+/// opens an order at a particular tick
+///
+/// Params
+/// - Order :: a generic type that implements the trait Order for the type of order to close
+/// - Reference Tick :: The  tick to place order
+fn _open_order(order: &mut LimitOrder) {
+    TICKS_DETAILS.with_borrow_mut(|ticks_details| {
+        INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
+            let mut open_order_params = OpenOrderParams {
+                order,
+                integrals_bitmaps,
+                ticks_details,
+                maker_fee: _get_state_details().maker_fee,
+            };
+            open_order_params.open_order();
+        })
+    });
+}
+/// Close Order Function
+///
+/// closes an order at a particular tick
+///
+/// Params :
+///  - Order :: a generic that implements the trait Order for the type of order to close
+///  - Order Size :: Tha amount of asset in order
+///  - Order Direction :: Either a buy or a sell
+///  - Order Reference Tick :: The tick where order was placed  
+///
+/// Returns
+///  - Amont Out :: This corresponds to the asset to be bought i.e perp(base) asset for a buy order or quote asset for a sell order
+///  - Amount Remaining :: This amount remaining corrseponds to the amount of asset at that tick that is still unfilled
+///
+fn _close_order(order: &LimitOrder) -> (Amount, Amount) {
+    TICKS_DETAILS.with_borrow_mut(|ticks_details| {
+        INTEGRAL_BITMAPS.with_borrow_mut(|multipliers_bitmaps| {
+            let mut close_order_params = CloseOrderParams {
+                order,
+                integrals_bitmaps: multipliers_bitmaps,
+                ticks_details,
+            };
+            close_order_params.close_order()
+        })
+    })
+}
+
+/// Swap Function
+///
+/// Params
+///  - Account :: The account of the swap initiator
+///  - Order Size :: Tha amount of asset in order
+///  - Buy :: the order direction ,true for buy and false otherwise
+///  - Init Tick :: The current state tick
+///  - Stopping Tick :: The maximum tick ,corresponds to maximum price
+///
+/// Returns
+///  - Amount Out :: The amount out froom swapping
+///  - Amount Remaining :: The amount remaining from swapping
+///  - resulting Tick :The last tick at which swap occured
+///  - Crossed Ticks :: An vector of all ticks crossed during swap
+///  - Swap Fees :: The taker and maker fees charged at the current state fees
+///
+/// Note
+///  - All stop loss and take profit triggers between the current tick before the swap and the resulting tick are scheduled for execution
+///  - The fill is appended to the trades log (see Trade Record) and added to the candles (see Update Candles)
+///  - The swap fees are only reported ,deducting the taker fee is left to the caller (see Collect Swap Fees)
+fn _swap(
+    account: Subaccount,
+    order_size: Amount,
+    buy: bool,
+    init_tick: Tick,
+    stopping_tick: Tick,
+) -> (Amount, Amount, Tick, Vec<Tick>, SwapFees) {
+    let state_details = _get_state_details();
+
+    let (amount_out, amount_remaining, resulting_tick, crossed_ticks, swap_fees) = TICKS_DETAILS
+        .with_borrow_mut(|ticks_details| {
+            INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
+                let mut swap_params = SwapParams {
+                    buy,
+                    init_tick,
+                    stopping_tick,
+                    order_size,
+                    integrals_bitmaps,
+                    ticks_details,
+                    taker_fee: state_details.taker_fee,
+                    dry_run: false,
+                };
+                swap_params._swap()
+            })
+        });
+
+    if amount_out > 0 {
+        _schedule_execution_for_triggers(state_details.current_tick, resulting_tick, buy);
+
+        let trade_record = TradeRecord {
+            account,
+            buy,
+            size: order_size - amount_remaining,
+            amount_out,
+            average_price: _average_price(buy, order_size - amount_remaining, amount_out),
+            resulting_tick,
+            crossed_ticks: crossed_ticks.clone(),
+            timestamp: ic_cdk::api::time(),
+        };
+
+        _update_candles(&trade_record);
+
+        _append_trade_record(trade_record);
+    }
+
+    (
+        amount_out,
+        amount_remaining,
+        resulting_tick,
+        crossed_ticks,
+        swap_fees,
+    )
+}
+
+/// Dry Run Swap Function
+///
+/// Simulates a swap without mutating the ticks details or the integrals bitmaps
+///
+/// Params :See Swap Function for params definition
+///
+/// Returns :See Swap Function ,the values that would result if the swap was executed
+fn _dry_run_swap(
+    order_size: Amount,
+    buy: bool,
+    init_tick: Tick,
+    stopping_tick: Tick,
+) -> (Amount, Amount, Tick, Vec<Tick>, SwapFees) {
+    let state_details = _get_state_details();
+
+    TICKS_DETAILS.with_borrow_mut(|ticks_details| {
+        INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
+            let mut swap_params = SwapParams {
+                buy,
+                init_tick,
+                stopping_tick,
+                order_size,
+                integrals_bitmaps,
+                ticks_details,
+                taker_fee: state_details.taker_fee,
+                dry_run: true,
+            };
+            swap_params._swap()
+        })
+    })
+}
+
+/// Quote Swap Function
+///
+/// Simulates a swap starting from the best offer and summarises the expected fill
+///
+/// Params
+///  - Order Size :The amount to swap ,in the quote token for a buy and in the perp asset for a sell
+///  - Buy :The swap direction
+///  - Current Tick :The current state tick
+///  - Stopping Tick :The max executing tick
+fn _quote_swap(
+    order_size: Amount,
+    buy: bool,
+    current_tick: Tick,
+    stopping_tick: Tick,
+) -> SwapQuote {
+    let init_tick = if buy {
+        Some(current_tick)
+    } else {
+        get_best_offer(false, current_tick, Some(stopping_tick))
+    };
+
+    let Some(init_tick) = init_tick else {
+        return SwapQuote {
+            amount_in: 0,
+            amount_out: 0,
+            amount_remaining: order_size,
+            average_price: 0,
+            resulting_tick: current_tick,
+            crossed_ticks: Vec::new(),
+            taker_fee: 0,
+        };
+    };
+
+    let (amount_out, amount_remaining, resulting_tick, crossed_ticks, swap_fees) =
+        _dry_run_swap(order_size, buy, init_tick, stopping_tick);
+
+    let amount_in = order_size - amount_remaining;
+
+    let average_price = _average_price(buy, amount_in, amount_out);
+
+    SwapQuote {
+        amount_in,
+        amount_out,
+        amount_remaining,
+        average_price,
+        resulting_tick: if amount_out == 0 {
+            current_tick
+        } else {
+            resulting_tick
+        },
+        crossed_ticks,
+        taker_fee: swap_fees.taker_fee,
+    }
+}
+
+/// Average Price
+///
+/// Calculates the average execution price of a swap as the quote token value over the perp asset amount traded
+///
+/// Returns
+///  - Average Price :The price in the same decimals as tick prices (see tick_lib) ,zero if nothing was traded
+fn _average_price(buy: bool, amount_in: Amount, amount_out: Amount) -> Amount {
+    match (amount_in, amount_out) {
+        (0, _) | (_, 0) => 0,
+        _ if buy => (amount_in * _PRICE_DECIMAL) / amount_out,
+        _ => (amount_out * _PRICE_DECIMAL) / amount_in,
+    }
+}
+
+/// Collect Swap Fees
+///
+/// Derives the taker fee deducted from the swap initiator and the part of it forwarded to the vault
+///
+/// Params
+///  - Swap Fees :The fees reported by the swap
+///  - Max Fee :The max value the taker fee can be deducted from
+///
+/// Returns
+///  - Taker Fee :The fee to deduct from the swap initiator
+///  - Trading Fee :The fee forwarded to the vault ,the part of the taker fee reserved for maker rebates is held back
+fn _collect_swap_fees(swap_fees: SwapFees, max_fee: Amount) -> (Amount, Amount) {
+    let taker_fee = swap_fees.taker_fee.min(max_fee);
+
+    let maker_rebate = if swap_fees.maker_fee < 0 {
+        swap_fees.maker_fee.unsigned_abs()
+    } else {
+        0
+    };
+
+    (taker_fee, taker_fee.saturating_sub(maker_rebate))
+}
+
+/// Charge Maker Fee
+///
+/// Charges or rebates the maker fee on the filled value of a limit position
+///
+/// Params
+///  - Position :A mutable reference to the limit position being converted
+///  - Filled Value :The value of the limit order filled
+///
+/// Returns
+///  - Filled Value :The filled value after the maker fee or rebate
+///  - Trading Fee :The maker fee forwarded to the vault ,zero for a rebate
+///
+/// Note
+///  - The maker fee is taken from the position collateral while a rebate is added to it
+///  - The fee is the one recorded on the limit order when placed ,which is the fee the swap charged for the fill (see TickDetails)
+fn _charge_maker_fee(position: &mut PositionDetails, filled_value: Amount) -> (Amount, Amount) {
+    let maker_fee = match position.order_type {
+        PositionOrderType::Limit(order) => order.maker_fee,
+        PositionOrderType::Market => 0,
+    };
+
+    let fee_value = _percentage128(maker_fee.unsigned_abs(), filled_value);
+
+    if maker_fee < 0 {
+        position.collateral_value += fee_value;
+
+        return (filled_value + fee_value, 0);
+    }
+
+    let fee_value = fee_value.min(position.collateral_value);
+
+    position.collateral_value -= fee_value;
+
+    (filled_value - fee_value, fee_value)
+}
+
+fn get_best_offer(buy: bool, current_tick: Tick, stopping_tick: Option<Tick>) -> Option<Tick> {
+    let max_tick = match stopping_tick {
+        Some(val) => val,
+        None => max_or_default_max(None, current_tick, buy),
+    };
+    TICKS_DETAILS.with_borrow_mut(|ticks_details| {
+        INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
+            _get_best_offer(
+                buy,
+                current_tick,
+                max_tick,
+                integrals_bitmaps,
+                ticks_details,
+            )
+        })
+    })
+}
+
+/// Max or Default Max Tick
+///
+/// retrieves the max tick if valid else returns the default max tick
+fn max_or_default_max(max_tick: Option<Tick>, current_tick: Tick, buy: bool) -> Tick {
+    match max_tick {
+        Some(tick) => {
+            if buy && tick <= _def_max_tick(current_tick, true) {
+                return tick;
+            };
+            if !buy && tick >= _def_max_tick(current_tick, false) {
+                return tick;
+            }
+        }
+        None => {
+            if buy {
+                return _def_max_tick(current_tick, true);
+            } else {
+                return _def_max_tick(current_tick, false);
+            }
+        }
+    }
+    return _def_max_tick(current_tick, buy);
+}
+/// Calculate Position PNL
+///
+/// Calculates the current pnl in percentage  for a particular position
+///
+/// Returns
+///  PNL :The pnl(in percentage) on that position
+///  Net Debt Value :The net debt on that position
+fn _calculate_position_pnl_and_net_debt_value(position: PositionDetails) -> (i64, Amount) {
+    let equivalent = |amount: Amount, tick: Tick, buy: bool| {
+        let tick_price = _tick_to_price(tick);
+        _equivalent(amount, tick_price, buy)
+    };
+
+    let mark_tick = _get_mark_tick();
+
+    let position_realised_value = _apply_position_funding(
+        &position,
+        _get_position_realised_value(position.volume_share, position.long),
+    );
+
+    let interest_on_debt_value = _calc_interest(
+        position.debt_value,
+        position.interest_rate,
+        position.timestamp,
+    );
+
+    let net_debt_value = position.debt_value + interest_on_debt_value;
+
+    let pnl;
+
+    if position.long {
+        let init_position_value = (position.debt_value + position.collateral_value) as i128;
+
+        let position_realised_size = equivalent(position_realised_value, position.entry_tick, true);
+
+        let position_current_value = equivalent(position_realised_size, mark_tick, false) as i128;
+
+        pnl = ((position_current_value - (interest_on_debt_value as i128) - init_position_value)
+            * (100 * _ONE_PERCENT as i128))
+            / init_position_value;
+    } else {
+        let init_position_size = equivalent(
+            position.debt_value + position.collateral_value,
+            position.entry_tick,
+            true,
+        ) as i128;
+
+        let position_current_size = equivalent(position_realised_value, mark_tick, true) as i128;
+
+        let interest_on_debt = equivalent(interest_on_debt_value, position.entry_tick, true);
+
+        pnl = ((position_current_size - (interest_on_debt as i128) - init_position_size)
+            * (100 * _ONE_PERCENT as i128))
+            / init_position_size;
+    }
+    return (pnl as i64, net_debt_value);
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////
+//////////////////////////////////////////////////////////////////////////////////////////////
+///  Funding Rate Functions
+///////////////////////////////////////////////////////////////////////////////////////////////
+///////////////////////////////////////////////////////////////////////////////////////////////
+/// Settle Funcding Rate
+///
+/// Settles Funding Rate by fetching the spot price from the market's price oracle ,calculating the premium and distributing the  fund to the right market direction,Long or Short
+///
+/// Params
+///  - Attempt :The number of times fetching the spot price has failed within this funding interval
+///
+/// Note
+///  - The perp price is the time weighted average price since the last settlement (see Price Accumulator)
+///  - On failure fetching the spot price is retried up to the max oracle retries with an exponential backoff
+///  - Once the retries are exhausted funding is settled on the last good spot price or paused if the last good spot price is stale
+async fn settle_funding_rate(attempt: u32) {
+    match _fetch_spot_price(_get_market_details()).await {
+        Ok(spot_price) => {
+            _record_oracle_price(&spot_price);
+
+            _settle_funding_on_spot_price(spot_price.price, spot_price.decimals);
+        }
+        Err(error) => {
+            _record_oracle_failure(error);
+
+            if attempt < _MAX_ORACLE_RETRIES {
+                let delay = _ORACLE_RETRY_DELAY * 2u64.pow(attempt);
+
+                ic_cdk_timers::set_timer(Duration::from_nanos(delay), move || {
+                    ic_cdk::spawn(async move { settle_funding_rate(attempt + 1).await });
+                });
+                return;
+            }
+
+            let oracle_details = _get_oracle_details();
+
+            if _is_oracle_stale(&oracle_details, &_get_funding_config()) {
+                _pause_funding();
+            } else {
+                _settle_funding_on_spot_price(oracle_details.last_price, oracle_details.decimals);
+            }
+        }
+    }
+}
+
+/// Settle Funding On Spot Price
+///
+/// Settles the funding rate with the mark price scaled to the decimals of the spot price
+///
+/// Note:The mark tick is derived from the average tick over the funding window before a new window is started
+fn _settle_funding_on_spot_price(spot_price: u128, decimals: u32) {
+    _update_mark_price();
+
+    let mark_price = _tick_to_price(_get_mark_tick());
+
+    _settle_average_tick();
+
+    let perp_price = (mark_price * 10u128.pow(decimals)) / _BASE_PRICE;
+
+    _settle_funding_rate(perp_price, spot_price);
+}
+
+/// Pause Funding
+///
+/// Accrues the funding at the previous funding rate up to now and sets the funding rate to zero until the next settlement
+fn _pause_funding() {
+    let now = ic_cdk::api::time();
+
+    FUNDING_RATE_TRACKER.with_borrow_mut(|reference| {
+        let mut funding_rate_tracker = *reference.get();
+
+        funding_rate_tracker.accrue_funding(now);
+
+        funding_rate_tracker.funding_rate = 0;
+
+        reference.set(funding_rate_tracker).unwrap();
+    });
+}
+
+/// Settle Funding Rate (Private)
+///
+/// Accrues the funding at the previous funding rate up to now and sets the funding rate for the next funding window
+///
+/// Note
+///  - Funding accrues every second at the funding rate through the funding indexes (see Accrue Funding) and each position pays or receives funding for the time it was open (see Apply Position Funding)
+fn _settle_funding_rate(perp_price: u128, spot_price: u128) {
+    let FundingConfig {
+        max_funding_rate,
+        dead_band,
+        interest_rate,
+        ..
+    } = _get_funding_config();
+
+    let funding_rate = _calc_funding_rate(
+        _calc_premium(perp_price, spot_price),
+        max_funding_rate,
+        dead_band,
+        interest_rate,
+    );
+
+    let now = ic_cdk::api::time();
+
+    FUNDING_RATE_TRACKER.with_borrow_mut(|reference| {
+        let mut funding_rate_tracker = reference.get().clone();
+
+        funding_rate_tracker.accrue_funding(now);
+
+        funding_rate_tracker.funding_rate = funding_rate;
+
+        reference.set(funding_rate_tracker).unwrap();
+    });
+
+    FUNDING_HISTORY.with_borrow_mut(|reference| {
+        reference.insert(
+            now,
+            FundingRecord {
+                funding_rate,
+                perp_price,
+                spot_price,
+            },
+        )
+    });
+}
+
+/// Get Funding Index
+///
+/// Gets the current funding index of a market direction ,Long or Short
+fn _get_funding_index(long: bool) -> i128 {
+    let (long_funding_index, short_funding_index) = FUNDING_RATE_TRACKER
+        .with_borrow(|reference| reference.get().funding_indexes(ic_cdk::api::time()));
+
+    if long {
+        return long_funding_index;
+    }
+    short_funding_index
+}
+
+/// Apply Position Funding
+///
+/// Deducts the funding paid by a position from the realised value of the position or adds the funding received to it
+///
+/// Params
+///  - Position :The position
+///  - Position Realised Value :The realised value of the position's volume share
+///
+/// Note
+///  - The funding is the realised value multiplied by the change in the funding index since the position funding index
+///  - The funding paid is bounded by the realised value while the funding received is paid in full (see Apply Funding)
+fn _apply_position_funding(position: &PositionDetails, position_realised_value: Amount) -> Amount {
+    let funding_index_delta = _get_funding_index(position.long) - position.funding_index;
+
+    _apply_funding(position_realised_value, funding_index_delta)
+}
+
+/// Blend Funding Index
+///
+/// Blends the funding indexes of two parts of a position by value so the funding owed by both parts is unchanged
+fn _blend_funding_index(
+    value: Amount,
+    funding_index: i128,
+    other_value: Amount,
+    other_funding_index: i128,
+) -> i128 {
+    let total_value = (value + other_value) as i128;
+
+    if total_value == 0 {
+        return funding_index;
+    }
+
+    (value as i128 * funding_index + other_value as i128 * other_funding_index) / total_value
+}
+
+fn _get_funding_config() -> FundingConfig {
+    FUNDING_CONFIG.with_borrow(|reference| *reference.get())
+}
+
+fn _get_oracle_details() -> OracleDetails {
+    ORACLE_DETAILS.with_borrow(|reference| reference.get().clone())
+}
+
+fn _get_price_feed() -> PriceFeed {
+    PRICE_FEED.with_borrow(|reference| *reference.get())
+}
+
+/// Fetch Spot Price
+///
+/// Fetches the spot price of the market pair from the price oracle selected in the market details
+async fn _fetch_spot_price(market_details: MarketDetails) -> Result<SpotPrice, OracleError> {
+    match market_details.oracle {
+        OracleKind::XRC => {
+            let xrc = XRC::init(
+                market_details.xrc_id,
+                market_details.base_asset,
+                market_details.quote_asset,
+            );
+            return xrc._get_spot_price().await;
+        }
+        OracleKind::Pushed => return PushedOracle::init()._get_spot_price().await,
+        OracleKind::Mock => return MockOracle::init()._get_spot_price().await,
+    }
+}
+
+/// Record Oracle Price
+///
+/// Records the spot price as the last good spot price and resets the failures
+fn _record_oracle_price(spot_price: &SpotPrice) {
+    ORACLE_DETAILS.with_borrow_mut(|reference| {
+        reference
+            .set(OracleDetails {
+                last_price: spot_price.price,
+                decimals: spot_price.decimals,
+                last_updated: spot_price.timestamp,
+                consecutive_failures: 0,
+                last_error: None,
+            })
+            .unwrap();
+    });
+
+    _update_mark_price();
+}
+
+/// Record Oracle Failure
+///
+/// Records a failed fetch of the spot price ,the last good spot price is kept
+fn _record_oracle_failure(error: OracleError) {
+    ORACLE_DETAILS.with_borrow_mut(|reference| {
+        let mut oracle_details = reference.get().clone();
+
+        oracle_details.consecutive_failures += 1;
+
+        oracle_details.last_error = Some(error);
+
+        reference.set(oracle_details).unwrap();
+    });
+}
+
+/// Is Oracle Stale
+///
+/// Returns true if no spot price has been fetched or the last good spot price is older than the max oracle staleness
+fn _is_oracle_stale(oracle_details: &OracleDetails, funding_config: &FundingConfig) -> bool {
+    if oracle_details.last_updated == 0 {
+        return true;
+    }
+
+    let age = ic_cdk::api::time().saturating_sub(oracle_details.last_updated);
+
+    age > funding_config.max_oracle_staleness * ONE_SECOND
+}
+
+/// Leverage Paused By Oracle
+///
+/// Returns true if opening or increasing positions with debt is paused due to a stale spot price
+fn _leverage_paused_by_oracle() -> bool {
+    let funding_config = _get_funding_config();
+
+    if !funding_config.pause_leverage_on_stale_oracle {
+        return false;
+    }
+
+    _is_oracle_stale(&_get_oracle_details(), &funding_config)
+}
+///Calculate Position Realised value
+///
+///Calculates the Realised value for a position's volume share in a particular market direction,Long or Short   
+///
+/// Note:This function also adjust's the volume share
+fn _calc_position_realised_value(volume_share: Amount, long: bool) -> Amount {
+    FUNDING_RATE_TRACKER.with_borrow_mut(|tr| {
+        let mut funding_rate_tracker = tr.get().clone();
+
+        // funding is accrued on the volume before the volume changes
+        funding_rate_tracker.accrue_funding(ic_cdk::api::time());
+
+        let value = funding_rate_tracker.remove_volume(volume_share, long);
+
+        tr.set(funding_rate_tracker).unwrap();
+        value
+    })
+}
+/// Get Position Realised Value
+///
+/// Gets the current value of a position's volume share without removing it from the market volume
+///
+/// Note:Utilised for checks that must not update the funding rate tracker i.e pnl and liquidation status
+fn _get_position_realised_value(volume_share: Amount, long: bool) -> Amount {
+    FUNDING_RATE_TRACKER.with_borrow(|tr| tr.get().volume_value(volume_share, long))
+}
+
+/// Calculate Position Volume Share
+///
+/// Calculates the volume share for a particular poistion volume in a market direction ,Long or Short
+fn _calc_position_volume_share(position_value: Amount, long: bool) -> Amount {
+    FUNDING_RATE_TRACKER.with_borrow_mut(|tr| {
+        let mut funding_rate_tracker = tr.get().clone();
+
+        funding_rate_tracker.accrue_funding(ic_cdk::api::time());
+
+        let value = funding_rate_tracker.add_volume(position_value, long);
+
+        tr.set(funding_rate_tracker).unwrap();
+        value
+    })
+}
+////////////////////////////////////////////////////////////////////////////////////////////////
+///////////////////////////////////////////////////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////////////////////
+
+//////////////////////////////////////////////////////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////////////////////
+///  Limit Order Functions
+/////////////////////////////////////////////////////////////////////////////////////////////////
+//////////////////////////////////////////////////////////////////////////////////////////////
+/// Store Tick Order
+///
+/// Stores an order under a particular tick
+///
+/// Utilised when positions are opened as limit orders
+///
+///Params
+/// - Tick    :The tickat which order is placed
+/// - Position Key : The account opening the position and the id of the position
+pub fn store_tick_order(tick: Tick, position_key: PositionKey) {
+    LIMIT_ORDERS_RECORD.with_borrow_mut(|reference| {
+        reference.insert((tick, position_key.0, position_key.1), ());
+    })
+}
+
+/// Remove Tick Order
+///
+/// Removes an order under a particular tick
+///
+/// Utilised when account owner closes a limit position before reference tick is fully crossed
+///
+/// Params
+/// - Tick    :The tickat which order was placed
+/// - Position Key : The account closing the position and the id of the position
+pub fn remove_tick_order(tick: Tick, position_key: PositionKey) {
+    LIMIT_ORDERS_RECORD.with_borrow_mut(|reference| {
+        reference.remove(&(tick, position_key.0, position_key.1));
+    })
+}
+
+/// Schedule Execution For Ticks Orders
+///
+/// Moves the orders at the crossed ticks from the limit orders record to the crossed ticks queue and schedules the queue to be drained
+///
+/// Params
+/// - Crossed Ticks :The ticks crossed during a swap (meaning all orders at those ticks have been filled)
+///
+/// Note
+///  - Only the orders at the tick when crossed are queued ,orders placed at the tick afterwards rest in the book
+fn _schedule_execution_for_ticks_orders(crossed_ticks: Vec<Tick>) {
+    if crossed_ticks.len() == 0 {
+        return;
+    }
+
+    for tick in crossed_ticks {
+        let ticks_orders: Vec<TickOrderKey> = LIMIT_ORDERS_RECORD.with_borrow(|reference| {
+            reference
+                .range((tick, [0u8; 32], 0)..=(tick, [u8::MAX; 32], PositionId::MAX))
+                .map(|(tick_order, _)| tick_order)
+                .collect()
+        });
+
+        for (tick, account, position_id) in ticks_orders {
+            remove_tick_order(tick, (account, position_id));
+
+            CROSSED_TICKS_QUEUE
+                .with_borrow_mut(|reference| reference.insert((tick, account, position_id), ()));
+        }
+    }
+
+    _schedule_limit_orders_execution();
+}
+
+/// Schedule Limit Orders Execution
+///
+/// Sets the execution timer for draining the crossed ticks queue if it is not already set
+///
+/// Note:Utilised in post upgrade as timers do not persist across upgrades
+fn _schedule_limit_orders_execution() {
+    if _get_pending_timer() != TimerId::default() {
+        return;
+    }
+
+    let timer_id = ic_cdk_timers::set_timer(Duration::from_nanos(0), || {
+        _set_pending_timer(TimerId::default());
+
+        _execute_ticks_orders();
+    });
+
+    _set_pending_timer(timer_id);
+}
+
+/// Execute Ticks Orders
+///
+/// Converts the limit positions at the crossed ticks queue into market positions until the queue is empty
+/// or the instruction budget for a single execution is exhausted
+///
+/// Note
+///  - Each order is removed from the queue as it is executed ,so an order is never executed twice
+///  - The execution does not trap ,since a trap would revert the cleared pending timer and the queue would never be drained again
+///  - The timer is re-armed while the queue is not empty
+fn _execute_ticks_orders() {
+    while ic_cdk::api::instruction_counter() < _EXECUTION_INSTRUCTION_BUDGET {
+        let tick_order = match CROSSED_TICKS_QUEUE
+            .with_borrow_mut(|reference| reference.pop_first().map(|(tick_order, _)| tick_order))
+        {
+            Some(tick_order) => tick_order,
+            None => break,
+        };
+
+        let (_, account, position_id) = tick_order;
+
+        _execute_limit_order((account, position_id));
+    }
+
+    if !CROSSED_TICKS_QUEUE.with_borrow(|reference| reference.is_empty()) {
+        _schedule_limit_orders_execution();
+    }
+}
+
+/// Execute Limit Order
+///
+/// Converts a filled limit position into a market position and sends the maker fee to the vault
+///
+/// Note:A position that has been closed or already converted is left unchanged
+fn _execute_limit_order(position_key: PositionKey) {
+    if !ACCOUNTS_POSITION.with_borrow(|reference| reference.contains_key(&position_key)) {
+        return;
+    }
+
+    let (_, _, removed_collateral, manage_debt_params) =
+        _convert_account_limit_position(position_key);
+
+    _send_conversion_update(position_key, removed_collateral, manage_debt_params);
+}
+
+/// Send Conversion Update
+///
+/// Sends the refund of the unfilled part of a converted limit position and the maker fee charged on conversion to the vault
+///
+/// Note:If the owner of the account is not recorded ,the update is stored as an error log of the account instead of trapping
+fn _send_conversion_update(
+    position_key: PositionKey,
+    removed_collateral: Amount,
+    manage_debt_params: ManageDebtParams,
+) {
+    if removed_collateral == 0 && !manage_debt_params.requires_update() {
+        return;
+    }
+
+    let owner = match _get_account_owner(&position_key.0) {
+        Some(owner) => owner,
+        None => {
+            // the owner is set when the update is retried (see Retry Account Error)
+            let error_log = PositionUpdateErrorLog {
+                user: Principal::anonymous(),
+                profit: removed_collateral,
+                debt_params: manage_debt_params,
+                liquidation: None,
+            };
+            _insert_account_error_log(position_key.0, error_log);
+            return;
+        }
+    };
+
+    let vault = Vault::init(_get_market_details().vault_id);
+
+    vault.manage_position_update(owner, removed_collateral, manage_debt_params);
+}
+
+/// Crossed Tick Order
+///
+/// Checks if a limit position is resting at a tick that has been fully crossed but not yet executed
+///
+/// Params
+///  - Position Key :The account and the id of the position
+///  - Position :The details of the position
+fn _is_crossed_tick_order(position_key: PositionKey, position: &PositionDetails) -> bool {
+    if let PositionOrderType::Market = position.order_type {
+        return false;
+    }
+
+    let tick = position.entry_tick;
+
+    CROSSED_TICKS_QUEUE
+        .with_borrow(|reference| reference.contains_key(&(tick, position_key.0, position_key.1)))
+}
+
+/// Settle Crossed Tick Order
+///
+/// Converts a limit position resting at a fully crossed tick into a market position without waiting for the execution queue
+///
+/// Returns
+///  - Removed Collateral :The collateral refunded for the unfilled part of the order
+///  - Manage Debt Params :The params carrying the refunded debt and the maker fee charged on conversion ,default if the position was not converted
+///
+/// Note
+///  - Queries utilise this directly to return the effective post fill position ,the conversion is discarded at the end of the query
+///  - Updates utilise Settle Account Position which also sends the maker fee to the vault
+fn _settle_crossed_tick_order(position_key: PositionKey) -> (Amount, ManageDebtParams) {
+    let position = match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key)) {
+        Some(position) => position,
+        None => return (0, ManageDebtParams::default()),
+    };
+
+    if !_is_crossed_tick_order(position_key, &position) {
+        return (0, ManageDebtParams::default());
+    }
+
+    CROSSED_TICKS_QUEUE.with_borrow_mut(|reference| {
+        reference.remove(&(position.entry_tick, position_key.0, position_key.1))
+    });
+
+    let (_, _, removed_collateral, manage_debt_params) =
+        _convert_account_limit_position(position_key);
+
+    (removed_collateral, manage_debt_params)
+}
+
+/// Settle Account Position
+///
+/// Settles any pending fill of a position before it is read or updated (see Settle Crossed Tick Order)
+fn _settle_account_position(position_key: PositionKey) {
+    let (removed_collateral, manage_debt_params) = _settle_crossed_tick_order(position_key);
+
+    _send_conversion_update(position_key, removed_collateral, manage_debt_params);
+}
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////
+
+//////////////////////////////////////////////////////////////////////////////////////////////
+///////////////////////////////////////////////////////////////////////////////////////////////
+///  Position Triggers Functions
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////
+/// Valid Triggers
+///
+/// Checks that the stop loss and take profit ticks are on the right side of the reference tick
+///
+/// Note
+///  - For a long position ,stop loss tick must be below the reference tick and take profit tick above it ,the reverse for a short position
+fn _valid_triggers(
+    long: bool,
+    reference_tick: Tick,
+    stop_loss_tick: Option<Tick>,
+    take_profit_tick: Option<Tick>,
+) -> bool {
+    let valid_stop_loss = match stop_loss_tick {
+        Some(tick) => {
+            if long {
+                tick < reference_tick
             } else {
-                (removed_collateral, manage_debt_params) =
-                    _convert_limit_position(position, amount_remaining_value);
+                tick > reference_tick
+            }
+        }
+        None => true,
+    };
+
+    let valid_take_profit = match take_profit_tick {
+        Some(tick) => {
+            if long {
+                tick > reference_tick
+            } else {
+                tick < reference_tick
+            }
+        }
+        None => true,
+    };
+
+    valid_stop_loss && valid_take_profit
+}
+
+/// Set Position Triggers (Private)
+///
+/// Replaces the stop loss and take profit ticks of a position ,stores each trigger under it's tick and stores the position
+///
+/// Params
+/// - User :The owner of the position
+/// - Position Key :The account of the position owner and the id of the position
+/// - Position :The position to update
+/// - Stop Loss Tick :The new stop loss tick
+/// - Take Profit Tick :The new take profit tick
+fn _set_position_triggers(
+    user: Principal,
+    position_key: PositionKey,
+    position: &mut PositionDetails,
+    stop_loss_tick: Option<Tick>,
+    take_profit_tick: Option<Tick>,
+) {
+    _remove_position_triggers(position_key, position);
+
+    let (account, position_id) = position_key;
 
-                _insert_account_position(account, position.clone());
+    position.stop_loss_tick = stop_loss_tick;
+    position.take_profit_tick = take_profit_tick;
+
+    POSITIONS_TRIGGERS.with_borrow_mut(|reference| {
+        // a long position's stop loss is reached by selling and it's take profit by buying ,the reverse for a short position
+        if let Some(tick) = stop_loss_tick {
+            reference.insert((tick, account, position_id), (user, !position.long));
+        }
+        if let Some(tick) = take_profit_tick {
+            reference.insert((tick, account, position_id), (user, position.long));
+        }
+    });
+
+    _insert_account_position(position_key, *position);
+}
+
+/// Remove Position Triggers
+///
+/// Removes all stored triggers of a position
+fn _remove_position_triggers(position_key: PositionKey, position: &PositionDetails) {
+    let (account, position_id) = position_key;
+
+    POSITIONS_TRIGGERS.with_borrow_mut(|reference| {
+        for tick in [position.stop_loss_tick, position.take_profit_tick]
+            .into_iter()
+            .flatten()
+        {
+            reference.remove(&(tick, account, position_id));
+        }
+    })
+}
+
+/// Schedule Execution For Triggers
+///
+/// Schedules the closing of all positions with a trigger within the range of ticks swapped through ,in the swap direction
+///
+/// Params
+/// - Init Tick :The current tick before the swap ,not the first filled tick so triggers between the current tick and the best offer are included
+/// - Resulting Tick :The last tick at which swap occured
+/// - Buy :The swap direction
+fn _schedule_execution_for_triggers(init_tick: Tick, resulting_tick: Tick, buy: bool) {
+    let (lower_tick, upper_tick) = if buy {
+        (init_tick, resulting_tick)
+    } else {
+        (resulting_tick, init_tick)
+    };
+
+    let triggered: Vec<(Tick, PositionKey, Principal)> =
+        POSITIONS_TRIGGERS.with_borrow(|reference| {
+            reference
+                .range((lower_tick, [0u8; 32], 0)..=(upper_tick, [u8::MAX; 32], PositionId::MAX))
+                .filter(|(_, (_, on_buy))| *on_buy == buy)
+                .map(|((tick, account, position_id), (owner, _))| {
+                    (tick, (account, position_id), owner)
+                })
+                .collect()
+        });
+
+    if triggered.is_empty() {
+        return;
+    }
+
+    ic_cdk_timers::set_timer(Duration::from_nanos(2 * ONE_SECOND), || {
+        _execute_triggers(triggered)
+    });
+}
+
+/// Execute Triggers
+///
+/// Closes each triggered position at the current tick
+///
+/// Note
+///  - A position is skipped if it has been closed or the trigger has been changed since it was scheduled
+fn _execute_triggers(triggered: Vec<(Tick, PositionKey, Principal)>) {
+    for (tick, position_key, owner) in triggered {
+        let mut position =
+            match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key)) {
+                Some(position) => position,
+                None => continue,
             };
 
-            return (removed_collateral, manage_debt_params);
+        if position.stop_loss_tick != Some(tick) && position.take_profit_tick != Some(tick) {
+            continue;
         }
-        PositionOrderType::Market => (0, ManageDebtParams::default()),
+
+        _close_market_position(owner, position_key, &mut position, None, true);
+
+        _store_market_position(position_key, position);
     }
 }
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////
 
-/// Close Limit Short Function
+//////////////////////////////////////////////////////////////////////////////////////////////
+///////////////////////////////////////////////////////////////////////////////////////////////
+//  Limit Orders Expiry Functions
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Insert Limit Order Expiry
 ///
-/// Similar to close limit long position function but for long position
-fn _close_limit_short_position(
-    account: Subaccount,
-    position: &mut PositionDetails,
-) -> (Amount, ManageDebtParams) {
-    match position.order_type {
-        PositionOrderType::Limit(order) => {
-            let (amount_received, amount_remaining) = _close_order(&order);
+/// Stores the expiry of a good till time limit position and re-arms the expiry timer if it is the earliest expiry (see Schedule Limit Orders Expiry)
+fn _insert_limit_order_expiry(expiry: Time, position_key: PositionKey, owner: Principal) {
+    LIMIT_ORDERS_EXPIRY.with_borrow_mut(|reference| {
+        reference.insert((expiry, position_key.0, position_key.1), owner)
+    });
 
-            let (removed_collateral, manage_debt_params);
+    _schedule_limit_orders_expiry(expiry);
+}
 
-            if amount_received == 0 {
-                (removed_collateral, manage_debt_params) = (
-                    position.collateral_value,
-                    ManageDebtParams::init(
-                        position.debt_value,
-                        position.debt_value,
-                        position.debt_value,
-                    ),
-                );
-                _remove_account_position(&account);
-            } else {
-                let entry_price = _tick_to_price(position.entry_tick);
+/// Schedule Limit Orders Expiry
+///
+/// Arms the expiry timer for the expiry time ,unless a timer is already armed for the same or an earlier time
+///
+/// Note
+///  - A single timer is armed at any time ,a later armed timer is cleared before the timer is re-armed for an earlier expiry
+fn _schedule_limit_orders_expiry(expiry: Time) {
+    EXPIRY_TIMER.with_borrow_mut(|reference| {
+        let (armed_expiry, timer_id) = *reference;
 
-                let amount_remaining_value = _equivalent(amount_remaining, entry_price, false);
-                (removed_collateral, manage_debt_params) =
-                    _convert_limit_position(position, amount_remaining_value);
+        if timer_id != TimerId::default() && armed_expiry <= expiry {
+            return;
+        }
+
+        ic_cdk_timers::clear_timer(timer_id);
+
+        let delay = expiry.saturating_sub(ic_cdk::api::time());
+
+        *reference = (
+            expiry,
+            ic_cdk_timers::set_timer(Duration::from_nanos(delay), _expire_limit_orders),
+        );
+    })
+}
+
+/// Expire Limit Orders
+///
+/// Closes a batch of limit positions whose expiry time has been reached ,refunding the unfilled collateral and debt to the vault
+///
+/// Note
+///  - A position that has been closed or converted into a market position before expiry is skipped
+///  - After each run the next expiry is scheduled (see Schedule Next Limit Orders Expiry) ,immediately if more positions are due after the batch
+fn _expire_limit_orders() {
+    // the armed timer has fired
+    EXPIRY_TIMER.with_borrow_mut(|reference| *reference = (0, TimerId::default()));
+
+    let now = ic_cdk::api::time();
+
+    let expired: Vec<((Time, Subaccount, PositionId), Principal)> = LIMIT_ORDERS_EXPIRY
+        .with_borrow(|reference| {
+            reference
+                .range(..=(now, [u8::MAX; 32], PositionId::MAX))
+                .take(_EXPIRY_BATCH_SIZE)
+                .collect()
+        });
 
-                _insert_account_position(account, position.clone());
+    for ((expiry, account, position_id), owner) in expired.iter() {
+        LIMIT_ORDERS_EXPIRY
+            .with_borrow_mut(|reference| reference.remove(&(*expiry, *account, *position_id)));
+
+        let position_key = (*account, *position_id);
+
+        let mut position =
+            match ACCOUNTS_POSITION.with_borrow(|reference| reference.get(&position_key)) {
+                Some(position) => position,
+                None => continue,
             };
 
-            return (removed_collateral, manage_debt_params);
+        if let PositionOrderType::Limit(_) = position.order_type {
+            _close_limit_position(*owner, position_key, &mut position);
         }
-        // unreachable code
-        PositionOrderType::Market => return (0, ManageDebtParams::default()),
     }
+
+    _schedule_next_limit_orders_expiry();
 }
 
-/// Update Market Position After Swap Function
+/// Schedule Next Limit Orders Expiry
 ///
-/// This function updates a  market position if it can not be closed i.e amount remaining after swapping to close position is greater than 0
+/// Schedules the expiry of the earliest stored limit order expiry ,if any
 ///
-/// It
-///   - Updates the position debt ,the position collateral value , the position volume share
-///   - Derives the update asset params that pays the debt either fully or partially
+/// Note
+///  - Timers do not persist across upgrades ,so this is also utilised to re-arm the expiry timer after an upgrade
+fn _schedule_next_limit_orders_expiry() {
+    let next_expiry = LIMIT_ORDERS_EXPIRY
+        .with_borrow(|reference| reference.first_key_value().map(|(key, _)| key.0));
+
+    if let Some(expiry) = next_expiry {
+        _schedule_limit_orders_expiry(expiry);
+    }
+}
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////
+
+//////////////////////////////////////////////////////////////////////////////////////////////
+///////////////////////////////////////////////////////////////////////////////////////////////
+//  Liquidation Sweeper Functions
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Sweep Positions
 ///
-/// Params
-///  - Position :A mutable reference to the particular position
-///  - Resulting Tick : The resulting tick after swapping to closing the position
-///  - Amount Out Value :The value of the amount gotten from swapping
-///  - Amount Remaining Value :The value of the amount remaining after swapping
-///  - Interest Value : The value of the interest accrued on current position debt
+/// Checks a batch of positions starting after the last checked position and liquidates any liquidatable position
 ///
-/// Returns
-///  - Profit : The amount of profit for position owner or the amount of removable collateral from position
-///  - Manage Debt Params : for repaying debt ,specifying the current debt and the previous debt and interest paid
-fn _update_market_position_after_swap(
-    position: &mut PositionDetails,
-    amount_out_value: Amount,
-    amount_remaining_value: Amount,
-    interest_value: Amount,
-) -> (Amount, ManageDebtParams) {
-    let init_debt_value = position.debt_value;
+/// Note
+///  - The whole liquidation fee of a swept position is added to the insurance fund
+///  - Once the last position has been checked ,the next sweep starts from the first position
+fn _sweep_positions() {
+    // the best bid and ask can move without the current tick moving
+    _update_mark_price();
 
-    let net_debt_value = init_debt_value + interest_value;
+    let mut sweeper_config = _get_sweeper_config();
 
-    let profit;
-    let manage_debt_params;
+    let batch_size = sweeper_config.batch_size;
 
-    if amount_out_value < net_debt_value {
-        position.debt_value = net_debt_value - amount_out_value;
+    let start = match sweeper_config.last_checked {
+        Some(position_key) => Excluded(position_key),
+        None => Unbounded,
+    };
 
-        profit = 0;
+    let batch: Vec<(PositionKey, PositionDetails)> = ACCOUNTS_POSITION.with_borrow(|reference| {
+        reference
+            .range((start, Unbounded))
+            .take(batch_size as usize)
+            .collect()
+    });
+
+    let next_last_checked = if (batch.len() as u64) < batch_size {
+        None
+    } else {
+        batch.last().map(|(position_key, _)| *position_key)
+    };
+
+    sweeper_config.last_checked = next_last_checked;
+
+    _set_sweeper_config(sweeper_config);
+
+    for (position_key, position) in batch {
+        let StateDetails {
+            maintenance_margin, ..
+        } = _get_state_details();
+
+        let position = if _is_crossed_tick_order(position_key, &position) {
+            _settle_account_position(position_key);
+
+            _get_account_position(&position_key)
+        } else {
+            position
+        };
+
+        if !_liquidation_status(position, maintenance_margin) {
+            continue;
+        }
+
+        // positions opened before the owner was recorded can not be swept
+        if let Some(owner) = _get_account_owner(&position_key.0) {
+            let _ = _liquidate_position(owner, position_key, None);
+        }
+    }
+}
+
+/// Schedule Liquidation Sweep
+///
+/// Schedules a single sweep if the sweeper is running and no sweep is already scheduled
+///
+/// Note:Utilised when the current tick moves
+fn _schedule_liquidation_sweep() {
+    let to_schedule = LIQUIDATION_SWEEPER.with_borrow_mut(|reference| {
+        if reference.timer_id == TimerId::default() || reference.sweep_scheduled {
+            return false;
+        }
+        reference.sweep_scheduled = true;
+        true
+    });
+
+    if to_schedule {
+        ic_cdk_timers::set_timer(Duration::from_nanos(0), || {
+            LIQUIDATION_SWEEPER.with_borrow_mut(|reference| reference.sweep_scheduled = false);
+
+            _sweep_positions();
+        });
+    }
+}
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////
 
-        manage_debt_params =
-            ManageDebtParams::init(init_debt_value, net_debt_value, amount_out_value);
-    } else {
-        position.debt_value = 0;
-        position.collateral_value = amount_remaining_value;
+//////////////////////////////////////////////////////////////////////////////////////////////
+///////////////////////////////////////////////////////////////////////////////////////////////
+/// System Functions
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////////
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    // the record saved by the previous version overwrote the memory manager header ,
+    // so it is read and the header rebuilt before the memory manager is first accessed
+    let stable_memory = DefaultMemoryImpl::default();
 
-        profit = amount_out_value - net_debt_value;
+    let legacy_limit_orders_record = migration::_read_legacy_limit_orders_record(&stable_memory);
 
-        manage_debt_params =
-            ManageDebtParams::init(init_debt_value, net_debt_value, net_debt_value);
+    if let Some(limit_orders_record) = &legacy_limit_orders_record {
+        migration::_restore_legacy_memory_manager_header(&stable_memory, limit_orders_record);
     }
 
-    let new_volume_share = _calc_position_volume_share(amount_remaining_value, position.long);
-
-    position.volume_share = new_volume_share;
+    migration::_migrate_accounts_positions();
 
-    // if position last time updated is greater than one hour ago ,position time is updated to current timestamp
-    if position.timestamp + ONE_HOUR > ic_cdk::api::time() {
-        position.timestamp = ic_cdk::api::time()
+    if let Some(limit_orders_record) = legacy_limit_orders_record {
+        migration::_migrate_limit_orders_record(limit_orders_record);
     }
 
-    return (profit, manage_debt_params);
-}
+    // timers do not persist across upgrades
+    if !CROSSED_TICKS_QUEUE.with_borrow(|reference| reference.is_empty()) {
+        _schedule_limit_orders_execution();
+    }
 
-/// Convert Account Limit Position
-///
-/// Params:
-///  - Account :The owner of the position
-///
-/// Returns
-///   - is Fully Filled :Returns true  the limit order has been fully filled or returns false otherwise
-///   - is Partially Filled :true if the position partially filled
-fn _convert_account_limit_position(account: Subaccount) -> (bool, bool) {
-    let mut position = _get_account_position(&account);
+    _schedule_next_limit_orders_expiry();
 
-    if let PositionOrderType::Limit(order) = position.order_type {
-        let (amount_out, amount_remaining) = _close_order(&order);
+    // triggers reached before the upgrade but not yet executed
+    let current_tick = _get_state_details().current_tick;
 
-        let is_fully_filled = amount_remaining == 0;
-        let is_partially_filled = amount_out > 0;
-        _convert_limit_position(&mut position, 0);
-        _insert_account_position(account, position);
+    _schedule_execution_for_triggers(Tick::MIN, current_tick, true);
+    _schedule_execution_for_triggers(Tick::MAX, current_tick, false);
 
-        return (is_fully_filled, is_partially_filled);
+    if _get_sweeper_config().running {
+        _start_sweeper_timer();
     }
-    return (false, false);
+}
+//////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////
+/// Admin Functions
+//////////////////////////////////////////////////////////////////////////////////////////////
+//////////////////////////////////////////////////////////////////////////////////////////////
+fn admin_guard() -> Result<(), String> {
+    ADMIN.with_borrow(|admin_ref| {
+        let admin = admin_ref.get().clone();
+        if ic_cdk::caller() == admin {
+            return Ok(());
+        } else {
+            return Err("Invalid".to_string());
+        };
+    })
 }
 
-/// Convert Limit Position function
-///
-/// Converts a limit position into a market position after the reference limit order of that position has been filled fully or partially
-/// any unfilled amount is refunded first as debt and if still remaining it is refunded back to the position owner and the position is updated to a market position
+/// Update State Details
 ///
-/// Params
-///  - Position : A mutable reference to the cuurent position
-///  - Amount Remaining Value : The value of the amount of  unfilled liquidity of the particular order
+/// Updates the state details after checking that the margin parameters are valid
 ///
-/// Returns
-///  - Removed Collateral : The amount of collateral removed from that position
-///  - Update Asset Details Params :The update asset details params for updating asset detailsin params   
-fn _convert_limit_position(
-    position: &mut PositionDetails,
-    amount_remaining_value: Amount,
-) -> (Amount, ManageDebtParams) {
-    let initial_collateral_value: u128 = position.collateral_value;
-
-    let initial_debt_value = position.debt_value;
+/// Note
+///  - Maintenance margin must be less than the initial margin (1 / max leverage) and at least the liquidation fee
+///  - Keeper share can not exceed 100%
+///  - Taker and maker fees can not exceed 1% and a maker rebate can not exceed the taker fee
+#[ic_cdk::update(guard = "admin_guard", name = "updateStateDetails")]
+async fn update_state_details(new_state_details: StateDetails) -> Result<(), String> {
+    if new_state_details.max_leveragex10 <= 10 {
+        return Err("Max leverage must be greater than 1".to_string());
+    }
 
-    let removed_collateral;
-    if amount_remaining_value > initial_debt_value {
-        removed_collateral = amount_remaining_value - initial_debt_value;
+    let initial_margin = (10 * 100 * _ONE_PERCENT) / u64::from(new_state_details.max_leveragex10);
 
-        position.debt_value = 0;
-        position.collateral_value -= removed_collateral;
-    } else {
-        removed_collateral = 0;
+    if new_state_details.maintenance_margin == 0
+        || new_state_details.maintenance_margin >= initial_margin
+    {
+        return Err("Maintenance margin must be between zero and the initial margin".to_string());
+    }
 
-        position.debt_value -= amount_remaining_value;
+    if new_state_details.liquidation_fee > new_state_details.maintenance_margin {
+        return Err("Liquidation fee can not exceed the maintenance margin".to_string());
     }
 
-    let remaining_order_value =
-        initial_collateral_value + initial_debt_value - amount_remaining_value;
+    if new_state_details.keeper_share > 100 * _ONE_PERCENT {
+        return Err("Keeper share can not exceed 100 percent".to_string());
+    }
 
-    let volume_share = _calc_position_volume_share(remaining_order_value, position.long);
+    if new_state_details.taker_fee > _MAX_TRADING_FEE
+        || new_state_details.maker_fee.unsigned_abs() > _MAX_TRADING_FEE
+    {
+        return Err("Trading fees can not exceed the max trading fee".to_string());
+    }
 
-    position.volume_share = volume_share;
-    position.order_type = PositionOrderType::Market;
-    position.timestamp = ic_cdk::api::time();
+    if new_state_details.maker_fee < 0
+        && new_state_details.maker_fee.unsigned_abs() > new_state_details.taker_fee
+    {
+        return Err("Maker rebate can not exceed the taker fee".to_string());
+    }
 
-    let manage_debt_params = ManageDebtParams::init(
-        initial_debt_value,
-        initial_debt_value,
-        initial_debt_value - position.debt_value,
-    );
+    _set_state_details(new_state_details);
 
-    return (removed_collateral, manage_debt_params);
+    Ok(())
 }
 
-/// Liquidation Status Function
+/// Update Funding Config
 ///
-/// Checks if a position is to be liquidated and the corrseponding collateral for liquidating that position
+/// Updates the parameters for calculating the funding rate (see funding_lib) and guarding against a stale spot price
 ///
-/// Params ;
-///  - Position :The Position to check
-///  - Max Leverage :The current maximum leverage for opening a position
-///
-/// Returns
-///  - To Liquidate :true if position should be liquidated
-///  - Collateral_Remaining : Returns the current value of collateral within the position
-///  - Net Debt Value :returns the net debt value
-///
-/// Note :This collateral value can be less than zero in such case, a bad debt has occured
-fn _liquidation_status(position: PositionDetails, max_leveragex10: u8) -> (bool, i128, Amount) {
-    if let PositionOrderType::Market = position.order_type {
-        let initial_collateral = position.collateral_value;
+/// Note
+///  - Max funding rate can not exceed 10%
+///  - Dead band and the absolute interest rate can not exceed the max funding rate
+///  - Max oracle staleness can not be zero
+#[ic_cdk::update(guard = "admin_guard", name = "updateFundingConfig")]
+fn update_funding_config(new_funding_config: FundingConfig) -> Result<(), String> {
+    if new_funding_config.max_funding_rate > _MAX_FUNDING_RATE {
+        return Err("Max funding rate can not exceed 10 percent".to_string());
+    }
 
-        let (pnl_in_percentage, net_debt_value) =
-            _calculate_position_pnl_and_net_debt_value(position);
+    if new_funding_config.dead_band > new_funding_config.max_funding_rate
+        || new_funding_config.interest_rate.unsigned_abs() > new_funding_config.max_funding_rate
+    {
+        return Err("Dead band and interest rate can not exceed the max funding rate".to_string());
+    }
 
-        let profit_or_loss = _percentage128(pnl_in_percentage.abs() as u64, initial_collateral);
+    if new_funding_config.max_oracle_staleness == 0 {
+        return Err("Max oracle staleness can not be zero".to_string());
+    }
 
-        let current_collateral_value = if pnl_in_percentage > 0 {
-            (position.collateral_value + profit_or_loss) as i128
-        } else {
-            (position.collateral_value as i128) - (profit_or_loss as i128)
-        };
+    FUNDING_CONFIG.with_borrow_mut(|reference| reference.set(new_funding_config).unwrap());
 
-        let current_leverage_x10 = ((position.debt_value + position.collateral_value) as i128 * 10)
-            / current_collateral_value;
+    Ok(())
+}
 
-        if current_leverage_x10.abs() as u8 >= max_leveragex10 {
-            return (true, current_collateral_value, net_debt_value);
-        }
-    }
+/// Set Oracle Kind
+///
+/// Sets the source of the spot price utilised for settling the funding rate
+#[ic_cdk::update(guard = "admin_guard", name = "setOracleKind")]
+fn set_oracle_kind(oracle: OracleKind) {
+    let mut market_details = _get_market_details();
 
-    return (false, 0, 0);
+    market_details.oracle = oracle;
+
+    MARKET_DETAILS.with_borrow_mut(|reference| reference.set(market_details).unwrap());
 }
 
-/// Opens Order Functions
+/// Set Price Keeper
 ///
-/// opens an order at a particular tick
-///
-/// Params
-/// - Order :: a generic type that implements the trait Order for the type of order to close
-/// - Reference Tick :: The  tick to place order
-fn _open_order(order: &mut LimitOrder) {
-    TICKS_DETAILS.with_borrow_mut(|ticks_details| {
-        INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
-            let mut open_order_params = OpenOrderParams {
-                order,
-                integrals_bitmaps,
-                ticks_details,
-            };
-            open_order_params.open_order();
-        })
+/// Sets the principal allowed to push prices to the price feed besides the admin ,none removes the price keeper
+#[ic_cdk::update(guard = "admin_guard", name = "setPriceKeeper")]
+fn set_price_keeper(keeper: Option<Principal>) {
+    PRICE_FEED.with_borrow_mut(|reference| {
+        let mut price_feed = *reference.get();
+
+        price_feed.keeper = keeper;
+
+        reference.set(price_feed).unwrap();
     });
 }
-/// Close Order Function
-///
-/// closes an order at a particular tick
-///
-/// Params :
-///  - Order :: a generic that implements the trait Order for the type of order to close
-///  - Order Size :: Tha amount of asset in order
-///  - Order Direction :: Either a buy or a sell
-///  - Order Reference Tick :: The tick where order was placed  
-///
-/// Returns
-///  - Amont Out :: This corresponds to the asset to be bought i.e perp(base) asset for a buy order or quote asset for a sell order
-///  - Amount Remaining :: This amount remaining corrseponds to the amount of asset at that tick that is still unfilled
+
+/// Price Keeper Guard
 ///
-fn _close_order(order: &LimitOrder) -> (Amount, Amount) {
-    TICKS_DETAILS.with_borrow_mut(|ticks_details| {
-        INTEGRAL_BITMAPS.with_borrow_mut(|multipliers_bitmaps| {
-            let mut close_order_params = CloseOrderParams {
-                order,
-                integrals_bitmaps: multipliers_bitmaps,
-                ticks_details,
-            };
-            close_order_params.close_order()
-        })
-    })
+/// Allows the admin or the price keeper
+fn price_keeper_guard() -> Result<(), String> {
+    if admin_guard().is_ok() || _get_price_feed().keeper == Some(ic_cdk::caller()) {
+        return Ok(());
+    }
+    Err("Invalid".to_string())
 }
 
-/// Swap Function
+/// Push Oracle Price
+///
+/// Pushes a spot price to the price feed read by the pushed and mock oracles
 ///
 /// Params
-///  - Order Size :: Tha amount of asset in order
-///  - Buy :: the order direction ,true for buy and false otherwise
-///  - Init Tick :: The current state tick
-///  - Stopping Tick :: The maximum tick ,corresponds to maximum price
+///  - Price :The spot price of the market pair scaled by 10^decimals
+///  - Decimals :The decimals of the price
 ///
-/// Returns
-///  - Amount Out :: The amount out froom swapping
-///  - Amount Remaining :: The amount remaining from swapping
-///  - resulting Tick :The last tick at which swap occured
-///  - Crossed Ticks :: An vector of all ticks crossed during swap
-fn _swap(
-    order_size: Amount,
-    buy: bool,
-    init_tick: Tick,
-    stopping_tick: Tick,
-) -> (Amount, Amount, Tick, Vec<Tick>) {
-    TICKS_DETAILS.with_borrow_mut(|ticks_details| {
-        INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
-            let mut swap_params = SwapParams {
-                buy,
-                init_tick,
-                stopping_tick,
-                order_size,
-                integrals_bitmaps,
-                ticks_details,
-            };
-            swap_params._swap()
-        })
-    })
-}
+/// Note
+///  - The price is timestamped at the time it is pushed
+///  - The decimals can not exceed the max oracle decimals
+#[ic_cdk::update(guard = "price_keeper_guard", name = "pushOraclePrice")]
+fn push_oracle_price(price: u128, decimals: u32) -> Result<(), String> {
+    if price == 0 {
+        return Err("Price can not be zero".to_string());
+    }
 
-fn get_best_offer(buy: bool, current_tick: Tick, stopping_tick: Option<Tick>) -> Option<Tick> {
-    let max_tick = match stopping_tick {
-        Some(val) => val,
-        None => max_or_default_max(None, current_tick, buy),
-    };
-    TICKS_DETAILS.with_borrow_mut(|ticks_details| {
-        INTEGRAL_BITMAPS.with_borrow_mut(|integrals_bitmaps| {
-            _get_best_offer(
-                buy,
-                current_tick,
-                max_tick,
-                integrals_bitmaps,
-                ticks_details,
-            )
-        })
-    })
+    if decimals > _MAX_ORACLE_DECIMALS {
+        return Err("Decimals exceed the max oracle decimals".to_string());
+    }
+
+    PRICE_FEED.with_borrow_mut(|reference| {
+        let mut price_feed = *reference.get();
+
+        price_feed.last_price = SpotPrice {
+            price,
+            decimals,
+            timestamp: ic_cdk::api::time(),
+        };
+
+        reference.set(price_feed).unwrap();
+    });
+
+    Ok(())
 }
 
-/// Max or Default Max Tick
-///
-/// retrieves the max tick if valid else returns the default max tick
-fn max_or_default_max(max_tick: Option<Tick>, current_tick: Tick, buy: bool) -> Tick {
-    match max_tick {
-        Some(tick) => {
-            if buy && tick <= _def_max_tick(current_tick, true) {
-                return tick;
-            };
-            if !buy && tick >= _def_max_tick(current_tick, false) {
-                return tick;
-            }
-        }
-        None => {
-            if buy {
-                return _def_max_tick(current_tick, true);
-            } else {
-                return _def_max_tick(current_tick, false);
-            }
-        }
-    }
-    return _def_max_tick(current_tick, buy);
+#[ic_cdk::update(guard = "admin_guard", name = "startTimer")]
+async fn start_timer() {
+    ic_cdk_timers::set_timer_interval(Duration::from_nanos(ONE_HOUR), || {
+        ic_cdk::spawn(async { settle_funding_rate(0).await });
+    });
 }
-/// Calculate Position PNL
-///
-/// Calculates the current pnl in percentage  for a particular position
+
+/// Start Liquidation Sweeper
 ///
-/// Returns
-///  PNL :The pnl(in percentage) on that position
-///  Net Debt Value :The net debt on that position
-fn _calculate_position_pnl_and_net_debt_value(position: PositionDetails) -> (i64, Amount) {
-    let equivalent = |amount: Amount, tick: Tick, buy: bool| {
-        let tick_price = _tick_to_price(tick);
-        _equivalent(amount, tick_price, buy)
-    };
+/// Starts a timer interval that sweeps a batch of positions for liquidation ,while running a sweep is also scheduled on every tick move
+#[ic_cdk::update(guard = "admin_guard", name = "startLiquidationSweeper")]
+fn start_liquidation_sweeper() {
+    let mut sweeper_config = _get_sweeper_config();
+
+    sweeper_config.running = true;
+
+    _set_sweeper_config(sweeper_config);
+
+    _start_sweeper_timer();
+}
 
-    let state_details = _get_state_details();
+/// Start Sweeper Timer
+///
+/// Sets the liquidation sweeper timer interval if it is not already set
+fn _start_sweeper_timer() {
+    LIQUIDATION_SWEEPER.with_borrow_mut(|reference| {
+        if reference.timer_id == TimerId::default() {
+            reference.timer_id = ic_cdk_timers::set_timer_interval(
+                Duration::from_nanos(_SWEEP_INTERVAL),
+                _sweep_positions,
+            );
+        }
+    })
+}
 
-    let position_realised_value =
-        _calc_position_realised_value(position.volume_share, position.long);
+/// Stop Liquidation Sweeper
+///
+/// Stops the liquidation sweeper timer interval
+#[ic_cdk::update(guard = "admin_guard", name = "stopLiquidationSweeper")]
+fn stop_liquidation_sweeper() {
+    let mut sweeper_config = _get_sweeper_config();
 
-    let interest_on_debt_value = _calc_interest(
-        position.debt_value,
-        position.interest_rate,
-        position.timestamp,
-    );
+    sweeper_config.running = false;
 
-    let net_debt_value = position.debt_value + interest_on_debt_value;
+    _set_sweeper_config(sweeper_config);
 
-    let pnl;
+    LIQUIDATION_SWEEPER.with_borrow_mut(|reference| {
+        ic_cdk_timers::clear_timer(reference.timer_id);
 
-    if position.long {
-        let init_position_value = (position.debt_value + position.collateral_value) as i128;
+        reference.timer_id = TimerId::default();
+    })
+}
 
-        let position_realised_size = equivalent(position_realised_value, position.entry_tick, true);
+/// Set Sweeper Batch Size
+///
+/// Sets the max number of positions checked by the liquidation sweeper per sweep
+#[ic_cdk::update(guard = "admin_guard", name = "setSweeperBatchSize")]
+fn set_sweeper_batch_size(batch_size: u64) {
+    assert!(batch_size != 0);
 
-        let position_current_value =
-            equivalent(position_realised_size, state_details.current_tick, false) as i128;
+    let mut sweeper_config = _get_sweeper_config();
 
-        pnl = ((position_current_value - (interest_on_debt_value as i128) - init_position_value)
-            * (100 * _ONE_PERCENT as i128))
-            / init_position_value;
-    } else {
-        let init_position_size = equivalent(
-            position.debt_value + position.collateral_value,
-            position.entry_tick,
-            true,
-        ) as i128;
+    sweeper_config.batch_size = batch_size;
 
-        let position_current_size =
-            equivalent(position_realised_value, state_details.current_tick, true) as i128;
+    _set_sweeper_config(sweeper_config);
+}
 
-        let interest_on_debt = equivalent(interest_on_debt_value, position.entry_tick, true);
+//////////////////////////////////////////////////////////////////////////////////////////////////
+//////////////////////////////////////////////////////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////////////////////////
+///  Error Handling Functions
+//////////////////////////////////////////////////////////////////////////////////////////////////
+/// ///////////////////////////////////////////////////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////////////////////////
+fn trusted_canister_guard() -> Result<(), String> {
+    let market_details = _get_market_details();
 
-        pnl = ((position_current_size - (interest_on_debt as i128) - init_position_size)
-            * (100 * _ONE_PERCENT as i128))
-            / init_position_size;
+    let caller = ic_cdk::caller();
+
+    if caller == market_details.vault_id {
+        return Ok(());
+    } else {
+        return Err("Untrusted Caller".to_string());
     }
-    return (pnl as i64, net_debt_value);
 }
 
-///////////////////////////////////////////////////////////////////////////////////////////////
-//////////////////////////////////////////////////////////////////////////////////////////////
-///  Funding Rate Functions
-///////////////////////////////////////////////////////////////////////////////////////////////
-///////////////////////////////////////////////////////////////////////////////////////////////
-/// Settle Funcding Rate
+/// Retry Account Error
 ///
-/// Settles Funding Rate by calling the XRC cansiter .fetching the Price ,calculating the premium and distributing the  fund to the right market direction,Long or Short
-async fn settle_funding_rate() {
-    let market_details = _get_market_details();
+/// Resends the failed vault notifications of an account
+///
+/// Params
+///  - User :The owner of the account
+///
+/// Note
+///  - Only the owner of the account ,the admin or the vault can retry
+///  - Each error log is removed before the vault is notified so that the same update is never sent twice ,the log is restored if the vault can not be notified
+///  - Error logs stored without a known owner are sent for the user (see Send Conversion Update)
+#[ic_cdk::update(name = "retryAccountError")]
+async fn retry_account_error(user: Principal) -> Result<(), String> {
+    if ic_cdk::caller() != user && admin_guard().is_err() && trusted_canister_guard().is_err() {
+        return Err("Untrusted Caller".to_string());
+    }
 
-    let xrc = XRC::init(market_details.xrc_id);
+    let account = user._to_subaccount();
 
-    let request = GetExchangeRateRequest {
-        base_asset: market_details.base_asset,
-        quote_asset: market_details.quote_asset,
-        timestamp: None,
-    };
+    let details = _get_market_details();
 
-    match xrc._get_exchange_rate(request).await {
-        Ok(rate_result) => {
-            let state_details = _get_state_details();
+    for (error_index, mut account_error_log) in _get_account_error_logs(&account) {
+        _remove_account_error_log(&account, error_index);
 
-            let current_price = _tick_to_price(state_details.current_tick);
+        // the account is derived from the user ,so the user owns every error log of the account
+        account_error_log.user = user;
+
+        if account_error_log.retry(details.clone()).is_err() {
+            ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| {
+                reference.insert((account, error_index), account_error_log)
+            });
+        }
+    }
 
-            let perp_price =
-                (current_price * 10u128.pow(rate_result.metadata.decimals)) / _BASE_PRICE;
+    Ok(())
+}
 
-            let spot_price = rate_result.rate as u128;
+#[ic_cdk::update(name = "successNotification", guard = "trusted_canister_guard")]
+async fn success_notif(account: Subaccount, _error_index: usize) {
+    let market_details = _get_market_details();
 
-            _settle_funding_rate(perp_price, spot_price);
-        }
-        Err(_) => {
-            return;
-        }
+    let caller = ic_cdk::caller();
+
+    if caller == market_details.vault_id {
+        _remove_account_error_log(&account, _error_index as u64);
+        return;
     }
 }
+/////////////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////////////
 
-fn _settle_funding_rate(perp_price: u128, spot_price: u128) {
-    let funding_rate = _calculate_funding_rate_premium(perp_price, spot_price);
-    FUNDING_RATE_TRACKER.with_borrow_mut(|reference| {
-        let mut funding_rate_tracker = reference.get().clone();
+///////////////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////////////
+/// Getter Functions
+///////////////////////////////////////////////////////////////////////////////////////////////////////
+
+fn _get_market_details() -> MarketDetails {
+    MARKET_DETAILS.with(|ref_market_details| ref_market_details.borrow().get().clone())
+}
+
+fn _get_state_details() -> StateDetails {
+    STATE_DETAILS.with(|ref_state_detaills| *ref_state_detaills.borrow().get())
+}
 
-        funding_rate_tracker.settle_funding_rate(funding_rate.abs() as u64, funding_rate > 0);
+fn _get_account_position(position_key: &PositionKey) -> PositionDetails {
+    ACCOUNTS_POSITION
+        .with(|ref_position_details| ref_position_details.borrow().get(position_key).unwrap())
+}
 
-        reference.set(funding_rate_tracker).unwrap();
+fn _get_account_error_logs(account: &Subaccount) -> Vec<(u64, PositionUpdateErrorLog)> {
+    ACCOUNTS_ERROR_LOGS.with_borrow(|reference| {
+        reference
+            .range((*account, 0)..=(*account, u64::MAX))
+            .map(|((_, error_index), error_log)| (error_index, error_log))
+            .collect()
     })
 }
 
-fn _calculate_funding_rate_premium(perp_price: u128, spot_price: u128) -> i64 {
-    let funding_rate = ((perp_price as i128 - spot_price as i128) * 100 * _ONE_PERCENT as i128)
-        / spot_price as i128;
-    return funding_rate as i64;
+fn _get_account_owner(account: &Subaccount) -> Option<Principal> {
+    ACCOUNTS_OWNER.with_borrow(|reference| reference.get(account))
 }
-///Calculate Position Realised value
-///
-///Calculates the Realised value for a position's volume share in a particular market direction,Long or Short   
-///
-/// Note:This function also adjust's the volume share
-fn _calc_position_realised_value(volume_share: Amount, long: bool) -> Amount {
-    FUNDING_RATE_TRACKER.with_borrow_mut(|tr| {
-        let mut funding_rate_tracker = tr.get().clone();
 
-        let value = funding_rate_tracker.remove_volume(volume_share, long);
+fn _get_sweeper_config() -> SweeperConfig {
+    SWEEPER_CONFIG.with_borrow(|reference| *reference.get())
+}
 
-        tr.set(funding_rate_tracker).unwrap();
-        value
-    })
+fn _get_pending_timer() -> TimerId {
+    PENDING_TIMER.with_borrow_mut(|reference| reference.clone())
 }
-/// Calculate Position Volume Share
-///
-/// Calculates the volume share for a particular poistion volume in a market direction ,Long or Short
-fn _calc_position_volume_share(position_value: Amount, long: bool) -> Amount {
-    FUNDING_RATE_TRACKER.with_borrow_mut(|tr| {
-        let mut funding_rate_tracker = tr.get().clone();
 
-        let value = funding_rate_tracker.add_volume(position_value, long);
+////////////////////////////////////////////////////////////////////////////////////////////////////
+/// ////////////////////////////////////////////////////////////////////////////////////////////////
+/////////////////////////////////////////////////////////////////////////////////////////////////////
 
-        tr.set(funding_rate_tracker).unwrap();
-        value
-    })
+///////////////////////////////////////////////////////////////////////////////////////////////////
+/// /////////////////////////////////////////////////////////////////////////////////////////////////
+///   Setter Function
+//////////////////////////////////////////////////////////////////////////////////////////////////////
+fn _set_state_details(new_state: StateDetails) {
+    let StateDetails { current_tick, .. } = _get_state_details();
+
+    STATE_DETAILS.with(|ref_state_details| ref_state_details.borrow_mut().set(new_state).unwrap());
+
+    // the mark tick moves with the current tick ,positions are swept when it moves
+    if new_state.current_tick != current_tick {
+        _update_price_accumulator(new_state.current_tick);
+
+        _update_mark_price();
+
+        _schedule_liquidation_sweep();
+    }
 }
-////////////////////////////////////////////////////////////////////////////////////////////////
-///////////////////////////////////////////////////////////////////////////////////////////////
-////////////////////////////////////////////////////////////////////////////////////////////////
 
-//////////////////////////////////////////////////////////////////////////////////////////////////
-////////////////////////////////////////////////////////////////////////////////////////////////
-///  Limit Order Functions
-/////////////////////////////////////////////////////////////////////////////////////////////////
-//////////////////////////////////////////////////////////////////////////////////////////////
-/// Store Tick Order
-///
-/// Stores an order under a particular tick
-///
-/// Utilised when positions are opened as limit orders
-///
-///Params
-/// - Tick    :The tickat which order is placed
-/// - Account : The account opening the position
-pub fn store_tick_order(tick: Tick, account: Subaccount) {
-    LIMIT_ORDERS_RECORD.with_borrow_mut(|reference| {
-        let accounts = reference.entry(tick).or_insert(Vec::new());
+fn _update_price_accumulator(current_tick: Tick) {
+    PRICE_ACCUMULATOR.with_borrow_mut(|reference| {
+        let mut price_accumulator = *reference.get();
+
+        price_accumulator.update(current_tick, ic_cdk::api::time());
 
-        accounts.push(account);
+        reference.set(price_accumulator).unwrap();
     })
 }
 
-/// Remove Tick Order
-///
-/// Removes an order under a particular tick
-///
-/// Utilised when account owner closes a limit position before reference tick is fully crossed
+/// Update Mark Price
 ///
-/// Params
-/// - Tick    :The tickat which order was placed
-/// - Account : The account closing the position
-pub fn remove_tick_order(tick: Tick, account: Subaccount) {
-    LIMIT_ORDERS_RECORD.with_borrow_mut(|reference| {
-        let accounts = reference.get_mut(&tick).unwrap();
+/// Updates the mark tick from the last good oracle spot price ,the perp time weighted average tick and the best bid and ask
+fn _update_mark_price() {
+    let now = ic_cdk::api::time();
 
-        let index = accounts.iter().position(|x| x == &account).unwrap();
+    let StateDetails { current_tick, .. } = _get_state_details();
 
-        accounts.remove(index);
-    })
-}
+    let oracle_details = _get_oracle_details();
 
-fn _schedule_execution_for_ticks_orders(crossed_ticks: Vec<Tick>) {
-    if crossed_ticks.len() == 0 {
-        return;
-    }
-    ic_cdk_timers::set_timer(Duration::from_nanos(2 * ONE_SECOND), || {
-        _execute_ticks_orders(crossed_ticks)
+    let spot_tick = if _is_oracle_stale(&oracle_details, &_get_funding_config()) {
+        None
+    } else {
+        let spot_price =
+            (oracle_details.last_price * _BASE_PRICE) / 10u128.pow(oracle_details.decimals);
+        Some(_price_to_tick(spot_price))
+    };
+
+    let price_accumulator = PRICE_ACCUMULATOR.with_borrow(|reference| *reference.get());
+
+    let twap_tick = if price_accumulator.last_updated == 0 {
+        current_tick
+    } else {
+        price_accumulator.average_tick(now)
+    };
+
+    let mid_tick = match (
+        get_best_offer(false, current_tick, None),
+        get_best_offer(true, current_tick, None),
+    ) {
+        (Some(best_bid), Some(best_ask)) => (best_bid + best_ask) / 2,
+        _ => current_tick,
+    };
+
+    MARK_PRICE.with_borrow_mut(|reference| {
+        reference
+            .set(MarkPrice {
+                mark_tick: _mark_tick(spot_tick, twap_tick, mid_tick),
+                spot_tick,
+                twap_tick,
+                mid_tick,
+                last_updated: now,
+            })
+            .unwrap();
     });
 }
 
-/// Execute Ticks Orders
+/// Get Mark Tick
 ///
-/// Notifies Watcher to execute all orders placed at those tick respectively
-///
-/// Ticks:  An array of ticks crossed during the swap (meaning all orders at those tick has been filled)
-pub fn _execute_ticks_orders(ticks: Vec<Tick>) {
-    let mut count = 1u64;
-
-    for tick in ticks {
-        // set the exceution incrementally
-        ic_cdk_timers::set_timer(Duration::from_nanos(count * ONE_SECOND), move || {
-            serialize_limit_orders_accounts(tick)
-        });
+/// Gets the mark tick utilised for valuing positions ,the current tick if the mark tick has not been updated
+fn _get_mark_tick() -> Tick {
+    let mark_price = MARK_PRICE.with_borrow(|reference| *reference.get());
 
-        count += 1
+    if mark_price.last_updated == 0 {
+        return _get_state_details().current_tick;
     }
-
-    ic_cdk_timers::set_timer(Duration::from_nanos((count + 1) * ONE_SECOND), || {
-        set_timer_for_limit_orders_execution();
-    });
+    mark_price.mark_tick
 }
 
-/// Serialize Limit Order Accounts
+/// Settle Average Tick
 ///
-/// serializes all accounts with limit orders at a particular tick
-fn serialize_limit_orders_accounts(tick: Tick) {
-    LIMIT_ORDERS_RECORD.with_borrow_mut(|reference| {
-        let accounts = reference.get(&tick).unwrap();
+/// Gets the time weighted average tick since the last funding rate settlement and starts a new funding window
+fn _settle_average_tick() -> Tick {
+    PRICE_ACCUMULATOR.with_borrow_mut(|reference| {
+        let mut price_accumulator = *reference.get();
 
-        for account in accounts.iter() {
-            EXECUTABLE_LIMIT_ORDERS_ACCOUNTS.with_borrow_mut(|ref2| {
-                ref2.push(account).unwrap();
-            })
+        if price_accumulator.last_updated == 0 {
+            price_accumulator.update(_get_state_details().current_tick, ic_cdk::api::time());
         }
+
+        let average_tick = price_accumulator.settle_window(ic_cdk::api::time());
+
+        reference.set(price_accumulator).unwrap();
+
+        average_tick
     })
 }
 
-/// Sets Timer for executing each limit order that has been filled, by creating a timer_interval for executing each limit order
+fn _insert_account_owner(account: Subaccount, owner: Principal) {
+    ACCOUNTS_OWNER.with_borrow_mut(|reference| reference.insert(account, owner));
+}
+
+fn _insert_account_position(position_key: PositionKey, position: PositionDetails) {
+    ACCOUNTS_POSITION.with(|ref_users_position| {
+        ref_users_position
+            .borrow_mut()
+            .insert(position_key, position)
+    });
+}
+
+/// Store Market Position
 ///
-/// Note:This function checks if TimerID is default(meaning it is not set or no timer interval is already running) and if true sets a timer_interval else
-/// starts a timer interval
-fn set_timer_for_limit_orders_execution() {
-    let pending_timer = _get_pending_timer();
+/// Stores a market position after it has been updated or removes it if it has been fully closed
+fn _store_market_position(position_key: PositionKey, position: PositionDetails) {
+    if position.volume_share == 0 {
+        _remove_account_position(&position_key);
+    } else {
+        _insert_account_position(position_key, position);
+    }
+}
 
-    if pending_timer == TimerId::default() {
-        let timer_id =
-            ic_cdk_timers::set_timer_interval(Duration::from_nanos(2 * ONE_SECOND), || {
-                _execute_each_limit_order();
-            });
+fn _remove_account_position(position_key: &PositionKey) {
+    let removed_position = ACCOUNTS_POSITION
+        .with(|ref_user_position| ref_user_position.borrow_mut().remove(position_key));
+
+    if let Some(position) = removed_position {
+        _remove_position_triggers(*position_key, &position);
 
-        _set_pending_timer(timer_id);
+        _close_position_record(*position_key);
     }
 }
 
-/// Execute Each Limit Order
-///
-/// Checks if user has any limit order ans if so  
-fn _execute_each_limit_order() {
-    EXECUTABLE_LIMIT_ORDERS_ACCOUNTS.with_borrow_mut(|reference| {
-        if reference.len() == 0 {
-            let timer_id = _get_pending_timer();
+fn _append_trade_record(trade_record: TradeRecord) {
+    TRADES_LOG.with_borrow_mut(|reference| reference.append(&trade_record).unwrap());
+}
 
-            _set_pending_timer(TimerId::default());
+/// Update Candles
+///
+/// Adds a fill to the candle of every candle interval containing the fill's timestamp
+///
+/// Note
+///  - The high and low are taken from the ticks crossed and the resulting tick of the fill
+///  - The volume is the value traded in the quote token
+fn _update_candles(trade_record: &TradeRecord) {
+    let close = _tick_to_price(trade_record.resulting_tick);
+
+    let (high, low) = trade_record
+        .crossed_ticks
+        .iter()
+        .map(|tick| _tick_to_price(*tick))
+        .fold((close, close), |(high, low), price| {
+            (high.max(price), low.min(price))
+        });
 
-            ic_cdk_timers::clear_timer(timer_id);
-        }
+    let volume = if trade_record.buy {
+        trade_record.size
+    } else {
+        trade_record.amount_out
+    };
 
-        let account = reference.pop().unwrap_or_default();
+    CANDLES.with_borrow_mut(|reference| {
+        for interval in CandleInterval::ALL {
+            let duration = interval.duration();
+
+            let start_time = trade_record.timestamp - (trade_record.timestamp % duration);
+
+            let candle = match reference.get(&(duration, start_time)) {
+                Some(candle) => Candle {
+                    open: candle.open,
+                    high: candle.high.max(high),
+                    low: candle.low.min(low),
+                    close,
+                    volume: candle.volume + volume,
+                },
+                None => Candle {
+                    // the first fill of a candle opens at the price it started executing at
+                    open: if trade_record.buy { low } else { high },
+                    high,
+                    low,
+                    close,
+                    volume,
+                },
+            };
 
-        _convert_account_limit_position(account);
-    })
+            reference.insert((duration, start_time), candle);
+        }
+    });
 }
-//////////////////////////////////////////////////////////////////////////////////////////////
-/////////////////////////////////////////////////////////////////////////////////////////////
 
-//////////////////////////////////////////////////////////////////////////////////////////////
-///////////////////////////////////////////////////////////////////////////////////////////////
-/// System Functions
-//////////////////////////////////////////////////////////////////////////////////////////////
-/////////////////////////////////////////////////////////////////////////////////////////////////
-#[ic_cdk::pre_upgrade]
-fn pre_upgrade() {
-    let limit_orders_accounts_record: HashMap<u64, Vec<[u8; 32]>> =
-        LIMIT_ORDERS_RECORD.with_borrow(|reference| reference.clone());
+/// Insert Position Record
+///
+/// Records a newly opened position in the positions history
+///
+/// Params
+///  - Position Key :The account and the id of the position
+///  - Position :The details of the opened position
+///  - Collateral Deposited :The collateral put into the position including the taker fee
+fn _insert_position_record(
+    position_key: PositionKey,
+    position: &PositionDetails,
+    collateral_deposited: Amount,
+) {
+    let position_record = PositionRecord {
+        long: position.long,
+        entry_tick: position.entry_tick,
+        opened_at: ic_cdk::api::time(),
+        closed_at: None,
+        collateral_deposited,
+        collateral_withdrawn: 0,
+        interest_paid: 0,
+        realised_pnl: -(collateral_deposited as i128),
+    };
 
-    storage::stable_save((limit_orders_accounts_record,)).expect("error storing data");
+    POSITIONS_HISTORY.with_borrow_mut(|reference| reference.insert(position_key, position_record));
 }
 
-#[ic_cdk::post_upgrade]
-fn post_upgrade() {
-    let limit_orders_accounts_record: HashMap<u64, Vec<[u8; 32]>>;
+/// Update Position Record
+///
+/// Adds the collateral deposited or withdrawn and the interest paid on a position update to the position record
+fn _update_position_record(
+    position_key: PositionKey,
+    collateral_deposited: Amount,
+    collateral_withdrawn: Amount,
+    interest_paid: Amount,
+) {
+    POSITIONS_HISTORY.with_borrow_mut(|reference| {
+        if let Some(mut position_record) = reference.get(&position_key) {
+            position_record.collateral_deposited += collateral_deposited;
+            position_record.collateral_withdrawn += collateral_withdrawn;
+            position_record.interest_paid += interest_paid;
+            position_record.realised_pnl = position_record.collateral_withdrawn as i128
+                - position_record.collateral_deposited as i128;
+
+            reference.insert(position_key, position_record);
+        }
+    });
+}
 
-    (limit_orders_accounts_record,) = storage::stable_restore().unwrap();
+fn _close_position_record(position_key: PositionKey) {
+    POSITIONS_HISTORY.with_borrow_mut(|reference| {
+        if let Some(mut position_record) = reference.get(&position_key) {
+            position_record.closed_at = Some(ic_cdk::api::time());
 
-    LIMIT_ORDERS_RECORD.with_borrow_mut(|reference| {
-        *reference = limit_orders_accounts_record;
-    })
-}
-//////////////////////////////////////////////////////////////////////////////////////////////
-/////////////////////////////////////////////////////////////////////////////////////////////
-/// Admin Functions
-//////////////////////////////////////////////////////////////////////////////////////////////
-//////////////////////////////////////////////////////////////////////////////////////////////
-fn admin_guard() -> Result<(), String> {
-    ADMIN.with_borrow(|admin_ref| {
-        let admin = admin_ref.get().clone();
-        if ic_cdk::caller() == admin {
-            return Ok(());
-        } else {
-            return Err("Invalid".to_string());
+            reference.insert(position_key, position_record);
+        }
+    });
+}
+
+/// Insert Account Error Log
+///
+/// Stores an error log under the account with the index after the account's last error log
+fn _insert_account_error_log(account: Subaccount, error_log: PositionUpdateErrorLog) {
+    ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| {
+        let error_index = match reference
+            .range((account, 0)..=(account, u64::MAX))
+            .next_back()
+        {
+            Some(((_, last_index), _)) => last_index + 1,
+            None => 0,
         };
-    })
+
+        reference.insert((account, error_index), error_log)
+    });
 }
 
-#[ic_cdk::update(guard = "admin_guard", name = "updateStateDetails")]
-async fn update_state_details(new_state_details: StateDetails) {
-    _set_state_details(new_state_details);
+fn _remove_account_error_log(account: &Subaccount, error_index: u64) {
+    ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| reference.remove(&(*account, error_index)));
 }
 
-#[ic_cdk::update(guard = "admin_guard", name = "startTimer")]
-async fn start_timer() {
-    ic_cdk_timers::set_timer_interval(Duration::from_nanos(ONE_HOUR), || {
-        ic_cdk::spawn(async { settle_funding_rate().await });
-    });
+fn _has_pending_error_log(_account: &Subaccount) -> bool {
+    ACCOUNTS_ERROR_LOGS.with_borrow(|reference| {
+        reference
+            .range((*_account, 0)..=(*_account, u64::MAX))
+            .next()
+            .is_some()
+    })
 }
 
-//////////////////////////////////////////////////////////////////////////////////////////////////
-//////////////////////////////////////////////////////////////////////////////////////////////////
-////////////////////////////////////////////////////////////////////////////////////////////////////
-///  Error Handling Functions
-//////////////////////////////////////////////////////////////////////////////////////////////////
-/// ///////////////////////////////////////////////////////////////////////////////////////////////
-////////////////////////////////////////////////////////////////////////////////////////////////////
-fn trusted_canister_guard() -> Result<(), String> {
-    let market_details = _get_market_details();
+/// Next Position Id
+///
+/// Returns the id for a new position and increments the position id counter
+fn _next_position_id() -> PositionId {
+    POSITION_ID_COUNTER.with_borrow_mut(|reference| {
+        let position_id = *reference.get();
 
-    let caller = ic_cdk::caller();
+        reference.set(position_id + 1).unwrap();
 
-    if caller == market_details.vault_id {
-        return Ok(());
-    } else {
-        return Err("Untrusted Caller".to_string());
-    }
+        position_id
+    })
 }
 
-#[ic_cdk::update(name = "retryAccountError")]
-async fn retry_account_error(user: Principal) {
-    let account = user._to_subaccount();
+fn _set_sweeper_config(sweeper_config: SweeperConfig) {
+    SWEEPER_CONFIG.with_borrow_mut(|reference| reference.set(sweeper_config).unwrap());
+}
 
-    let account_error_log = _get_account_error_log(&account);
+fn _set_pending_timer(timer_id: TimerId) {
+    PENDING_TIMER.with_borrow_mut(|reference| {
+        *reference = timer_id;
+    })
+}
+////////////////////////////////////////////////////////////////////////////////////////////////////
+///////////////////////////////////////////////////////////////////////////////////////////////////
+////////////////////////////////////////////////////////////////////////////////////////////////////
 
-    let details = _get_market_details();
-    account_error_log.retry(details);
+#[derive(CandidType, Deserialize, Debug, Serialize, Clone, Copy)]
+enum OrderType {
+    Market,
+    Limit,
 }
 
-#[ic_cdk::update(name = "successNotification", guard = "trusted_canister_guard")]
-async fn success_notif(account: Subaccount, _error_index: usize) {
-    let market_details = _get_market_details();
+/// Order Flags
+///
+/// Optional flags that restrict how an order is executed
+#[derive(CandidType, Deserialize, Debug, Default, Clone, Copy)]
+struct OrderFlags {
+    /// Post Only
+    ///
+    /// a post only limit order is rejected if it would cross the best offer instead of being executed at market
+    post_only: bool,
+    /// Reduce Only
+    ///
+    /// the id of the position to reduce ,a reduce only order only closes part of that position
+    reduce_only: Option<PositionId>,
+    /// Time In Force
+    ///
+    /// how long the order remains active ,if none limit orders are good till cancelled and market orders are immediate or cancel
+    time_in_force: Option<TimeInForce>,
+}
 
-    let caller = ic_cdk::caller();
+/// Time In Force
+///
+/// How long an order remains active before it is executed or cancelled
+#[derive(CandidType, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+enum TimeInForce {
+    /// Good Till Cancelled ,the order rests in the book until it is filled or closed ,only for limit orders
+    Gtc,
+    /// Immediate Or Cancel ,the order is filled as far as possible up to the max tick and the rest is refunded
+    Ioc,
+    /// Fill Or Kill ,the order is reverted completely if it can not be fully filled up to the max tick
+    Fok,
+    /// Good Till Time ,the order rests in the book until it is filled or the expiry time (in nanoseconds) is reached ,only for limit orders
+    Gtt(Time),
+}
 
-    if caller == market_details.vault_id {
-        _remove_account_error_log(&account);
-        return;
+impl TimeInForce {
+    /// Rests In Book
+    ///
+    /// true if an order with this time in force rests in the book when not immediately filled
+    fn rests_in_book(&self) -> bool {
+        matches!(self, TimeInForce::Gtc | TimeInForce::Gtt(_))
     }
 }
-/////////////////////////////////////////////////////////////////////////////////////////////////////
-/////////////////////////////////////////////////////////////////////////////////////////////////////
-/////////////////////////////////////////////////////////////////////////////////////////////////////
-
-///////////////////////////////////////////////////////////////////////////////////////////////////////
-/////////////////////////////////////////////////////////////////////////////////////////////////////
-/// Getter Functions
-///////////////////////////////////////////////////////////////////////////////////////////////////////
 
-fn _get_market_details() -> MarketDetails {
-    MARKET_DETAILS.with(|ref_market_details| ref_market_details.borrow().get().clone())
+/// Position Reduction
+///
+/// The part of a market position to close when reducing the position
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+enum PositionReduction {
+    /// Fraction of the position in percentage i.e 50% is 50 * 100_000
+    Fraction(u64),
+    /// Value of the position to close in collateral token
+    Amount(Amount),
 }
 
-fn _get_state_details() -> StateDetails {
-    STATE_DETAILS.with(|ref_state_detaills| *ref_state_detaills.borrow().get())
+/// Swap Quote
+///
+/// The expected fill of a swap ,amounts in are in the quote token for a buy and in the perp asset for a sell
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct SwapQuote {
+    /// Amount In
+    ///
+    /// the amount of the order that would be filled
+    amount_in: Amount,
+    /// Amount Out
+    ///
+    /// the amount received ,in the perp asset for a buy and in the quote token for a sell
+    amount_out: Amount,
+    /// Amount Remaining
+    ///
+    /// the amount of the order that would remain unfilled
+    amount_remaining: Amount,
+    /// Average Price
+    ///
+    /// the average execution price in the same decimals as tick prices (see tick_lib) ,zero if nothing would be filled
+    average_price: Amount,
+    resulting_tick: Tick,
+    crossed_ticks: Vec<Tick>,
+    /// Taker Fee
+    ///
+    /// the taker fee that would be charged in the quote token
+    taker_fee: Amount,
 }
 
-fn _get_account_position(account: &Subaccount) -> PositionDetails {
-    ACCOUNTS_POSITION
-        .with(|ref_position_details| ref_position_details.borrow().get(&account).unwrap())
+/// Trade Record
+///
+/// A fill of a swap stored in the trades log
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct TradeRecord {
+    /// the account of the swap initiator (taker)
+    account: Subaccount,
+    /// true if the taker bought the perp asset
+    buy: bool,
+    /// Size
+    ///
+    /// the amount of the order filled ,in the quote token for a buy and in the perp asset for a sell
+    size: Amount,
+    /// Amount Out
+    ///
+    /// the amount received ,in the perp asset for a buy and in the quote token for a sell
+    amount_out: Amount,
+    /// Average Price (see Average Price)
+    average_price: Amount,
+    resulting_tick: Tick,
+    crossed_ticks: Vec<Tick>,
+    timestamp: Time,
 }
 
-fn _get_account_error_log(account: &Subaccount) -> PositionUpdateErrorLog {
-    ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.get(account).unwrap())
+impl Storable for TradeRecord {
+    const BOUND: Bound = Bound::Unbounded;
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 }
 
-fn _get_pending_timer() -> TimerId {
-    PENDING_TIMER.with_borrow_mut(|reference| reference.clone())
+/// Position Record
+///
+/// The history of a position kept after the position is closed
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+struct PositionRecord {
+    long: bool,
+    /// the entry tick when the position was opened
+    entry_tick: Tick,
+    opened_at: Time,
+    /// the time the position was fully closed ,none while the position is open
+    closed_at: Option<Time>,
+    /// Collateral Deposited
+    ///
+    /// the total collateral put into the position including taker fees
+    collateral_deposited: Amount,
+    /// Collateral Withdrawn
+    ///
+    /// the total amount sent back to the owner from closing ,reducing or liquidating the position
+    collateral_withdrawn: Amount,
+    /// the total interest paid on the position debt
+    interest_paid: Amount,
+    /// Realised PNL
+    ///
+    /// the collateral withdrawn minus the collateral deposited ,final once the position is closed
+    realised_pnl: i128,
 }
 
-////////////////////////////////////////////////////////////////////////////////////////////////////
-/// ////////////////////////////////////////////////////////////////////////////////////////////////
-/////////////////////////////////////////////////////////////////////////////////////////////////////
+impl Storable for PositionRecord {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 300,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-///////////////////////////////////////////////////////////////////////////////////////////////////
-/// /////////////////////////////////////////////////////////////////////////////////////////////////
-///   Setter Function
-//////////////////////////////////////////////////////////////////////////////////////////////////////
-fn _set_state_details(new_state: StateDetails) {
-    STATE_DETAILS.with(|ref_state_details| ref_state_details.borrow_mut().set(new_state).unwrap());
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 }
 
-fn _insert_account_position(account: Subaccount, position: PositionDetails) {
-    ACCOUNTS_POSITION
-        .with(|ref_users_position| ref_users_position.borrow_mut().insert(account, position));
+/// Candle Interval
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+enum CandleInterval {
+    OneMinute,
+    FiveMinutes,
+    OneHour,
+    OneDay,
 }
 
-fn _remove_account_position(account: &Subaccount) {
-    ACCOUNTS_POSITION.with(|ref_user_position| ref_user_position.borrow_mut().remove(account));
-}
+impl CandleInterval {
+    const ALL: [CandleInterval; 4] = [
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::OneDay,
+    ];
 
-fn _insert_account_error_log(account: Subaccount, error_log: PositionUpdateErrorLog) {
-    ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| reference.insert(account, error_log));
+    /// Duration
+    ///
+    /// the length of the interval in nanoseconds
+    fn duration(&self) -> u64 {
+        match self {
+            CandleInterval::OneMinute => ONE_MINUTE,
+            CandleInterval::FiveMinutes => 5 * ONE_MINUTE,
+            CandleInterval::OneHour => ONE_HOUR,
+            CandleInterval::OneDay => ONE_DAY,
+        }
+    }
 }
 
-fn _remove_account_error_log(account: &Subaccount) {
-    ACCOUNTS_ERROR_LOGS.with_borrow_mut(|reference| reference.remove(account));
+/// Candle
+///
+/// The open ,high ,low and close prices (see tick_lib) and the volume in the quote token of the swaps executed within an interval
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+struct Candle {
+    open: Amount,
+    high: Amount,
+    low: Amount,
+    close: Amount,
+    volume: Amount,
 }
 
-fn _has_position_or_pending_error_log(_account: &Subaccount) -> bool {
-    let has_position = ACCOUNTS_POSITION.with_borrow(|reference| reference.contains_key(_account));
-    let has_pending_error =
-        ACCOUNTS_ERROR_LOGS.with_borrow(|reference| reference.contains_key(_account));
+impl Storable for Candle {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 150,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
 
-    return has_pending_error || has_position;
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
 }
 
-fn _set_pending_timer(timer_id: TimerId) {
-    PENDING_TIMER.with_borrow_mut(|reference| {
-        *reference = timer_id;
-    })
+/// Order Book Side
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+enum OrderBookSide {
+    /// Long limit orders ,filled by sell orders
+    Bids,
+    /// Short limit orders ,filled by buy orders
+    Asks,
 }
-////////////////////////////////////////////////////////////////////////////////////////////////////
-///////////////////////////////////////////////////////////////////////////////////////////////////
-////////////////////////////////////////////////////////////////////////////////////////////////////
 
-#[derive(CandidType, Deserialize, Debug, Serialize, Clone, Copy)]
-enum OrderType {
-    Market,
-    Limit,
+/// Order Book Level
+///
+/// The aggregated liquidity of all limit orders at a tick
+#[derive(CandidType, Deserialize, Debug, Clone, Copy)]
+struct OrderBookLevel {
+    tick: Tick,
+    /// Price at the tick (see tick_lib)
+    price: Amount,
+    /// Liquidity
+    ///
+    /// the unfilled liquidity at the tick ,in the quote token for bids and in the perp asset for asks
+    liquidity: Amount,
+}
+
+/// Order Book Page
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct OrderBookPage {
+    levels: Vec<OrderBookLevel>,
+    /// Next Tick
+    ///
+    /// the start tick of the next page ,none if there are no more levels
+    next_tick: Option<Tick>,
 }
 
 #[derive(CandidType, Deserialize, Debug, Serialize, Clone, Copy)]
@@ -1739,11 +4901,23 @@ struct PositionDetails {
     ///
     /// Note: For order type, position this  is time  order was excuted
     timestamp: Time,
+    /// Stop Loss Tick
+    ///
+    /// tick at which a market position is closed to stop further loss
+    stop_loss_tick: Option<Tick>,
+    /// Take Profit Tick
+    ///
+    /// tick at which a market position is closed to take profit
+    take_profit_tick: Option<Tick>,
+    /// Funding Index
+    ///
+    /// the funding index of the position direction when the position was executed (see Apply Position Funding)
+    funding_index: i128,
 }
 
 impl Storable for PositionDetails {
     const BOUND: Bound = Bound::Bounded {
-        max_size: 200,
+        max_size: 320,
         is_fixed_size: false,
     };
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -1761,6 +4935,8 @@ struct ManageDebtParams {
     initial_debt: Amount,
     net_debt: Amount,
     amount_repaid: Amount,
+    /// the trading fees paid with the update ,distributed to the vault stakers
+    trading_fee: Amount,
 }
 
 impl ManageDebtParams {
@@ -1769,10 +4945,85 @@ impl ManageDebtParams {
             initial_debt,
             net_debt,
             amount_repaid,
+            trading_fee: 0,
+        }
+    }
+
+    /// Requires Update
+    ///
+    /// true if the params repay debt or pay trading fees and so must be sent to the vault
+    fn requires_update(&self) -> bool {
+        self.amount_repaid != 0 || self.trading_fee != 0
+    }
+
+    /// Interest Value
+    ///
+    /// the interest accrued on the initial debt
+    fn interest_value(&self) -> Amount {
+        self.net_debt - self.initial_debt
+    }
+}
+
+/// Liquidation Sweeper
+///
+/// Details of the timer driven sweeper that liquidates positions in batches
+///
+/// Note:The timers do not persist across upgrades ,the sweeper is restarted from the sweeper config (see Sweeper Config)
+struct LiquidationSweeper {
+    /// the timer id of the sweeper interval ,default if the sweeper is stopped
+    timer_id: TimerId,
+    /// true if a sweep has been scheduled after a tick move and has not run yet
+    sweep_scheduled: bool,
+}
+
+/// Sweeper Config
+///
+/// The stored state of the liquidation sweeper
+#[derive(CandidType, Deserialize, Clone, Copy)]
+struct SweeperConfig {
+    /// true if the sweeper has been started by the admin
+    running: bool,
+    /// the max number of positions checked per sweep
+    batch_size: u64,
+    /// the last position checked ,the next sweep starts after it
+    last_checked: Option<PositionKey>,
+}
+
+impl Default for SweeperConfig {
+    fn default() -> Self {
+        SweeperConfig {
+            running: false,
+            batch_size: _DEFAULT_SWEEP_BATCH_SIZE,
+            last_checked: None,
         }
     }
 }
 
+impl Storable for SweeperConfig {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 100,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// LiquidationFees is utilised to split the liquidation fee of a liquidated position
+#[derive(Copy, Clone, Deserialize, CandidType)]
+struct LiquidationFees {
+    /// the caller of the liquidation
+    keeper: Principal,
+    /// the part of the liquidation fee added to the keeper's margin balance
+    keeper_fee: Amount,
+    /// the part of the liquidation fee added to the insurance fund
+    insurance_fee: Amount,
+}
+
 /////////////////////////////
 ///   Possible error during inter canister calls and retry api
 ////////////////////////////
@@ -1783,8 +5034,8 @@ impl ManageDebtParams {
 trait Retrying {
     /// Retry  Function
     ///
-    /// This is used to retry the  failed inter canister call
-    fn retry(&self, details: MarketDetails);
+    /// This is used to retry the  failed inter canister call ,returns an error if the call could not be sent
+    fn retry(&self, details: MarketDetails) -> Result<(), RejectionCode>;
 }
 
 /// ManageDebtError
@@ -1795,20 +5046,35 @@ struct PositionUpdateErrorLog {
     user: Principal,
     profit: Amount,
     debt_params: ManageDebtParams,
+    /// the liquidation fees and true if the position was fully closed ,none if the failed call was a position update
+    liquidation: Option<(LiquidationFees, bool)>,
 }
 impl Retrying for PositionUpdateErrorLog {
-    fn retry(&self, details: MarketDetails) {
-        let _ = ic_cdk::notify(
-            details.vault_id,
-            "managePositionUpdate",
-            (self.user, self.profit, self.debt_params),
-        );
+    fn retry(&self, details: MarketDetails) -> Result<(), RejectionCode> {
+        match self.liquidation {
+            Some((liquidation_fees, position_closed)) => ic_cdk::notify(
+                details.vault_id,
+                "manageLiquidation",
+                (
+                    self.user,
+                    self.profit,
+                    liquidation_fees,
+                    self.debt_params,
+                    position_closed,
+                ),
+            ),
+            None => ic_cdk::notify(
+                details.vault_id,
+                "managePositionUpdate",
+                (self.user, self.profit, self.debt_params),
+            ),
+        }
     }
 }
 
 impl Storable for PositionUpdateErrorLog {
     const BOUND: Bound = Bound::Bounded {
-        max_size: 130,
+        max_size: 300,
         is_fixed_size: false,
     };
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -1820,32 +5086,124 @@ impl Storable for PositionUpdateErrorLog {
     }
 }
 
+/// Price Oracle
+///
+/// A source of the spot price of the market pair ,selected by the oracle kind in the market details
+trait PriceOracle {
+    /// fetches the current spot price of the market pair
+    async fn _get_spot_price(&self) -> Result<SpotPrice, OracleError>;
+}
+
 /// Exchange Rate Canister
 ///
 /// Utilised for fetching the price of current exchnage rate (spot price) of the market pair
 struct XRC {
     canister_id: Principal,
+    base_asset: Asset,
+    quote_asset: Asset,
 }
 
 impl XRC {
-    fn init(canister_id: Principal) -> Self {
-        XRC { canister_id }
+    fn init(canister_id: Principal, base_asset: Asset, quote_asset: Asset) -> Self {
+        XRC {
+            canister_id,
+            base_asset,
+            quote_asset,
+        }
     }
 
     /// tries to fetch the current exchange rate of the pair and returns the result
-    async fn _get_exchange_rate(&self, request: GetExchangeRateRequest) -> GetExchangeRateResult {
-        if let Ok((rate_result,)) = ic_cdk::api::call::call_with_payment128(
-            self.canister_id,
-            "get_exchange_rate",
-            (request,),
-            1_000_000_000,
-        )
-        .await
-        {
-            return rate_result;
-        } else {
-            panic!()
+    ///
+    /// Note:Fails with an Oracle Error if the call is rejected or the XRC canister returns an error
+    async fn _get_exchange_rate(
+        &self,
+        request: GetExchangeRateRequest,
+    ) -> Result<ExchangeRate, OracleError> {
+        let call_result: ic_cdk::api::call::CallResult<(GetExchangeRateResult,)> =
+            ic_cdk::api::call::call_with_payment128(
+                self.canister_id,
+                "get_exchange_rate",
+                (request,),
+                1_000_000_000,
+            )
+            .await;
+
+        match call_result {
+            Ok((rate_result,)) => rate_result.map_err(OracleError::ExchangeRate),
+            Err((code, message)) => {
+                Err(OracleError::CallFailed(format!("{:?}: {}", code, message)))
+            }
+        }
+    }
+}
+
+impl PriceOracle for XRC {
+    async fn _get_spot_price(&self) -> Result<SpotPrice, OracleError> {
+        let request = GetExchangeRateRequest {
+            base_asset: self.base_asset.clone(),
+            quote_asset: self.quote_asset.clone(),
+            timestamp: None,
+        };
+
+        let rate_result = self._get_exchange_rate(request).await?;
+
+        Ok(SpotPrice {
+            price: rate_result.rate as u128,
+            decimals: rate_result.metadata.decimals,
+            timestamp: rate_result.timestamp * ONE_SECOND,
+        })
+    }
+}
+
+/// Pushed Oracle
+///
+/// Utilised for reading the last price pushed to the price feed by the admin or the price keeper
+struct PushedOracle {
+    price_feed: PriceFeed,
+}
+
+impl PushedOracle {
+    fn init() -> Self {
+        PushedOracle {
+            price_feed: _get_price_feed(),
+        }
+    }
+}
+
+impl PriceOracle for PushedOracle {
+    /// Note:The pushed price keeps the time it was pushed so it goes stale if no price is pushed
+    async fn _get_spot_price(&self) -> Result<SpotPrice, OracleError> {
+        if self.price_feed.last_price.timestamp == 0 {
+            return Err(OracleError::NoPrice);
+        }
+        Ok(self.price_feed.last_price)
+    }
+}
+
+/// Mock Oracle
+///
+/// Deterministic price oracle for local testing ,returns the last price pushed to the price feed timestamped at the current time
+struct MockOracle {
+    price_feed: PriceFeed,
+}
+
+impl MockOracle {
+    fn init() -> Self {
+        MockOracle {
+            price_feed: _get_price_feed(),
+        }
+    }
+}
+
+impl PriceOracle for MockOracle {
+    async fn _get_spot_price(&self) -> Result<SpotPrice, OracleError> {
+        if self.price_feed.last_price.timestamp == 0 {
+            return Err(OracleError::NoPrice);
         }
+        Ok(SpotPrice {
+            timestamp: ic_cdk::api::time(),
+            ..self.price_feed.last_price
+        })
     }
 }
 
@@ -1882,6 +5240,43 @@ impl Vault {
                 user,
                 profit,
                 debt_params: manage_debt_params,
+                liquidation: None,
+            };
+            _insert_account_error_log(user._to_subaccount(), error_log);
+        }
+    }
+
+    /// Manage Liquidation
+    ///
+    /// Utilised when position is liquidated or closed without repaying it's debt for settling the debt and the liquidation fees
+    ///
+    /// Note :If the vault can not be notified ,the liquidation settlement is stored as an error log to be retried (see Retry Account Error)
+    pub fn manage_liquidation(
+        &self,
+        user: Principal,
+        margin_delta: Amount,
+        liquidation_fees: LiquidationFees,
+        manage_debt_params: ManageDebtParams,
+        position_closed: bool,
+    ) {
+        if ic_cdk::notify(
+            self.canister_id,
+            "manageLiquidation",
+            (
+                user,
+                margin_delta,
+                liquidation_fees,
+                manage_debt_params,
+                position_closed,
+            ),
+        )
+        .is_err()
+        {
+            let error_log = PositionUpdateErrorLog {
+                user,
+                profit: margin_delta,
+                debt_params: manage_debt_params,
+                liquidation: Some((liquidation_fees, position_closed)),
             };
             _insert_account_error_log(user._to_subaccount(), error_log);
         }
@@ -1938,6 +5333,7 @@ impl UniqueSubAccount for Principal {
 export_candid!();
 
 pub mod corelib;
+mod migration;
 pub mod types;
 
 #[cfg(test)]
@@ -0,0 +1,462 @@
+use super::*;
+
+use candid::de::IDLDeserialize;
+use ic_stable_structures::{Memory as StableMemory, StableVec};
+use std::collections::HashMap;
+
+/// the memory manager layout of the previous version ,it's header was overwritten by the pre upgrade hook
+const _LEGACY_LAYOUT_VERSION: u8 = 1;
+
+const _LEGACY_MAX_NUM_MEMORIES: usize = 255;
+
+const _LEGACY_MAX_NUM_BUCKETS: usize = 32768;
+
+const _LEGACY_BUCKET_SIZE_IN_PAGES: u16 = 128;
+
+const _LEGACY_UNALLOCATED_BUCKET: u8 = 255;
+
+/// magic ,version ,allocated buckets ,bucket size ,reserved bytes and the size of each memory
+const _LEGACY_HEADER_SIZE: u64 = 3 + 1 + 2 + 2 + 32 + 8 * _LEGACY_MAX_NUM_MEMORIES as u64;
+
+const _LEGACY_BUCKETS_OFFSET: u64 = 65536;
+
+/// Limit Orders Record (Version 0)
+///
+/// The accounts with limit orders at each tick ,kept on the heap and written to stable memory by the pre upgrade hook
+pub(crate) type LimitOrdersRecordV0 = HashMap<Tick, Vec<Subaccount>>;
+
+/// Position Details (Version 0)
+///
+/// The details of a position stored by the account only ,before an account could hold multiple positions
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub(crate) struct PositionDetailsV0 {
+    entry_tick: Tick,
+    long: bool,
+    collateral_value: Amount,
+    debt_value: Amount,
+    volume_share: Amount,
+    interest_rate: u32,
+    order_type: PositionOrderTypeV0,
+    timestamp: Time,
+}
+
+/// Position Order Type (Version 0)
+///
+/// The order type of a position stored before limit orders recorded the maker fee
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub(crate) enum PositionOrderTypeV0 {
+    Market,
+    Limit(LimitOrderV0),
+}
+
+/// Limit Order (Version 0)
+///
+/// The limit order stored before the maker fee was recorded on the order
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub(crate) struct LimitOrderV0 {
+    buy: bool,
+    order_size: Amount,
+    init_removed_liquidity: Amount,
+    init_lower_bound: Amount,
+    ref_tick: Tick,
+    init_tick_timestamp: Time,
+}
+
+impl From<PositionOrderTypeV0> for PositionOrderType {
+    fn from(order_type: PositionOrderTypeV0) -> Self {
+        match order_type {
+            PositionOrderTypeV0::Market => PositionOrderType::Market,
+            PositionOrderTypeV0::Limit(order) => PositionOrderType::Limit(LimitOrder {
+                buy: order.buy,
+                order_size: order.order_size,
+                init_removed_liquidity: order.init_removed_liquidity,
+                init_lower_bound: order.init_lower_bound,
+                ref_tick: order.ref_tick,
+                init_tick_timestamp: order.init_tick_timestamp,
+                // no maker fee was charged before the fee was added
+                maker_fee: 0,
+            }),
+        }
+    }
+}
+
+impl Storable for PositionDetailsV0 {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 200,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Manage Debt Params (Version 0)
+///
+/// The debt management params before the trading fee was added
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub(crate) struct ManageDebtParamsV0 {
+    initial_debt: Amount,
+    net_debt: Amount,
+    amount_repaid: Amount,
+}
+
+/// Position Update Error Log (Version 0)
+///
+/// The error log stored by the account only ,before an account could hold multiple error logs
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub(crate) struct PositionUpdateErrorLogV0 {
+    user: Principal,
+    profit: Amount,
+    debt_params: ManageDebtParamsV0,
+}
+
+impl Storable for PositionUpdateErrorLogV0 {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 130,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Migrate Accounts Positions
+///
+/// Moves the positions and the error logs stored by account into the current memories ,giving each position a new position id
+///
+/// Note
+///  - Limit positions whose orders were waiting to be executed are added to the crossed ticks queue at their order's reference tick
+///  - The owner of an account with an error log is recorded as the account owner
+///  - The legacy memories are reset after being migrated ,so the migration only runs once
+pub(crate) fn _migrate_accounts_positions() {
+    let legacy_positions_memory =
+        MEMORY_MANAGER.with_borrow(|s| s.get(_LEGACY_ACCOUNTS_POSITION_MEMORY));
+
+    let legacy_positions: Vec<(Subaccount, PositionDetailsV0)> =
+        StableBTreeMap::<Subaccount, PositionDetailsV0, Memory>::init(
+            legacy_positions_memory.clone(),
+        )
+        .iter()
+        .collect();
+
+    let legacy_executable_memory =
+        MEMORY_MANAGER.with_borrow(|s| s.get(_LEGACY_EXECUTABLE_ORDERS_MEMORY));
+
+    let legacy_executable_accounts: Vec<Subaccount> =
+        StableVec::<Subaccount, Memory>::init(legacy_executable_memory.clone())
+            .unwrap()
+            .iter()
+            .collect();
+
+    let legacy_error_logs_memory =
+        MEMORY_MANAGER.with_borrow(|s| s.get(_LEGACY_ACCOUNT_ERROR_LOGS_MEMORY));
+
+    let legacy_error_logs: Vec<(Subaccount, PositionUpdateErrorLogV0)> =
+        StableBTreeMap::<Subaccount, PositionUpdateErrorLogV0, Memory>::init(
+            legacy_error_logs_memory.clone(),
+        )
+        .iter()
+        .collect();
+
+    for (account, legacy_position) in legacy_positions.iter() {
+        let position_id = _next_position_id();
+
+        let position = PositionDetails {
+            entry_tick: legacy_position.entry_tick,
+            long: legacy_position.long,
+            collateral_value: legacy_position.collateral_value,
+            debt_value: legacy_position.debt_value,
+            volume_share: legacy_position.volume_share,
+            interest_rate: legacy_position.interest_rate,
+            order_type: legacy_position.order_type.into(),
+            timestamp: legacy_position.timestamp,
+            stop_loss_tick: None,
+            take_profit_tick: None,
+            // funding before the migration is not charged
+            funding_index: _get_funding_index(legacy_position.long),
+        };
+
+        _insert_account_position((*account, position_id), position);
+
+        if !legacy_executable_accounts.contains(account) {
+            continue;
+        }
+
+        if let PositionOrderType::Limit(order) = position.order_type {
+            CROSSED_TICKS_QUEUE.with_borrow_mut(|reference| {
+                reference.insert((order.ref_tick, *account, position_id), ())
+            });
+        }
+    }
+
+    for (account, legacy_error_log) in legacy_error_logs.iter() {
+        let ManageDebtParamsV0 {
+            initial_debt,
+            net_debt,
+            amount_repaid,
+        } = legacy_error_log.debt_params;
+
+        let error_log = PositionUpdateErrorLog {
+            user: legacy_error_log.user,
+            profit: legacy_error_log.profit,
+            debt_params: ManageDebtParams::init(initial_debt, net_debt, amount_repaid),
+            liquidation: None,
+        };
+
+        _insert_account_error_log(*account, error_log);
+
+        _insert_account_owner(*account, legacy_error_log.user);
+    }
+
+    if !legacy_positions.is_empty() {
+        StableBTreeMap::<Subaccount, PositionDetailsV0, Memory>::new(legacy_positions_memory);
+    }
+
+    if !legacy_executable_accounts.is_empty() {
+        StableVec::<Subaccount, Memory>::new(legacy_executable_memory).unwrap();
+    }
+
+    if !legacy_error_logs.is_empty() {
+        StableBTreeMap::<Subaccount, PositionUpdateErrorLogV0, Memory>::new(
+            legacy_error_logs_memory,
+        );
+    }
+}
+
+/// Read Legacy Limit Orders Record
+///
+/// Reads the limit orders record saved to stable memory by the pre upgrade hook of the previous version
+///
+/// Params
+///  - Memory :The stable memory
+///
+/// Returns
+///  - Limit Orders Record :The accounts with limit orders at each tick ,none if stable memory does not start with a saved record
+///
+/// Note
+///  - The record was saved at the start of stable memory ,over the memory manager header ,so this must be called before the memory manager is first accessed (see Restore Legacy Memory Manager Header)
+///  - Only the first page is read ,a record running past it has also overwritten the first memory bucket
+pub(crate) fn _read_legacy_limit_orders_record<M: StableMemory>(
+    memory: &M,
+) -> Option<LimitOrdersRecordV0> {
+    if memory.size() == 0 {
+        return None;
+    }
+
+    let mut bytes = vec![0u8; _LEGACY_BUCKETS_OFFSET as usize];
+    memory.read(0, &mut bytes);
+
+    if !bytes.starts_with(b"DIDL") {
+        return None;
+    }
+
+    let mut deserializer = IDLDeserialize::new(&bytes).ok()?;
+
+    deserializer.get_value::<LimitOrdersRecordV0>().ok()
+}
+
+/// Restore Legacy Memory Manager Header
+///
+/// Rebuilds the memory manager header overwritten by the limit orders record of the previous version ,so that the memory manager loads the previous memories instead of starting empty
+///
+/// Params
+///  - Memory :The stable memory
+///  - Limit Orders Record :The legacy record read from the start of stable memory (see Read Legacy Limit Orders Record)
+///
+/// Note
+///  - The bucket allocations follow the header and are kept if the record fits within the header ,each memory's size is rebuilt as all of it's allocated buckets
+///  - A record larger than the header has also overwritten the bucket allocations ,the upgrade then fails and is rolled back ,the positions have to be exported from the previous version and imported after a fresh install
+pub(crate) fn _restore_legacy_memory_manager_header<M: StableMemory>(
+    memory: &M,
+    limit_orders_record: &LimitOrdersRecordV0,
+) {
+    let record_size = Encode!(limit_orders_record).unwrap().len() as u64;
+
+    assert!(
+        record_size <= _LEGACY_HEADER_SIZE,
+        "The limit orders record has overwritten the stable memory layout"
+    );
+
+    let mut buckets = vec![0u8; _LEGACY_MAX_NUM_BUCKETS];
+    memory.read(_LEGACY_HEADER_SIZE, &mut buckets);
+
+    // buckets are allocated in order ,so the allocated buckets come before any unallocated bucket
+    let allocated_buckets = buckets
+        .iter()
+        .take_while(|memory_id| **memory_id != _LEGACY_UNALLOCATED_BUCKET)
+        .count();
+
+    assert!(
+        buckets[allocated_buckets..]
+            .iter()
+            .all(|memory_id| *memory_id == _LEGACY_UNALLOCATED_BUCKET),
+        "The stable memory bucket allocations are corrupted"
+    );
+
+    let mut memory_sizes_in_pages = [0u64; _LEGACY_MAX_NUM_MEMORIES];
+
+    for memory_id in buckets[..allocated_buckets].iter() {
+        memory_sizes_in_pages[*memory_id as usize] += _LEGACY_BUCKET_SIZE_IN_PAGES as u64;
+    }
+
+    let mut header = Vec::with_capacity(_LEGACY_HEADER_SIZE as usize);
+
+    header.extend_from_slice(b"MGR");
+    header.push(_LEGACY_LAYOUT_VERSION);
+    header.extend_from_slice(&(allocated_buckets as u16).to_le_bytes());
+    header.extend_from_slice(&_LEGACY_BUCKET_SIZE_IN_PAGES.to_le_bytes());
+    header.extend_from_slice(&[0u8; 32]);
+
+    for memory_size_in_pages in memory_sizes_in_pages {
+        header.extend_from_slice(&memory_size_in_pages.to_le_bytes());
+    }
+
+    memory.write(0, &header);
+}
+
+/// Migrate Limit Orders Record
+///
+/// Moves the legacy limit orders record into the current limit orders record
+///
+/// Params
+///  - Limit Orders Record :The legacy record (see Read Legacy Limit Orders Record)
+///
+/// Note
+///  - Each account is recorded with the id of its migrated limit position at that tick ,accounts without one or already queued for execution are skipped
+pub(crate) fn _migrate_limit_orders_record(limit_orders_record: LimitOrdersRecordV0) {
+    for (tick, accounts) in limit_orders_record.iter() {
+        for account in accounts.iter() {
+            let position_id = ACCOUNTS_POSITION.with_borrow(|reference| {
+                reference
+                    .range((*account, PositionId::MIN)..=(*account, PositionId::MAX))
+                    .find(|(_, position)| match position.order_type {
+                        PositionOrderType::Limit(order) => order.ref_tick == *tick,
+                        PositionOrderType::Market => false,
+                    })
+                    .map(|((_, position_id), _)| position_id)
+            });
+
+            let position_id = match position_id {
+                Some(position_id) => position_id,
+                None => continue,
+            };
+
+            // already queued for execution from the legacy executable orders
+            let queued = CROSSED_TICKS_QUEUE
+                .with_borrow(|reference| reference.contains_key(&(*tick, *account, position_id)));
+
+            if !queued {
+                store_tick_order(*tick, (*account, position_id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_test {
+
+    use super::*;
+    use ic_stable_structures::VectorMemory;
+
+    /// Writes a limit orders record over the header like the pre upgrade hook of the previous version
+    fn _save_legacy_record(memory: &VectorMemory, limit_orders_record: &LimitOrdersRecordV0) {
+        memory.write(0, &Encode!(limit_orders_record).unwrap());
+    }
+
+    #[test]
+    fn test_previous_memories_restored_after_legacy_record() {
+        let memory = VectorMemory::default();
+
+        let memory_manager = MemoryManager::init(memory.clone());
+
+        let mut positions = StableBTreeMap::<Subaccount, u64, _>::init(
+            memory_manager.get(_LEGACY_ACCOUNTS_POSITION_MEMORY),
+        );
+        let mut state =
+            StableBTreeMap::<u64, u64, _>::init(memory_manager.get(_STATE_DETAILS_MEMORY));
+
+        for index in 0..200u64 {
+            positions.insert([index as u8; 32], index);
+        }
+
+        for index in 0..1000u64 {
+            state.insert(index, index);
+        }
+
+        let limit_orders_record: LimitOrdersRecordV0 =
+            HashMap::from([(100000 * 200, vec![[1u8; 32], [2u8; 32]])]);
+
+        _save_legacy_record(&memory, &limit_orders_record);
+
+        // the memory manager header is lost
+        let mut magic = [0u8; 3];
+        memory.read(0, &mut magic);
+
+        assert_ne!(&magic, b"MGR");
+
+        let read_record = _read_legacy_limit_orders_record(&memory).unwrap();
+
+        assert_eq!(read_record, limit_orders_record);
+
+        _restore_legacy_memory_manager_header(&memory, &read_record);
+
+        let memory_manager = MemoryManager::init(memory.clone());
+
+        let positions = StableBTreeMap::<Subaccount, u64, _>::init(
+            memory_manager.get(_LEGACY_ACCOUNTS_POSITION_MEMORY),
+        );
+        let state = StableBTreeMap::<u64, u64, _>::init(memory_manager.get(_STATE_DETAILS_MEMORY));
+
+        assert_eq!(positions.len(), 200);
+        assert_eq!(positions.get(&[7u8; 32]), Some(7));
+        assert_eq!(state.len(), 1000);
+
+        // new memories are allocated after the previous buckets
+        let mut new_memory =
+            StableBTreeMap::<u64, u64, _>::init(memory_manager.get(_ACCOUNTS_POSITION_MEMORY));
+
+        for index in 0..1000u64 {
+            new_memory.insert(index, index);
+        }
+
+        assert_eq!(state.get(&999), Some(999));
+        assert_eq!(new_memory.get(&999), Some(999));
+    }
+
+    #[test]
+    fn test_no_legacy_record_on_current_layout() {
+        let memory = VectorMemory::default();
+
+        assert!(_read_legacy_limit_orders_record(&memory).is_none());
+
+        let _ = MemoryManager::init(memory.clone());
+
+        assert!(_read_legacy_limit_orders_record(&memory).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "overwritten the stable memory layout")]
+    fn test_legacy_record_over_bucket_allocations() {
+        let memory = VectorMemory::default();
+
+        let _ = MemoryManager::init(memory.clone());
+
+        let limit_orders_record: LimitOrdersRecordV0 = (0..100u64)
+            .map(|index| (index, vec![[index as u8; 32]]))
+            .collect();
+
+        _save_legacy_record(&memory, &limit_orders_record);
+
+        let read_record = _read_legacy_limit_orders_record(&memory).unwrap();
+
+        _restore_legacy_memory_manager_header(&memory, &read_record);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::corelib::calc_lib::{_calc_shares, _calc_shares_value, _percentage128};
+use crate::corelib::calc_lib::{_calc_shares, _calc_shares_value};
+use crate::corelib::constants::{_ONE_BASIS_POINT, _ONE_PERCENT};
 use candid::{CandidType, Decode, Encode, Principal};
 use ic_stable_structures::{storable::Bound, Storable};
 
@@ -130,6 +131,98 @@ pub struct OtherError {
 /// Short-hand for returning the result of a `get_exchange_rate` request.
 pub type GetExchangeRateResult = Result<ExchangeRate, ExchangeRateError>;
 
+/// Oracle Error
+///
+/// Returned when the spot price could not be fetched from the exchange rate canister
+#[derive(CandidType, Clone, Debug, Deserialize)]
+pub enum OracleError {
+    /// the inter canister call failed ,contains the rejection code and message
+    CallFailed(String),
+    /// the exchange rate canister returned an error
+    ExchangeRate(ExchangeRateError),
+    /// no price has been pushed to the price feed
+    NoPrice,
+}
+
+/// Oracle Kind
+///
+/// The source of the spot price utilised for settling the funding rate (see Price Oracle)
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Default, PartialEq)]
+pub enum OracleKind {
+    /// the exchange rate canister at the xrc id
+    #[default]
+    XRC,
+    /// the last price pushed to the price feed by the admin or the price keeper
+    Pushed,
+    /// the last price pushed to the price feed timestamped at the time it is read ,for local testing
+    Mock,
+}
+
+/// Spot Price
+///
+/// A spot price returned by a price oracle
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Default)]
+pub struct SpotPrice {
+    /// the spot price scaled by 10^decimals
+    pub price: u128,
+    pub decimals: u32,
+    /// the timestamp (in nanoseconds) of the spot price
+    pub timestamp: Time,
+}
+
+/// Price Feed
+///
+/// The price pushed by the admin or the price keeper ,read by the pushed and mock oracles
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Default)]
+pub struct PriceFeed {
+    /// the principal allowed to push prices besides the admin
+    pub keeper: Option<Principal>,
+    /// the last pushed price ,the timestamp is zero if no price has been pushed
+    pub last_price: SpotPrice,
+}
+
+impl Storable for PriceFeed {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 200,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Oracle Details
+///
+/// The last good spot price fetched from the exchange rate canister and the failures since
+#[derive(CandidType, Clone, Debug, Deserialize, Default)]
+pub struct OracleDetails {
+    /// the last good spot price scaled by 10^decimals
+    pub last_price: u128,
+    pub decimals: u32,
+    /// the timestamp (in nanoseconds) of the last good spot price ,zero if no price has been fetched
+    pub last_updated: Time,
+    /// the number of failed fetches since the last good spot price
+    pub consecutive_failures: u32,
+    /// the error of the last failed fetch
+    pub last_error: Option<OracleError>,
+}
+
+impl Storable for OracleDetails {
+    const BOUND: Bound = Bound::Unbounded;
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
 // user opens position
 //the mount_in
 //debt_value in collateral
@@ -140,6 +233,20 @@ pub struct FundingRateTracker {
     pub total_long_shares: Amount,
     pub net_volume_short: Amount,
     pub total_short_shares: Amount,
+    /// Funding Rate
+    ///
+    /// the current hourly funding rate in percentage ,positive if longs pay shorts and negative otherwise
+    pub funding_rate: i64,
+    /// Long Funding Index
+    ///
+    /// the cumulative funding paid per unit of long volume ,in percentage multiplied by seconds (see Accrue Funding)
+    pub long_funding_index: i128,
+    /// Short Funding Index
+    ///
+    /// similar to long funding index but for short volume
+    pub short_funding_index: i128,
+    /// the time funding was last accrued ,zero if funding has not been accrued
+    pub last_accrued: Time,
 }
 
 impl FundingRateTracker {
@@ -171,26 +278,95 @@ impl FundingRateTracker {
         }
     }
 
-    pub fn settle_funding_rate(&mut self, funding_rate: u64, positive: bool) {
-        if positive {
-            let amount_to_settle = _percentage128(funding_rate, self.net_volume_long);
-            self.net_volume_short += amount_to_settle;
-            self.net_volume_long -= amount_to_settle;
+    /// Volume Value
+    ///
+    /// Gets the current value of a volume share without removing it
+    pub fn volume_value(&self, volume_share: Amount, long: bool) -> Amount {
+        if long {
+            _calc_shares_value(volume_share, self.total_long_shares, self.net_volume_long)
         } else {
-            let amount_to_settle = _percentage128(funding_rate, self.net_volume_short);
-            self.net_volume_long += amount_to_settle;
-            self.net_volume_short -= amount_to_settle
+            _calc_shares_value(volume_share, self.total_short_shares, self.net_volume_short)
+        }
+    }
+
+    /// Accrue Funding
+    ///
+    /// Accrues the current funding rate into the funding indexes for the time elapsed since the last accrual
+    ///
+    /// Note
+    ///  - The paying side index grows by the funding rate multiplied by the seconds elapsed
+    ///  - The receiving side index falls by the same amount scaled by the ratio of the paying volume to the receiving volume ,so the total funding received equals the total funding owed by the paying side (see Apply Funding for unpaid funding)
+    ///  - Funding is accrued for whole seconds ,so the last accrued time only moves by the seconds accrued and the remainder is accrued later
+    pub fn accrue_funding(&mut self, now: Time) {
+        if self.last_accrued == 0 {
+            self.last_accrued = now;
+            return;
         }
+
+        let (long_funding_index, short_funding_index) = self.funding_indexes(now);
+
+        self.long_funding_index = long_funding_index;
+        self.short_funding_index = short_funding_index;
+        self.last_accrued += Self::elapsed_seconds(self.last_accrued, now) * 1_000_000_000;
+    }
+
+    /// Elapsed Seconds
+    ///
+    /// Gets the whole seconds elapsed from the last accrual to now
+    fn elapsed_seconds(last_accrued: Time, now: Time) -> u64 {
+        now.saturating_sub(last_accrued) / 1_000_000_000
+    }
+
+    /// Funding Indexes
+    ///
+    /// Gets the long and short funding indexes accrued up to now without updating the tracker
+    pub fn funding_indexes(&self, now: Time) -> (i128, i128) {
+        if self.last_accrued == 0 || now <= self.last_accrued {
+            return (self.long_funding_index, self.short_funding_index);
+        }
+
+        let elapsed = i128::from(Self::elapsed_seconds(self.last_accrued, now));
+
+        let accrued = i128::from(self.funding_rate) * elapsed;
+
+        let (payer_volume, receiver_volume) = if accrued > 0 {
+            (self.net_volume_long as i128, self.net_volume_short as i128)
+        } else {
+            (self.net_volume_short as i128, self.net_volume_long as i128)
+        };
+
+        let received = if receiver_volume == 0 {
+            0
+        } else {
+            (accrued.abs() * payer_volume) / receiver_volume
+        };
+
+        if accrued > 0 {
+            return (
+                self.long_funding_index + accrued,
+                self.short_funding_index - received,
+            );
+        }
+        (
+            self.long_funding_index - received,
+            self.short_funding_index + accrued.abs(),
+        )
     }
 }
 
 impl Storable for FundingRateTracker {
     const BOUND: Bound = Bound::Bounded {
-        max_size: 65,
-        is_fixed_size: true,
+        max_size: 200,
+        is_fixed_size: false,
     };
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        match Decode!(bytes.as_ref(), Self) {
+            Ok(funding_rate_tracker) => funding_rate_tracker,
+            // stored before funding was accrued
+            Err(_) => Decode!(bytes.as_ref(), FundingRateTrackerV0)
+                .unwrap()
+                .into(),
+        }
     }
 
     fn to_bytes(&self) -> Cow<[u8]> {
@@ -205,7 +381,228 @@ impl Default for FundingRateTracker {
             total_long_shares: 0,
             net_volume_short: 0,
             total_short_shares: 0,
+            funding_rate: 0,
+            long_funding_index: 0,
+            short_funding_index: 0,
+            last_accrued: 0,
+        }
+    }
+}
+
+/// Funding Rate Tracker (Version 0)
+///
+/// The funding rate tracker stored before funding was accrued through the funding indexes
+#[derive(CandidType, Deserialize)]
+struct FundingRateTrackerV0 {
+    net_volume_long: Amount,
+    total_long_shares: Amount,
+    net_volume_short: Amount,
+    total_short_shares: Amount,
+}
+
+impl From<FundingRateTrackerV0> for FundingRateTracker {
+    fn from(funding_rate_tracker: FundingRateTrackerV0) -> Self {
+        FundingRateTracker {
+            net_volume_long: funding_rate_tracker.net_volume_long,
+            total_long_shares: funding_rate_tracker.total_long_shares,
+            net_volume_short: funding_rate_tracker.net_volume_short,
+            total_short_shares: funding_rate_tracker.total_short_shares,
+            // funding starts accruing from the first accrual after the upgrade
+            funding_rate: 0,
+            long_funding_index: 0,
+            short_funding_index: 0,
+            last_accrued: 0,
+        }
+    }
+}
+
+/// Funding Config
+///
+/// The parameters for calculating the funding rate from the premium (see funding_lib) and guarding against a stale spot price
+#[derive(CandidType, Clone, Deserialize, Copy, Debug, PartialEq, Eq)]
+pub struct FundingConfig {
+    /// Max Funding Rate
+    ///
+    /// the max absolute funding rate for an interval in percentage
+    pub max_funding_rate: u64,
+    /// Dead Band
+    ///
+    /// the absolute premium in percentage below which the premium is ignored
+    pub dead_band: u64,
+    /// Interest Rate
+    ///
+    /// the baseline funding rate for an interval in percentage ,positive if longs pay shorts
+    pub interest_rate: i64,
+    /// Max Oracle Staleness
+    ///
+    /// the max age (in seconds) of the spot price after which funding is paused
+    pub max_oracle_staleness: u64,
+    /// Pause Leverage On Stale Oracle
+    ///
+    /// if true positions with debt can not be opened or increased while the spot price is stale
+    pub pause_leverage_on_stale_oracle: bool,
+}
+
+impl Default for FundingConfig {
+    fn default() -> Self {
+        FundingConfig {
+            max_funding_rate: _ONE_PERCENT,
+            dead_band: 5 * _ONE_BASIS_POINT,
+            interest_rate: 0,
+            max_oracle_staleness: 2 * 3600,
+            pause_leverage_on_stale_oracle: false,
+        }
+    }
+}
+
+impl Storable for FundingConfig {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 150,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Mark Price
+///
+/// The mark tick utilised for valuing positions and the ticks it is derived from (see Mark Tick in tick_lib)
+#[derive(CandidType, Clone, Copy, Debug, Deserialize, Default)]
+pub struct MarkPrice {
+    pub mark_tick: Tick,
+    /// the tick of the last good oracle spot price ,none if the spot price is stale
+    pub spot_tick: Option<Tick>,
+    /// the time weighted average tick since the last funding rate settlement
+    pub twap_tick: Tick,
+    /// the tick midway between the best bid and the best ask ,the current tick if either side is empty
+    pub mid_tick: Tick,
+    /// the timestamp (in nanoseconds) the mark tick was last updated ,zero if never updated
+    pub last_updated: Time,
+}
+
+impl Storable for MarkPrice {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 120,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Funding Record
+///
+/// The funding rate set at a funding rate settlement
+#[derive(CandidType, Clone, Deserialize, Copy, Debug)]
+pub struct FundingRecord {
+    /// the hourly funding rate in percentage ,positive if longs pay shorts
+    pub funding_rate: i64,
+    /// the time weighted average perp price over the funding window in the exchange rate decimals
+    pub perp_price: u128,
+    /// the spot price from the exchange rate canister
+    pub spot_price: u128,
+}
+
+impl Storable for FundingRecord {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 100,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+/// Price Accumulator
+///
+/// Accumulates the current tick over time to derive the time weighted average tick of the perp price
+#[derive(CandidType, Clone, Deserialize, Copy, Default)]
+pub struct PriceAccumulator {
+    /// Tick Cumulative
+    ///
+    /// the sum of each current tick multiplied by the time (in nanoseconds) it was the current tick
+    pub tick_cumulative: u128,
+    /// the current tick since the last update
+    pub last_tick: Tick,
+    /// the time of the last update ,zero if the accumulator has not been initialised
+    pub last_updated: Time,
+    /// the tick cumulative at the start of the current window
+    pub window_start_cumulative: u128,
+    /// the start time of the current window
+    pub window_start: Time,
+}
+
+impl PriceAccumulator {
+    /// Update
+    ///
+    /// Accumulates the last tick up to now and sets the new current tick
+    pub fn update(&mut self, current_tick: Tick, now: Time) {
+        if self.last_updated == 0 {
+            self.window_start = now;
+        } else {
+            self.tick_cumulative +=
+                u128::from(self.last_tick) * u128::from(now - self.last_updated);
         }
+
+        self.last_tick = current_tick;
+        self.last_updated = now;
+    }
+
+    /// Average Tick
+    ///
+    /// Gets the time weighted average tick since the start of the current window without starting a new window
+    pub fn average_tick(&self, now: Time) -> Tick {
+        let mut price_accumulator = *self;
+        price_accumulator.settle_window(now)
+    }
+
+    /// Settle Window
+    ///
+    /// Gets the time weighted average tick since the start of the current window and starts a new window
+    ///
+    /// Note:The last tick is returned if no time has elapsed since the start of the window
+    pub fn settle_window(&mut self, now: Time) -> Tick {
+        self.update(self.last_tick, now);
+
+        let elapsed = now - self.window_start;
+
+        let average_tick = if elapsed == 0 {
+            self.last_tick
+        } else {
+            ((self.tick_cumulative - self.window_start_cumulative) / u128::from(elapsed)) as Tick
+        };
+
+        self.window_start = now;
+        self.window_start_cumulative = self.tick_cumulative;
+
+        average_tick
+    }
+}
+
+impl Storable for PriceAccumulator {
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 120,
+        is_fixed_size: false,
+    };
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
     }
 }
 
@@ -226,6 +623,11 @@ pub struct MarketDetails {
 
     /// token decimal of collateral token
     pub collateral_decimal: u8,
+
+    /// Oracle
+    ///
+    /// The source of the spot price utilised for settling the funding rate
+    pub oracle: OracleKind,
 }
 
 impl Default for MarketDetails {
@@ -236,6 +638,7 @@ impl Default for MarketDetails {
             vault_id: Principal::anonymous(),
             xrc_id: Principal::anonymous(),
             collateral_decimal: 0,
+            oracle: OracleKind::XRC,
         };
     }
 }
@@ -243,7 +646,11 @@ impl Default for MarketDetails {
 impl Storable for MarketDetails {
     const BOUND: Bound = Bound::Unbounded;
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        match Decode!(bytes.as_ref(), Self) {
+            Ok(market_details) => market_details,
+            // stored before the oracle was added
+            Err(_) => Decode!(bytes.as_ref(), MarketDetailsV0).unwrap().into(),
+        }
     }
 
     fn to_bytes(&self) -> Cow<[u8]> {
@@ -251,6 +658,32 @@ impl Storable for MarketDetails {
     }
 }
 
+/// Market Details (Version 0)
+///
+/// The market details stored before the oracle was added
+#[derive(CandidType, Deserialize)]
+struct MarketDetailsV0 {
+    base_asset: Asset,
+    quote_asset: Asset,
+    vault_id: Principal,
+    xrc_id: Principal,
+    collateral_decimal: u8,
+}
+
+impl From<MarketDetailsV0> for MarketDetails {
+    fn from(market_details: MarketDetailsV0) -> Self {
+        MarketDetails {
+            base_asset: market_details.base_asset,
+            quote_asset: market_details.quote_asset,
+            vault_id: market_details.vault_id,
+            xrc_id: market_details.xrc_id,
+            collateral_decimal: market_details.collateral_decimal,
+            // the spot price was fetched from the exchange rate canister
+            oracle: OracleKind::XRC,
+        }
+    }
+}
+
 ///State Details comprises for useful parameters that change more frequently
 /// compared to market details  that is  set on initialization
 
@@ -267,6 +700,10 @@ pub struct StateDetails {
     /// the maximum leverage allowed for any position * 10
     ///
     /// typically leverage is set multiplied by 10 ,so a leverage of 2x would be written as 20  
+    ///
+    /// Note:
+    ///
+    /// - this acts as the initial margin ,a position must be opened with a margin above 1 / max leverage
     pub max_leveragex10: u8,
     /// Minimum Collateral
     ///
@@ -281,15 +718,57 @@ pub struct StateDetails {
     ///
     /// base token multiple for cases of perp_assets with lower value than the underlying collateral asset
     pub base_token_multiple: u8,
+    /// Liquidation Fee
+    ///
+    /// the fee taken from the remaining collateral of a liquidated position as a percentage of the position value
+    ///
+    /// Note:
+    ///
+    /// - this is given in percentage i.e 1% is 100_000
+    pub liquidation_fee: u64,
+    /// Keeper Share
+    ///
+    /// the percentage of the liquidation fee sent to the caller of the liquidation ,the rest is added to the insurance fund
+    pub keeper_share: u64,
+    /// Maintenance Margin
+    ///
+    /// the minimum remaining collateral of a position as a percentage of it's current value ,below which the position is liquidated
+    ///
+    /// Note:
+    ///
+    /// - this must be less than the initial margin (see max leverage) and at least the liquidation fee
+    pub maintenance_margin: u64,
+    /// Taker Fee
+    ///
+    /// the fee charged on the value traded by a market order ,distributed to the vault stakers
+    ///
+    /// Note:
+    ///
+    /// - this is given in percentage i.e 1 basis point is 1_000
+    pub taker_fee: u64,
+    /// Maker Fee
+    ///
+    /// the fee charged on the value of a limit order when filled ,distributed to the vault stakers
+    ///
+    /// Note:
+    ///
+    /// - this is given in percentage i.e 1 basis point is 1_000
+    /// - a negative maker fee is a rebate paid to the limit order owner out of the taker fee ,so it can not exceed the taker fee
+    /// - the fee is recorded on a tick when created ,so changing it does not change the fee of already placed orders
+    pub maker_fee: i64,
 }
 
 impl Storable for StateDetails {
     const BOUND: Bound = Bound::Bounded {
-        max_size: 29,
+        max_size: 150,
         is_fixed_size: false,
     };
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        match Decode!(bytes.as_ref(), Self) {
+            Ok(state_details) => state_details,
+            // stored before the liquidation parameters were added
+            Err(_) => Decode!(bytes.as_ref(), StateDetailsV0).unwrap().into(),
+        }
     }
 
     fn to_bytes(&self) -> Cow<[u8]> {
@@ -297,6 +776,44 @@ impl Storable for StateDetails {
     }
 }
 
+/// State Details (Version 0)
+///
+/// The state details stored before the liquidation parameters were added
+#[derive(CandidType, Deserialize)]
+struct StateDetailsV0 {
+    not_paused: bool,
+    current_tick: Tick,
+    max_leveragex10: u8,
+    min_collateral: Amount,
+    base_token_multiple: u8,
+}
+
+impl From<StateDetailsV0> for StateDetails {
+    fn from(state_details: StateDetailsV0) -> Self {
+        // positions were liquidated once their leverage exceeded the max leverage ,
+        // so the maintenance margin is set just below the initial margin
+        let initial_margin = match state_details.max_leveragex10 {
+            0 => 0,
+            max_leveragex10 => (10 * 100 * _ONE_PERCENT) / u64::from(max_leveragex10),
+        };
+
+        StateDetails {
+            not_paused: state_details.not_paused,
+            current_tick: state_details.current_tick,
+            max_leveragex10: state_details.max_leveragex10,
+            min_collateral: state_details.min_collateral,
+            base_token_multiple: state_details.base_token_multiple,
+            // no liquidation fee is taken until set by the admin
+            liquidation_fee: 0,
+            keeper_share: 0,
+            maintenance_margin: initial_margin.saturating_sub(1),
+            // no trading fee is charged until set by the admin
+            taker_fee: 0,
+            maker_fee: 0,
+        }
+    }
+}
+
 #[derive(CandidType, Default, Deserialize, Clone, Copy)]
 pub struct TickDetails {
     pub liq_bounds_token0: LiquidityBoundary,
@@ -304,14 +821,24 @@ pub struct TickDetails {
     pub liq_bounds_token1: LiquidityBoundary,
 
     pub created_timestamp: Time,
+
+    /// Maker Fee
+    ///
+    /// the maker fee when the tick was created ,charged on the orders filled at the tick
+    ///
+    /// Note:
+    ///
+    /// - recording the fee keeps the rebate paid to the filled orders equal to the rebate held back from the taker fee
+    pub maker_fee: i64,
 }
 
 impl TickDetails {
-    pub fn new() -> Self {
+    pub fn new(maker_fee: i64) -> Self {
         TickDetails {
             liq_bounds_token0: LiquidityBoundary::default(),
             liq_bounds_token1: LiquidityBoundary::default(),
             created_timestamp: ic_cdk::api::time(),
+            maker_fee,
         }
     }
     /// Add_liquidity function
@@ -342,11 +869,15 @@ impl TickDetails {
 
 impl Storable for TickDetails {
     const BOUND: Bound = Bound::Bounded {
-        max_size: 104,
+        max_size: 120,
         is_fixed_size: false,
     };
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        match Decode!(bytes.as_ref(), Self) {
+            Ok(tick_details) => tick_details,
+            // stored before the maker fee was added
+            Err(_) => Decode!(bytes.as_ref(), TickDetailsV0).unwrap().into(),
+        }
     }
 
     fn to_bytes(&self) -> Cow<[u8]> {
@@ -354,6 +885,28 @@ impl Storable for TickDetails {
     }
 }
 
+/// Tick Details (Version 0)
+///
+/// The tick details stored before the maker fee was added
+#[derive(CandidType, Deserialize)]
+struct TickDetailsV0 {
+    liq_bounds_token0: LiquidityBoundary,
+    liq_bounds_token1: LiquidityBoundary,
+    created_timestamp: Time,
+}
+
+impl From<TickDetailsV0> for TickDetails {
+    fn from(tick_details: TickDetailsV0) -> Self {
+        TickDetails {
+            liq_bounds_token0: tick_details.liq_bounds_token0,
+            liq_bounds_token1: tick_details.liq_bounds_token1,
+            created_timestamp: tick_details.created_timestamp,
+            // no maker fee was charged before the fee was added
+            maker_fee: 0,
+        }
+    }
+}
+
 /// Liquidity Boundary tracks the amount of Static Liquidity currently at a tick
 ///
 ///   Static Liquidity refers to liquidity from  limit orders that normal traders make
@@ -417,3 +970,96 @@ impl LiquidityBoundary {
         self.lifetime_removed_liquidity += delta
     }
 }
+
+#[cfg(test)]
+mod unit_test {
+
+    use super::*;
+
+    const ONE_SECOND: Time = 1_000_000_000;
+
+    #[test]
+    fn test_price_accumulator_update() {
+        let mut price_accumulator = PriceAccumulator::default();
+
+        price_accumulator.update(100, ONE_SECOND);
+
+        // the first update only initialises the accumulator
+        assert_eq!(price_accumulator.tick_cumulative, 0);
+        assert_eq!(price_accumulator.window_start, ONE_SECOND);
+
+        // sub second intervals are accumulated
+        price_accumulator.update(200, ONE_SECOND + 500_000_000);
+
+        assert_eq!(price_accumulator.tick_cumulative, 100 * 500_000_000);
+
+        price_accumulator.update(200, ONE_SECOND + 750_000_000);
+
+        assert_eq!(
+            price_accumulator.tick_cumulative,
+            100 * 500_000_000 + 200 * 250_000_000
+        );
+        assert_eq!(price_accumulator.last_tick, 200);
+        assert_eq!(price_accumulator.last_updated, ONE_SECOND + 750_000_000);
+    }
+
+    #[test]
+    fn test_price_accumulator_settle_window() {
+        let mut price_accumulator = PriceAccumulator::default();
+
+        price_accumulator.update(100, ONE_SECOND);
+
+        price_accumulator.update(300, 4 * ONE_SECOND);
+
+        // 100 for 3 seconds and 300 for 1 second
+        assert_eq!(price_accumulator.settle_window(5 * ONE_SECOND), 150);
+
+        assert_eq!(price_accumulator.window_start, 5 * ONE_SECOND);
+
+        // the next window only averages the ticks after the settled window
+        price_accumulator.update(200, 5 * ONE_SECOND + 500_000_000);
+
+        assert_eq!(price_accumulator.settle_window(6 * ONE_SECOND), 250);
+
+        // an empty window returns the last tick
+        assert_eq!(price_accumulator.settle_window(6 * ONE_SECOND), 200);
+    }
+
+    #[test]
+    fn test_price_accumulator_average_tick() {
+        let mut price_accumulator = PriceAccumulator::default();
+
+        price_accumulator.update(100, ONE_SECOND);
+
+        price_accumulator.update(200, ONE_SECOND + 250_000_000);
+
+        assert_eq!(price_accumulator.average_tick(2 * ONE_SECOND), 175);
+
+        // the window is left unchanged
+        assert_eq!(price_accumulator.window_start, ONE_SECOND);
+        assert_eq!(price_accumulator.settle_window(2 * ONE_SECOND), 175);
+    }
+
+    #[test]
+    fn test_funding_accrued_for_sub_second_remainders() {
+        let mut funding_rate_tracker = FundingRateTracker {
+            net_volume_long: 1_000,
+            net_volume_short: 1_000,
+            funding_rate: 10,
+            ..FundingRateTracker::default()
+        };
+
+        funding_rate_tracker.accrue_funding(ONE_SECOND);
+
+        funding_rate_tracker.accrue_funding(2 * ONE_SECOND + 500_000_000);
+
+        // only the whole second is accrued ,the remainder is kept for the next accrual
+        assert_eq!(funding_rate_tracker.long_funding_index, 10);
+        assert_eq!(funding_rate_tracker.last_accrued, 2 * ONE_SECOND);
+
+        funding_rate_tracker.accrue_funding(3 * ONE_SECOND);
+
+        assert_eq!(funding_rate_tracker.long_funding_index, 20);
+        assert_eq!(funding_rate_tracker.short_funding_index, -20);
+    }
+}
@@ -100,7 +100,7 @@ async fn create_position_validity_check(
 ///  - Margin Delta : The amount to add back into user's margin balance  
 ///  - Manage Debt Params :The debt management paramters
 ///
-/// Note : This function also updates the vault staking details distributing the fees gotten into the respective stake spans
+/// Note : This function also updates the vault staking details distributing the fees gotten (interest and trading fees) into the respective stake spans
 
 #[ic_cdk::update(name = "managePositionUpdate", guard = "approved_market_guard")]
 async fn manage_position_update(
@@ -114,20 +114,86 @@ async fn manage_position_update(
 
     let mut vault_details = _get_vault_details();
 
+    _settle_debt(&mut vault_details, manage_debt_params);
+
+    _update_vault_details(vault_details);
+}
+
+/// Manage Liquidation
+///
+/// This function is called when a position is liquidated or closed without repaying it's full debt
+///
+/// Params;
+///  - User :The user whose position was liquidated
+///  - Margin Delta :The collateral remaining after the liquidation fee to add back into user's margin balance
+///  - Liquidation Fees :The liquidation fee split between the keeper and the insurance fund
+///  - Manage Debt Params :The debt management paramters
+///  - Position Closed :If the position was fully closed ,false if it was partially closed and still holds it's remaining debt
+///
+/// Note : If the position was fully closed and the amount repaid is less than the net debt ,the shortfall is covered by the insurance fund and any remaining shortfall is written off from the vault's debt
+
+#[ic_cdk::update(name = "manageLiquidation", guard = "approved_market_guard")]
+async fn manage_liquidation(
+    user: Principal,
+    margin_delta: Amount,
+    liquidation_fees: LiquidationFees,
+    mut manage_debt_params: ManageDebtParams,
+    position_closed: bool,
+) {
+    if margin_delta != 0 {
+        _update_user_margin_balance(user, margin_delta, true);
+    }
+
+    if liquidation_fees.keeper_fee != 0 {
+        _update_user_margin_balance(liquidation_fees.keeper, liquidation_fees.keeper_fee, true);
+    }
+
+    let mut vault_details = _get_vault_details();
+
+    vault_details.insurance_fund += liquidation_fees.insurance_fee;
+
+    if position_closed && manage_debt_params.net_debt > manage_debt_params.amount_repaid {
+        let shortfall = manage_debt_params.net_debt - manage_debt_params.amount_repaid;
+
+        let amount_covered = shortfall.min(vault_details.insurance_fund);
+
+        vault_details.insurance_fund -= amount_covered;
+
+        manage_debt_params.amount_repaid += amount_covered;
+        // the shortfall not covered is removed from the debt as a loss to the vault
+        manage_debt_params.net_debt = manage_debt_params.amount_repaid;
+    }
+
+    _settle_debt(&mut vault_details, manage_debt_params);
+
+    _update_vault_details(vault_details);
+}
+
+/// Get Insurance Fund
+///
+/// Returns the insurance fund balance used for covering debt that can not be repaid by liquidated positions
+#[ic_cdk::query(name = "getInsuranceFund")]
+fn get_insurance_fund() -> Amount {
+    _get_vault_details().insurance_fund
+}
+
+/// Settle Debt
+///
+/// Updates the vault debt and free liquidity with the debt repaid and distributes any fees gotten into the respective stake spans
+fn _settle_debt(vault_details: &mut VaultDetails, manage_debt_params: ManageDebtParams) {
     let ManageDebtParams {
         initial_debt,
         net_debt,
         amount_repaid,
-    } = &manage_debt_params;
+        trading_fee,
+    } = manage_debt_params;
 
     vault_details.debt = vault_details.debt + net_debt - (initial_debt + amount_repaid);
-    vault_details.free_liquidity += amount_repaid;
+    vault_details.free_liquidity += amount_repaid + trading_fee;
 
-    let fees_gotten = if amount_repaid > initial_debt {
-        amount_repaid - initial_debt
-    } else {
-        0
-    };
+    let interest_gotten = amount_repaid.saturating_sub(initial_debt);
+
+    let fees_gotten = interest_gotten + trading_fee;
     if fees_gotten == 0 {
         return;
     }
@@ -159,7 +225,6 @@ async fn manage_position_update(
             .staking_details
             ._create_stake(0, vault_details.lifetime_fees, StakeSpan::Year)
     };
-    _update_vault_details(vault_details);
 }
 
 /// Funds a Traders margin account to make a thread
@@ -462,6 +527,15 @@ struct ManageDebtParams {
     initial_debt: Amount,
     net_debt: Amount,
     amount_repaid: Amount,
+    /// the trading fees paid by the position ,distributed to stakers like interest
+    trading_fee: Amount,
+}
+
+#[derive(Copy, Clone, Deserialize, CandidType)]
+struct LiquidationFees {
+    keeper: Principal,
+    keeper_fee: Amount,
+    insurance_fee: Amount,
 }
 
 trait UniqueSubAccount {
@@ -489,3 +563,119 @@ fn _vault_subaccount() -> Subaccount {
 
 pub mod core_lib;
 pub mod types;
+
+#[cfg(test)]
+mod unit_test {
+
+    use super::*;
+
+    fn _liquidation_fees(keeper_fee: Amount, insurance_fee: Amount) -> LiquidationFees {
+        LiquidationFees {
+            keeper: Principal::from_slice(&[2]),
+            keeper_fee,
+            insurance_fee,
+        }
+    }
+
+    fn _debt_params(
+        initial_debt: Amount,
+        net_debt: Amount,
+        amount_repaid: Amount,
+    ) -> ManageDebtParams {
+        ManageDebtParams {
+            initial_debt,
+            net_debt,
+            amount_repaid,
+            trading_fee: 0,
+        }
+    }
+
+    fn _set_vault_debt(debt: Amount, insurance_fund: Amount) {
+        _update_vault_details(VaultDetails {
+            debt,
+            insurance_fund,
+            ..VaultDetails::default()
+        });
+    }
+
+    #[test]
+    fn test_liquidation_fees_split() {
+        let user = Principal::from_slice(&[1]);
+
+        _set_vault_debt(1000, 0);
+
+        futures::executor::block_on(manage_liquidation(
+            user,
+            500,
+            _liquidation_fees(20, 80),
+            _debt_params(1000, 1000, 1000),
+            true,
+        ));
+
+        assert_eq!(_get_user_balance(user), 500);
+        assert_eq!(_get_user_balance(Principal::from_slice(&[2])), 20);
+        assert_eq!(get_insurance_fund(), 80);
+
+        let vault_details = _get_vault_details();
+
+        assert_eq!(vault_details.debt, 0);
+        assert_eq!(vault_details.free_liquidity, 1000);
+    }
+
+    #[test]
+    fn test_bad_debt_covered_by_insurance_fund() {
+        _set_vault_debt(1000, 100);
+
+        futures::executor::block_on(manage_liquidation(
+            Principal::from_slice(&[1]),
+            0,
+            _liquidation_fees(0, 0),
+            _debt_params(1000, 1000, 950),
+            true,
+        ));
+
+        let vault_details = _get_vault_details();
+
+        assert_eq!(get_insurance_fund(), 50);
+        assert_eq!(vault_details.debt, 0);
+        assert_eq!(vault_details.free_liquidity, 1000);
+    }
+
+    #[test]
+    fn test_bad_debt_exceeding_insurance_fund_written_off() {
+        _set_vault_debt(1000, 10);
+
+        futures::executor::block_on(manage_liquidation(
+            Principal::from_slice(&[1]),
+            0,
+            _liquidation_fees(0, 0),
+            _debt_params(1000, 1000, 900),
+            true,
+        ));
+
+        let vault_details = _get_vault_details();
+
+        assert_eq!(get_insurance_fund(), 0);
+        assert_eq!(vault_details.debt, 0);
+        assert_eq!(vault_details.free_liquidity, 910);
+    }
+
+    #[test]
+    fn test_partial_liquidation_keeps_remaining_debt() {
+        _set_vault_debt(1000, 100);
+
+        futures::executor::block_on(manage_liquidation(
+            Principal::from_slice(&[1]),
+            0,
+            _liquidation_fees(0, 0),
+            _debt_params(1000, 1000, 900),
+            false,
+        ));
+
+        let vault_details = _get_vault_details();
+
+        assert_eq!(get_insurance_fund(), 100);
+        assert_eq!(vault_details.debt, 100);
+        assert_eq!(vault_details.free_liquidity, 900);
+    }
+}
@@ -21,6 +21,10 @@ pub struct VaultDetails {
     pub free_liquidity: Amount,
     pub lifetime_fees: Amount,
     pub staking_details: VaultStakingDetails,
+    /// Insurance Fund
+    ///
+    /// liquidation fees kept for covering debt that can not be repaid by liquidated positions
+    pub insurance_fund: Amount,
 }
 
 impl Default for VaultDetails {
@@ -34,6 +38,7 @@ impl Default for VaultDetails {
             free_liquidity: 0,
             lifetime_fees: 0,
             staking_details: VaultStakingDetails::default(),
+            insurance_fund: 0,
         }
     }
 }
@@ -41,10 +46,45 @@ impl Default for VaultDetails {
 impl Storable for VaultDetails {
     const BOUND: Bound = Bound::Unbounded;
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        match Decode!(bytes.as_ref(), Self) {
+            Ok(vault_details) => vault_details,
+            // stored before the insurance fund was added
+            Err(_) => Decode!(bytes.as_ref(), VaultDetailsV0).unwrap().into(),
+        }
     }
 
     fn to_bytes(&self) -> Cow<[u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
 }
+
+/// Vault Details (Version 0)
+///
+/// The vault details stored before the insurance fund was added
+#[derive(CandidType, Deserialize)]
+struct VaultDetailsV0 {
+    asset: Asset,
+    virtaul_asset: Asset,
+    tx_fee: Amount,
+    min_amount: Amount,
+    debt: Amount,
+    free_liquidity: Amount,
+    lifetime_fees: Amount,
+    staking_details: VaultStakingDetails,
+}
+
+impl From<VaultDetailsV0> for VaultDetails {
+    fn from(vault_details: VaultDetailsV0) -> Self {
+        VaultDetails {
+            asset: vault_details.asset,
+            virtaul_asset: vault_details.virtaul_asset,
+            tx_fee: vault_details.tx_fee,
+            min_amount: vault_details.min_amount,
+            debt: vault_details.debt,
+            free_liquidity: vault_details.free_liquidity,
+            lifetime_fees: vault_details.lifetime_fees,
+            staking_details: vault_details.staking_details,
+            insurance_fund: 0,
+        }
+    }
+}